@@ -0,0 +1,79 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use globset::GlobSet;
+
+use crate::filter;
+
+/// Known-good paths, as globs like `**/bin/nmap`, whose files are scanned
+/// but whose matches are not reported.
+#[derive(Debug)]
+pub struct PathAllowlist {
+    globs: GlobSet,
+}
+
+impl PathAllowlist {
+    /// Builds the allowlist from `patterns`, matching regardless of case if
+    /// `case_insensitive`.
+    pub fn new<'a>(
+        patterns: impl IntoIterator<Item = &'a str>,
+        case_insensitive: bool,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            globs: filter::glob_set(patterns, case_insensitive)?,
+        })
+    }
+
+    /// Reads the allowlist at `path`, one glob per line. Empty lines and
+    /// lines starting with `#` are skipped.
+    pub fn read(path: &Path, case_insensitive: bool) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("can not read `{}`", path.display()))?;
+        Self::new(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#')),
+            case_insensitive,
+        )
+        .with_context(|| format!("invalid allowlist `{}`", path.display()))
+    }
+
+    /// Returns whether `path`, an absolute path, is allowlisted.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.globs.is_match(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        let allowlist = PathAllowlist::new(["**/bin/nmap", "/opt/tools/**"], false).unwrap();
+        assert!(allowlist.contains(Path::new("/mnt/image/usr/bin/nmap")));
+        assert!(allowlist.contains(Path::new("/opt/tools/sub/scanner")));
+        assert!(!allowlist.contains(Path::new("/usr/bin/nmap.bak")));
+        assert!(!allowlist.contains(Path::new("/usr/bin/NMAP")));
+        // `*` doesn't cross folders.
+        let allowlist = PathAllowlist::new(["/opt/*"], true).unwrap();
+        assert!(allowlist.contains(Path::new("/OPT/scanner")));
+        assert!(!allowlist.contains(Path::new("/opt/tools/scanner")));
+    }
+
+    #[test]
+    fn test_read() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("allowlist.txt");
+        fs::write(&path, "# Admin tools\n\n  **/bin/nmap  \n")?;
+        let allowlist = PathAllowlist::read(&path, false)?;
+        assert!(allowlist.contains(Path::new("/usr/bin/nmap")));
+        assert!(!allowlist.contains(Path::new("# Admin tools")));
+
+        fs::write(&path, "/opt/[tools\n")?;
+        assert!(PathAllowlist::read(&path, false).is_err());
+        assert!(PathAllowlist::read(&dir.path().join("missing"), false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+
+/// File names longer than this many bytes are anomalous. Most file systems
+/// allow 255.
+const MAX_NAME_LEN: usize = 200;
+
+/// Unicode characters that change the direction of the text, used to make
+/// `exe.pdf` display as `fdp.exe`.
+const BIDI_OVERRIDES: [char; 9] = [
+    '\u{202a}', '\u{202b}', '\u{202c}', '\u{202d}', '\u{202e}', '\u{2066}', '\u{2067}', '\u{2068}',
+    '\u{2069}',
+];
+
+/// Extensions of files that are commonly opened without a second thought.
+const DOCUMENT_EXTENSIONS: [&str; 12] = [
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "txt", "rtf", "jpg", "png", "gif",
+];
+
+/// Extensions of files that run code when opened.
+const EXECUTABLE_EXTENSIONS: [&str; 11] = [
+    "exe", "scr", "com", "pif", "bat", "cmd", "js", "vbs", "ps1", "hta", "lnk",
+];
+
+/// A suspicious trait of a file name, used to masquerade files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilenameAnomaly {
+    /// The name is longer than [`MAX_NAME_LEN`], which may hide its end.
+    LongName,
+    /// The name contains a Unicode bidirectional override character.
+    BidiOverride,
+    /// An executable extension follows a document one, like `invoice.pdf.exe`.
+    DoubleExtension,
+}
+
+impl FilenameAnomaly {
+    /// Describes the anomaly, for reporting.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::LongName => "overlong file name",
+            Self::BidiOverride => "bidirectional override character in file name",
+            Self::DoubleExtension => "executable extension after a document extension",
+        }
+    }
+}
+
+/// Returns the anomalies of the name of the file at `path`.
+pub fn filename_anomalies(path: &Path) -> Vec<FilenameAnomaly> {
+    let Some(name) = path.file_name() else {
+        return Vec::new();
+    };
+    let mut anomalies = Vec::new();
+    if name.len() > MAX_NAME_LEN {
+        anomalies.push(FilenameAnomaly::LongName);
+    }
+    let name = name.to_string_lossy().to_lowercase();
+    if name.contains(BIDI_OVERRIDES) {
+        anomalies.push(FilenameAnomaly::BidiOverride);
+    }
+    let mut extensions = name.rsplit('.');
+    if let (Some(last), Some(previous), Some(_)) =
+        (extensions.next(), extensions.next(), extensions.next())
+    {
+        if EXECUTABLE_EXTENSIONS.contains(&last) && DOCUMENT_EXTENSIONS.contains(&previous) {
+            anomalies.push(FilenameAnomaly::DoubleExtension);
+        }
+    }
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filename_anomalies() {
+        let anomalies =
+            |name: &str| filename_anomalies(Path::new("/evidence").join(name).as_path());
+
+        assert_eq!(anomalies("report.pdf"), []);
+        assert_eq!(anomalies("archive.tar.gz"), []);
+        assert_eq!(anomalies(".profile.exe"), []);
+        assert_eq!(
+            anomalies("invoice\u{202e}fdp.exe"),
+            [FilenameAnomaly::BidiOverride]
+        );
+        assert_eq!(
+            anomalies("Invoice.PDF.exe"),
+            [FilenameAnomaly::DoubleExtension]
+        );
+        assert_eq!(
+            anomalies(&format!("{}.txt", "a".repeat(MAX_NAME_LEN))),
+            [FilenameAnomaly::LongName]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fs, io, ops::Range, path::Path};
+
+use crate::targets::Target;
+
+/// The blocks of an image that changed since a previous scan, one bit per
+/// block, least significant bit first, like ext4 block bitmaps. Blocks
+/// past the end of the bitmap are unchanged.
+pub struct BlockBitmap {
+    bits: Vec<u8>,
+    block_size: u64,
+}
+
+impl BlockBitmap {
+    pub fn new(bits: Vec<u8>, block_size: u64) -> Self {
+        assert!(block_size > 0, "the block size can't be 0");
+        Self { bits, block_size }
+    }
+
+    /// Reads the bitmap stored in the file at `path`.
+    pub fn read(path: &Path, block_size: u64) -> io::Result<Self> {
+        Ok(Self::new(fs::read(path)?, block_size))
+    }
+
+    /// Returns true if the block with index `block` changed.
+    pub fn is_changed(&self, block: u64) -> bool {
+        let Some(byte) = usize::try_from(block / 8)
+            .ok()
+            .and_then(|index| self.bits.get(index))
+        else {
+            return false;
+        };
+        byte & (1 << (block % 8)) != 0
+    }
+
+    /// Returns the parts of the byte range `range` that lie in changed
+    /// blocks, with adjacent changed blocks merged.
+    pub fn changed_ranges(&self, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        if range.is_empty() {
+            return ranges;
+        }
+        let first = range.start / self.block_size;
+        let last = (range.end - 1) / self.block_size;
+        for block in (first..=last).filter(|block| self.is_changed(*block)) {
+            let start = (block * self.block_size).max(range.start);
+            let end = ((block + 1) * self.block_size).min(range.end);
+            match ranges.last_mut() {
+                Some(previous) if previous.end == start => previous.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
+    /// Narrows `targets` down to their parts in changed blocks. Targets
+    /// without any changed block are left out.
+    pub fn intersect(&self, targets: &[Target]) -> Vec<Target> {
+        targets
+            .iter()
+            .flat_map(|target| {
+                self.changed_ranges(target.offset..target.offset + target.len)
+                    .into_iter()
+                    .map(|range| Target {
+                        path: target.path.clone(),
+                        offset: range.start,
+                        len: range.end - range.start,
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_ranges() {
+        // Blocks 1, 2 and 4 changed.
+        let bitmap = BlockBitmap::new(vec![0b0001_0110], 512);
+
+        assert_eq!(bitmap.changed_ranges(0..4096), [512..1536, 2048..2560]);
+        assert_eq!(bitmap.changed_ranges(1000..2100), [1000..1536, 2048..2100]);
+        assert_eq!(bitmap.changed_ranges(0..512), []);
+        // Past the end of the bitmap.
+        assert_eq!(bitmap.changed_ranges(8192..10000), []);
+    }
+
+    #[test]
+    fn test_unchanged_regions_not_scanned() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let image = dir.path().join("disk.img");
+        let mut data = vec![0; 4096];
+        data[100..104].copy_from_slice(b"EVIL");
+        data[2100..2104].copy_from_slice(b"EVIL");
+        fs::write(&image, &data)?;
+
+        // Only block 2 changed.
+        let bitmap = BlockBitmap::new(vec![0b0000_0100], 1024);
+        let targets = bitmap.intersect(&[Target {
+            path: image.clone(),
+            offset: 0,
+            len: 4096,
+        }]);
+        assert_eq!(
+            targets,
+            [Target {
+                path: image,
+                offset: 2048,
+                len: 1024,
+            }]
+        );
+
+        let rules = yara_x::compile(r#"rule Evil { strings: $a = "EVIL" condition: $a }"#)?;
+        let mut scanner = yara_x::Scanner::new(&rules);
+        let offsets: Vec<_> = targets
+            .iter()
+            .map(|target| -> anyhow::Result<Vec<u64>> {
+                let data = target.read()?;
+                let results = scanner.scan(&data)?;
+                Ok(results
+                    .matching_rules()
+                    .flat_map(|rule| rule.patterns().flat_map(|p| p.matches()))
+                    .map(|m| target.offset + m.range().start as u64)
+                    .collect())
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+        assert_eq!(offsets, [2100]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The contents of a file read into memory for scanning.
+pub struct FileBuffer {
+    /// Bytes that could actually be read.
+    pub data: Vec<u8>,
+    /// Size reported by the file's metadata before reading.
+    pub expected_len: u64,
+}
+
+impl FileBuffer {
+    /// Returns true when fewer bytes were read than the reported size, which
+    /// usually means the file was carved or is otherwise incomplete.
+    pub fn is_truncated(&self) -> bool {
+        (self.data.len() as u64) < self.expected_len
+    }
+}
+
+/// Reads up to `expected_len` bytes from `reader`.
+pub fn read_buffered<R: Read>(reader: R, expected_len: u64) -> io::Result<FileBuffer> {
+    let mut data = Vec::with_capacity(expected_len as usize);
+    reader.take(expected_len).read_to_end(&mut data)?;
+
+    Ok(FileBuffer { data, expected_len })
+}
+
+/// Reads the file at `path`, which is expected to be `expected_len` bytes
+/// long according to its metadata.
+pub fn read_file(path: &Path, expected_len: u64) -> io::Result<FileBuffer> {
+    read_buffered(File::open(path)?, expected_len)
+}
+
+/// A soft limit on the number of bytes held in file buffers at once, shared
+/// by all the scanning threads.
+pub struct MemoryBudget {
+    limit: u64,
+    used: AtomicU64,
+}
+
+/// Bytes reserved from a [`MemoryBudget`], returned to it when dropped.
+pub struct MemoryReservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    /// Creates a budget allowing up to `limit` bytes to be buffered.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Reserves `bytes` from the budget, or returns `None` if that would
+    /// exceed the limit. Callers should then avoid buffering the file.
+    pub fn try_reserve(&self, bytes: u64) -> Option<MemoryReservation<'_>> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|total| *total <= self.limit)
+            })
+            .ok()
+            .map(|_| MemoryReservation {
+                budget: self,
+                bytes,
+            })
+    }
+
+    /// Bytes still available in the budget.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used.load(Ordering::SeqCst))
+    }
+}
+
+impl Drop for MemoryReservation<'_> {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_read_buffered_complete() -> io::Result<()> {
+        let buffer = read_buffered(Cursor::new(b"complete"), 8)?;
+
+        assert_eq!(buffer.data, b"complete");
+        assert!(!buffer.is_truncated());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_buffered_short_read() -> io::Result<()> {
+        // The metadata claims 4096 bytes but only 5 can be read.
+        let buffer = read_buffered(Cursor::new(b"short"), 4096)?;
+
+        assert_eq!(buffer.data.len(), 5);
+        assert!(buffer.is_truncated());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_buffered_ignores_growth() -> io::Result<()> {
+        let buffer = read_buffered(Cursor::new(b"grown file"), 5)?;
+
+        assert_eq!(buffer.data, b"grown");
+        assert!(!buffer.is_truncated());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_budget_refuses_large_files() {
+        let budget = MemoryBudget::new(1024);
+
+        assert!(budget.try_reserve(4096).is_none());
+
+        let first = budget.try_reserve(600).unwrap();
+        assert_eq!(budget.remaining(), 424);
+        assert!(budget.try_reserve(600).is_none());
+
+        drop(first);
+        assert_eq!(budget.remaining(), 1024);
+        assert!(budget.try_reserve(600).is_some());
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Pauses the walker threads while a control file exists, so that operators
+/// can throttle a long scan without killing it.
+pub struct ControlFile {
+    path: PathBuf,
+    poll_interval: Duration,
+}
+
+impl ControlFile {
+    /// Creates a control pausing while `path` exists, checked every second.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets how often the control file is checked while paused.
+    pub fn poll_interval(&mut self, interval: Duration) -> &mut Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Returns true if the scan is paused.
+    pub fn is_paused(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Blocks while the scan is paused. Returns true if it was.
+    pub fn wait_while_paused(&self) -> bool {
+        let mut paused = false;
+        while self.is_paused() {
+            paused = true;
+            thread::sleep(self.poll_interval);
+        }
+        paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_control_file_pauses_and_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pause");
+        let mut control = ControlFile::new(&path);
+        control.poll_interval(Duration::from_millis(10));
+
+        fs::write(&path, "").unwrap();
+        let scanned = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                for _ in 0..3 {
+                    control.wait_while_paused();
+                    scanned.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+
+            thread::sleep(Duration::from_millis(100));
+            assert_eq!(scanned.load(Ordering::SeqCst), 0);
+
+            fs::remove_file(&path).unwrap();
+            worker.join().unwrap();
+        });
+        assert_eq!(scanned.load(Ordering::SeqCst), 3);
+        assert!(!control.wait_while_paused());
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use crossbeam::channel::Sender;
+use yara_x::{Rule, Rules};
+
+use crate::{
+    scan::{OutputHandler, ScannedFile},
+    walk::Message,
+};
+
+/// Counts the files matched by each rule over a corpus, to find the rules
+/// that never match and the ones matching too much.
+pub struct RuleCoverage {
+    /// Number of files matched, by rule name.
+    files: Mutex<BTreeMap<String, usize>>,
+}
+
+impl RuleCoverage {
+    /// Starts counting for every rule of `rules`, so that the ones never
+    /// matching are reported with 0.
+    pub fn new(rules: &Rules) -> Self {
+        let files = rules
+            .iter()
+            .map(|rule| (rule.identifier().to_string(), 0))
+            .collect();
+        Self {
+            files: Mutex::new(files),
+        }
+    }
+
+    /// Returns the number of files matched, by rule name.
+    pub fn into_counts(self) -> BTreeMap<String, usize> {
+        self.files.into_inner().unwrap()
+    }
+}
+
+impl OutputHandler for RuleCoverage {
+    /// Counts every matching rule, whatever its score.
+    fn on_file_scanned(
+        &self,
+        _file: &ScannedFile<'_>,
+        scan_results: &[Rule<'_, '_>],
+        _output: &Sender<Message>,
+        _minimum_score: u32,
+    ) {
+        let mut files = self.files.lock().unwrap();
+        for rule in scan_results {
+            *files.entry(rule.identifier().to_string()).or_default() += 1;
+        }
+    }
+
+    fn on_done(&self, _output: &Sender<Message>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        rules,
+        scan::{self, ScanConfig},
+    };
+
+    #[test]
+    fn test_rule_coverage() -> anyhow::Result<()> {
+        let corpus = tempfile::tempdir()?;
+        fs::write(corpus.path().join("first.bin"), b"EVIL")?;
+        fs::write(corpus.path().join("second.bin"), b"more EVIL")?;
+        fs::write(corpus.path().join("clean.bin"), b"clean")?;
+        let mut compiler = rules::new_compiler(&Default::default()).unwrap();
+        compiler.add_source(
+            r#"
+rule Evil { strings: $a = "EVIL" condition: $a }
+rule Dead { strings: $a = "NEVER" condition: $a }
+rule Broad { condition: true }
+"#,
+        )?;
+        let rules = compiler.build();
+
+        let coverage = RuleCoverage::new(&rules);
+        let config = ScanConfig::new(Default::default(), vec![corpus.path().to_path_buf()]);
+        scan::scan_with_rules(&rules, &config, &coverage)?;
+
+        assert_eq!(
+            coverage.into_counts(),
+            BTreeMap::from([
+                ("Broad".to_string(), 3),
+                ("Dead".to_string(), 0),
+                ("Evil".to_string(), 2),
+            ])
+        );
+
+        Ok(())
+    }
+}
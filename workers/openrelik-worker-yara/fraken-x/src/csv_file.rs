@@ -0,0 +1,43 @@
+use std::borrow::Cow;
+
+/// Columns of the CSV output, in order.
+pub const HEADER: [&str; 6] = [
+    "ImagePath",
+    "SHA256",
+    "Signature",
+    "Description",
+    "Reference",
+    "Score",
+];
+
+/// Quotes `field` if it contains a comma, a quote or a line break, doubling
+/// its quotes.
+pub fn quote(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Renders `fields` as a CSV row, without its line break.
+pub fn row<'a>(fields: impl IntoIterator<Item = &'a str>) -> String {
+    fields.into_iter().map(quote).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row() {
+        assert_eq!(
+            row(HEADER),
+            "ImagePath,SHA256,Signature,Description,Reference,Score"
+        );
+        assert_eq!(
+            row(["/a b", "Evil, \"really\"", "two\nlines", ""]),
+            "/a b,\"Evil, \"\"really\"\"\",\"two\nlines\","
+        );
+    }
+}
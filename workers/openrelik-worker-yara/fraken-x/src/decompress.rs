@@ -0,0 +1,206 @@
+use std::io;
+
+/// Compression formats recognised by their magic bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Number of leading bytes [`Compression::detect`] needs.
+    pub const MAGIC_LEN: usize = 6;
+
+    /// Detects the compression format of `data` from its first bytes. Only
+    /// formats whose codec is compiled in are detected.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if cfg!(feature = "gzip") && data.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if cfg!(feature = "zstd") && data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if cfg!(feature = "bzip2") && data.starts_with(b"BZh") {
+            Some(Self::Bzip2)
+        } else if cfg!(feature = "xz") && data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Short name of the format, used in the virtual path of the
+    /// decompressed content.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+        }
+    }
+}
+
+/// Bounds applied when decompressing a file whose content is itself
+/// compressed, layer after layer, to keep decompression bombs in check.
+#[derive(Clone, Copy, Debug)]
+pub struct DecompressLimits {
+    /// Maximum number of nested layers decompressed.
+    pub max_depth: usize,
+    /// Maximum number of decompressed bytes, summed over all the layers.
+    pub max_bytes: u64,
+}
+
+impl Default for DecompressLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 1,
+            max_bytes: 100 * 1024 * 1024,
+        }
+    }
+}
+
+/// Decompresses `data`, keeping at most `max_len` bytes of output.
+pub fn decompress(compression: Compression, data: &[u8], max_len: u64) -> io::Result<Vec<u8>> {
+    match compression {
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => read_bounded(flate2::read::MultiGzDecoder::new(data), max_len),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => read_bounded(
+            ruzstd::decoding::StreamingDecoder::new(data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?,
+            max_len,
+        ),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => read_bounded(bzip2::read::MultiBzDecoder::new(data), max_len),
+        #[cfg(feature = "xz")]
+        Compression::Xz => xz_decompress(data, max_len),
+        #[allow(unreachable_patterns)]
+        _ => {
+            let _ = (data, max_len);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} support is not compiled in", compression.name()),
+            ))
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "bzip2"))]
+fn read_bounded(reader: impl io::Read, max_len: u64) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut output = Vec::new();
+    reader.take(max_len).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+#[cfg(feature = "xz")]
+fn xz_decompress(data: &[u8], max_len: u64) -> io::Result<Vec<u8>> {
+    // lzma-rs only writes its output, stop it once the limit is hit.
+    let mut output = Vec::new();
+    let mut limited = LimitedWriter {
+        output: &mut output,
+        max_len: max_len as usize,
+    };
+    match lzma_rs::xz_decompress(&mut io::BufReader::new(data), &mut limited) {
+        Ok(()) => {}
+        Err(_) if limited.is_full() => {}
+        Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+    }
+    Ok(output)
+}
+
+/// A writer that fails once `max_len` bytes were written.
+#[cfg(feature = "xz")]
+struct LimitedWriter<'a> {
+    output: &'a mut Vec<u8>,
+    max_len: usize,
+}
+
+#[cfg(feature = "xz")]
+impl LimitedWriter<'_> {
+    fn is_full(&self) -> bool {
+        self.output.len() >= self.max_len
+    }
+}
+
+#[cfg(feature = "xz")]
+impl io::Write for LimitedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_full() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "decompression limit reached",
+            ));
+        }
+        let len = buf.len().min(self.max_len - self.output.len());
+        self.output.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "gzip", feature = "zstd", feature = "xz"))]
+    const PAYLOAD: &[u8] = b"a compressed payload hiding EVIL content";
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() -> io::Result<()> {
+        use std::io::Write;
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(PAYLOAD)?;
+        let compressed = encoder.finish()?;
+
+        assert_eq!(Compression::detect(&compressed), Some(Compression::Gzip));
+        assert_eq!(decompress(Compression::Gzip, &compressed, 1024)?, PAYLOAD);
+        assert_eq!(
+            decompress(Compression::Gzip, &compressed, 10)?,
+            &PAYLOAD[..10]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() -> io::Result<()> {
+        let compressed =
+            ruzstd::encoding::compress_to_vec(PAYLOAD, ruzstd::encoding::CompressionLevel::Fastest);
+
+        assert_eq!(Compression::detect(&compressed), Some(Compression::Zstd));
+        assert_eq!(decompress(Compression::Zstd, &compressed, 1024)?, PAYLOAD);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn test_xz() -> io::Result<()> {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut io::BufReader::new(PAYLOAD), &mut compressed)?;
+
+        assert_eq!(Compression::detect(&compressed), Some(Compression::Xz));
+        assert_eq!(decompress(Compression::Xz, &compressed, 1024)?, PAYLOAD);
+        assert_eq!(
+            decompress(Compression::Xz, &compressed, 10)?,
+            &PAYLOAD[..10]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_plain_data() {
+        assert_eq!(Compression::detect(b"MZ\x90\x00"), None);
+        assert_eq!(Compression::detect(b""), None);
+    }
+}
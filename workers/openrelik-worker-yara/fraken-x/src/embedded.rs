@@ -0,0 +1,191 @@
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+
+/// Accepts base64 with or without padding, as found in scripts.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+/// Bounds applied when looking for encoded blobs.
+#[derive(Clone, Copy, Debug)]
+pub struct EmbeddedLimits {
+    /// Minimum length of an encoded run for it to be decoded.
+    pub min_encoded_len: usize,
+    /// Maximum number of blobs decoded per file.
+    pub max_blobs: usize,
+    /// Maximum size of a single decoded blob, longer runs are cut.
+    pub max_decoded_len: usize,
+}
+
+impl Default for EmbeddedLimits {
+    fn default() -> Self {
+        Self {
+            min_encoded_len: 64,
+            max_blobs: 16,
+            max_decoded_len: 1024 * 1024,
+        }
+    }
+}
+
+/// A base64 or hex blob found in a text file, already decoded.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EmbeddedBlob {
+    /// Offset of the encoded run in the file.
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+/// Returns true if `data` looks like text: no NUL bytes and mostly
+/// printable characters. Only the first 8 KiB are checked.
+pub fn looks_like_text(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(8192)];
+    if head.is_empty() || head.contains(&0) {
+        return false;
+    }
+    let printable = head
+        .iter()
+        .filter(|b| b.is_ascii_graphic() || b.is_ascii_whitespace() || **b >= 0x80)
+        .count();
+    printable * 100 / head.len() >= 95
+}
+
+fn is_base64_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'+' || b == b'/'
+}
+
+fn decode_hex(run: &[u8]) -> Option<Vec<u8>> {
+    run.chunks_exact(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+/// Finds runs of base64 or hex characters in `data` and decodes them.
+///
+/// Runs made only of hex digits are decoded as hex, others as base64. Runs
+/// that fail to decode are ignored.
+pub fn find_embedded_blobs(data: &[u8], limits: &EmbeddedLimits) -> Vec<EmbeddedBlob> {
+    let mut blobs = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() && blobs.len() < limits.max_blobs {
+        if !is_base64_char(data[pos]) {
+            pos += 1;
+            continue;
+        }
+        let start = pos;
+        while pos < data.len() && is_base64_char(data[pos]) {
+            pos += 1;
+        }
+        let end = pos;
+        while pos < data.len() && data[pos] == b'=' {
+            pos += 1;
+        }
+        if end - start < limits.min_encoded_len {
+            continue;
+        }
+
+        let run = &data[start..end];
+        let decoded = if run.iter().all(u8::is_ascii_hexdigit) {
+            // Each decoded byte takes two hex digits.
+            let len = (run.len() & !1).min(limits.max_decoded_len * 2);
+            decode_hex(&run[..len])
+        } else {
+            // Every 4 base64 characters decode into 3 bytes, a trailing
+            // single character can't be decoded.
+            let mut len = run.len().min(limits.max_decoded_len.div_ceil(3) * 4);
+            if len % 4 == 1 {
+                len -= 1;
+            }
+            BASE64.decode(&run[..len]).ok().map(|mut data| {
+                data.truncate(limits.max_decoded_len);
+                data
+            })
+        };
+        if let Some(data) = decoded {
+            blobs.push(EmbeddedBlob {
+                offset: start,
+                data,
+            });
+        }
+    }
+
+    blobs
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+
+    use super::*;
+
+    const LIMITS: EmbeddedLimits = EmbeddedLimits {
+        min_encoded_len: 16,
+        max_blobs: 16,
+        max_decoded_len: 1024,
+    };
+
+    #[test]
+    fn test_find_base64_blob() {
+        let payload = b"powershell -enc payload with EVIL inside";
+        let script = format!("$x = \"{}\"\nInvoke($x)\n", STANDARD.encode(payload));
+
+        let blobs = find_embedded_blobs(script.as_bytes(), &LIMITS);
+
+        assert_eq!(
+            blobs,
+            vec![EmbeddedBlob {
+                offset: 6,
+                data: payload.to_vec()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_hex_blob() {
+        let script = "data = '4d5a90000300000004000000ffff0000'";
+
+        let blobs = find_embedded_blobs(script.as_bytes(), &LIMITS);
+
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].offset, 8);
+        assert!(blobs[0].data.starts_with(b"MZ\x90\x00"));
+    }
+
+    #[test]
+    fn test_short_runs_ignored() {
+        let blobs = find_embedded_blobs(b"short words only here", &LIMITS);
+        assert!(blobs.is_empty());
+    }
+
+    #[test]
+    fn test_limits() {
+        let encoded = STANDARD.encode([b'A'; 300]);
+        let text = format!("{} {} {}", encoded, encoded, encoded);
+        let limits = EmbeddedLimits {
+            max_blobs: 2,
+            max_decoded_len: 100,
+            ..LIMITS
+        };
+
+        let blobs = find_embedded_blobs(text.as_bytes(), &limits);
+
+        assert_eq!(blobs.len(), 2);
+        assert!(blobs.iter().all(|blob| blob.data.len() == 100));
+    }
+
+    #[test]
+    fn test_looks_like_text() {
+        assert!(looks_like_text(b"#!/bin/sh\necho hello\n"));
+        assert!(!looks_like_text(b"MZ\x90\x00\x03\x00"));
+        assert!(!looks_like_text(b""));
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// Bytes read at once when looking for a non-zero byte. Most files with
+/// content are told apart by their first chunk.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// A file without any content, often left behind by carving or corruption.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmptyFile {
+    /// The file has no bytes at all.
+    ZeroBytes,
+    /// Every byte of the file is zero.
+    AllZeros,
+}
+
+impl EmptyFile {
+    /// Describes the file, for reporting.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::ZeroBytes => "empty file",
+            Self::AllZeros => "file filled with zeros",
+        }
+    }
+}
+
+/// Returns whether the file at `path`, `len` bytes long, is empty or only
+/// holds zeros. Reading stops at the first non-zero byte.
+pub fn check_empty(path: &Path, len: u64) -> io::Result<Option<EmptyFile>> {
+    if len == 0 {
+        return Ok(Some(EmptyFile::ZeroBytes));
+    }
+    let mut file = File::open(path)?.take(len);
+    let mut chunk = vec![0; CHUNK_LEN];
+    loop {
+        let n = file.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(Some(EmptyFile::AllZeros));
+        }
+        if chunk[..n].iter().any(|b| *b != 0) {
+            return Ok(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_check_empty() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let check = |name: &str, content: &[u8]| -> io::Result<Option<EmptyFile>> {
+            let path = dir.path().join(name);
+            fs::write(&path, content)?;
+            check_empty(&path, content.len() as u64)
+        };
+
+        assert_eq!(check("empty", b"")?, Some(EmptyFile::ZeroBytes));
+        assert_eq!(
+            check("zeros", &vec![0; 3 * CHUNK_LEN])?,
+            Some(EmptyFile::AllZeros)
+        );
+        assert_eq!(check("text", b"hello")?, None);
+        // The content is past the first chunk.
+        let mut tail = vec![0; 2 * CHUNK_LEN];
+        tail.push(1);
+        assert_eq!(check("tail", &tail)?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Encoding used when raw bytes (matched data, context, regions) are
+/// written to the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ByteEncoding {
+    /// Lowercase hexadecimal, two characters per byte.
+    #[default]
+    Hex,
+    /// Standard base64 with padding.
+    Base64,
+}
+
+impl ByteEncoding {
+    /// Encodes `bytes` as a string using this encoding.
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            ByteEncoding::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            ByteEncoding::Base64 => STANDARD.encode(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_match_region() {
+        let data = b"\x00\x01MZ\x90\xff";
+        let region = &data[2..6];
+
+        assert_eq!(ByteEncoding::Hex.encode(region), "4d5a90ff");
+        assert_eq!(ByteEncoding::Base64.encode(region), "TVqQ/w==");
+    }
+
+    #[test]
+    fn test_encode_empty() {
+        assert_eq!(ByteEncoding::Hex.encode(b""), "");
+        assert_eq!(ByteEncoding::Base64.encode(b""), "");
+    }
+
+    #[test]
+    fn test_default_is_hex() {
+        assert_eq!(ByteEncoding::default(), ByteEncoding::Hex);
+    }
+}
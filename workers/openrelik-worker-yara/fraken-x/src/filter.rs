@@ -0,0 +1,248 @@
+use std::{
+    fs::Metadata,
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// The setuid and setgid bits of a file's mode.
+const SETUID_SETGID: u32 = 0o6000;
+
+/// Returns the path of `file_path` relative to the scan `root`.
+///
+/// The walker strips a leading `./` from the root, so both forms of the root
+/// are tried.
+pub fn relative_to_root<'a>(file_path: &'a Path, root: &Path) -> Option<&'a Path> {
+    file_path
+        .strip_prefix(root)
+        .or_else(|_| file_path.strip_prefix(root.strip_prefix(".").unwrap_or(root)))
+        .ok()
+}
+
+/// Returns where `file_path` really is, once every symlink in it is
+/// resolved, if that is outside the scan `root`.
+pub fn resolved_outside_root(file_path: &Path, root: &Path) -> io::Result<Option<PathBuf>> {
+    let resolved = file_path.canonicalize()?;
+    let root = root.canonicalize()?;
+    Ok((!resolved.starts_with(root)).then_some(resolved))
+}
+
+/// Returns true if the file should be scanned when comparing against a
+/// baseline tree.
+///
+/// The file at the same relative path under `baseline` is looked up. Files
+/// that are absent from the baseline are new and must be scanned, as must
+/// files whose modification time differs from the baseline's.
+pub fn differs_from_baseline(
+    file_path: &Path,
+    metadata: &Metadata,
+    root: &Path,
+    baseline: &Path,
+) -> bool {
+    let Some(relative) = relative_to_root(file_path, root) else {
+        return true;
+    };
+    let Ok(baseline_metadata) = baseline.join(relative).metadata() else {
+        return true;
+    };
+    match (metadata.modified(), baseline_metadata.modified()) {
+        (Ok(mtime), Ok(baseline_mtime)) => mtime != baseline_mtime,
+        _ => true,
+    }
+}
+
+/// Returns true if the file has the setuid or setgid bit set.
+pub fn is_setuid_or_setgid(metadata: &Metadata) -> bool {
+    metadata.mode() & SETUID_SETGID != 0
+}
+
+/// Builds a set of the globs in `patterns`, where `*` doesn't cross folders
+/// and `**` does, matching regardless of case if `case_insensitive`.
+pub fn glob_set<'a>(
+    patterns: impl IntoIterator<Item = &'a str>,
+    case_insensitive: bool,
+) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("invalid glob `{}`", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// The globs of `--include` and `--exclude`, selecting the scanned files by
+/// their path relative to the scanned folder.
+#[derive(Debug)]
+pub struct PathGlobs {
+    /// Without any, all the files are included.
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathGlobs {
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        case_insensitive: bool,
+    ) -> anyhow::Result<Self> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(glob_set(
+                include.iter().map(String::as_str),
+                case_insensitive,
+            )?)
+        };
+        Ok(Self {
+            include,
+            exclude: glob_set(exclude.iter().map(String::as_str), case_insensitive)?,
+        })
+    }
+
+    /// Returns true if the file at `path` is scanned: matched by an include
+    /// glob, if there are any, and by no exclude glob.
+    pub fn is_scanned(&self, path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|globs| globs.is_match(path))
+            && !self.exclude.is_match(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    #[test]
+    fn test_relative_to_root_dot_prefix() {
+        assert_eq!(
+            relative_to_root(Path::new("scan/etc/passwd"), Path::new("./scan")),
+            Some(Path::new("etc/passwd"))
+        );
+        assert_eq!(
+            relative_to_root(Path::new("/mnt/etc/passwd"), Path::new("/mnt")),
+            Some(Path::new("etc/passwd"))
+        );
+        assert_eq!(
+            relative_to_root(Path::new("/other/passwd"), Path::new("/mnt")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_differs_from_baseline() -> std::io::Result<()> {
+        let root = tempfile::tempdir()?;
+        let baseline = tempfile::tempdir()?;
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        for name in ["unchanged", "modified"] {
+            for dir in [root.path(), baseline.path()] {
+                fs::write(dir.join(name), name)?;
+                File::options()
+                    .write(true)
+                    .open(dir.join(name))?
+                    .set_modified(mtime)?;
+            }
+        }
+        File::options()
+            .write(true)
+            .open(root.path().join("modified"))?
+            .set_modified(mtime + Duration::from_secs(60))?;
+        fs::write(root.path().join("new"), "new")?;
+
+        let check = |name: &str| {
+            let path = root.path().join(name);
+            let metadata = path.metadata().unwrap();
+            differs_from_baseline(&path, &metadata, root.path(), baseline.path())
+        };
+
+        assert!(!check("unchanged"));
+        assert!(check("modified"));
+        assert!(check("new"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_globs() {
+        let globs = PathGlobs::new(
+            &["**/*.exe".to_string(), "**/*.dll".to_string()],
+            &["**/cache/**".to_string()],
+            false,
+        )
+        .unwrap();
+        assert!(globs.is_scanned(Path::new("setup.exe")));
+        assert!(globs.is_scanned(Path::new("Windows/System32/kernel32.dll")));
+        assert!(!globs.is_scanned(Path::new("notes.txt")));
+        assert!(!globs.is_scanned(Path::new("SETUP.EXE")));
+        // Excludes take precedence.
+        assert!(!globs.is_scanned(Path::new("app/cache/setup.exe")));
+
+        // Without includes, everything not excluded is scanned.
+        let globs = PathGlobs::new(&[], &["**/cache/**".to_string()], true).unwrap();
+        assert!(globs.is_scanned(Path::new("notes.txt")));
+        assert!(!globs.is_scanned(Path::new("app/CACHE/notes.txt")));
+
+        assert!(PathGlobs::new(&["[".to_string()], &[], false).is_err());
+    }
+
+    #[test]
+    fn test_is_setuid_or_setgid() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let regular = dir.path().join("regular");
+        let setuid = dir.path().join("setuid");
+        let setgid = dir.path().join("setgid");
+        for (path, mode) in [(&regular, 0o755), (&setuid, 0o4755), (&setgid, 0o2755)] {
+            File::create(path).unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        assert!(!is_setuid_or_setgid(&regular.metadata().unwrap()));
+        assert!(is_setuid_or_setgid(&setuid.metadata().unwrap()));
+        assert!(is_setuid_or_setgid(&setgid.metadata().unwrap()));
+    }
+
+    #[test]
+    fn test_symlink_outside_root_not_scanned() -> anyhow::Result<()> {
+        let host = tempfile::tempdir()?;
+        let image = tempfile::tempdir()?;
+        fs::write(host.path().join("shadow"), b"host secret")?;
+        fs::write(image.path().join("evil.bin"), b"EVIL")?;
+        std::os::unix::fs::symlink(host.path().join("shadow"), image.path().join("shadow"))?;
+        std::os::unix::fs::symlink(image.path().join("evil.bin"), image.path().join("link"))?;
+
+        let mut scanned = Vec::new();
+        let mut walker = crate::walk::Walker::path(image.path());
+        walker.follow_links(true);
+        walker.walk(
+            |path| {
+                if resolved_outside_root(path, image.path())?.is_none() {
+                    scanned.push(path.file_name().unwrap().to_owned());
+                }
+                Ok(())
+            },
+            Err,
+        )?;
+        scanned.sort();
+        assert_eq!(scanned, ["evil.bin", "link"]);
+
+        assert_eq!(
+            resolved_outside_root(&image.path().join("shadow"), image.path())?,
+            Some(host.path().join("shadow").canonicalize()?)
+        );
+
+        Ok(())
+    }
+}
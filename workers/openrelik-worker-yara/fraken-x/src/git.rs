@@ -0,0 +1,226 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+
+/// Runs `git` with `args`, returning its stdout.
+fn git(args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .context("can not run `git`")?;
+    if !output.status.success() {
+        bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn is_commit_sha(git_ref: &str) -> bool {
+    git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Resolves `git_ref` (a branch, tag, full ref name or `HEAD`) in the
+/// remote repository at `url` to a commit SHA.
+///
+/// A name that is both a branch and a tag pointing to different commits is
+/// ambiguous and fails.
+pub fn resolve_ref(url: &str, git_ref: &str) -> anyhow::Result<String> {
+    if is_commit_sha(git_ref) {
+        return Ok(git_ref.to_lowercase());
+    }
+    let refs = git(&["ls-remote", "--", url, git_ref])?;
+    let lines: Vec<(&str, &str)> = refs
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect();
+    // Annotated tags are listed twice, the peeled `^{}` entry points to
+    // the commit itself.
+    let find = |name: &str| {
+        let peeled = format!("{}^{{}}", name);
+        lines
+            .iter()
+            .find(|(_, listed)| *listed == peeled)
+            .or_else(|| lines.iter().find(|(_, listed)| *listed == name))
+            .map(|(sha, _)| sha.to_string())
+    };
+
+    let sha = if git_ref == "HEAD" || git_ref.starts_with("refs/") {
+        find(git_ref)
+    } else {
+        let branch = find(&format!("refs/heads/{}", git_ref));
+        let tag = find(&format!("refs/tags/{}", git_ref));
+        match (branch, tag) {
+            (Some(branch), Some(tag)) if branch != tag => bail!(
+                "ref `{}` is both a branch and a tag in `{}`, use `refs/heads/{}` or `refs/tags/{}`",
+                git_ref,
+                url,
+                git_ref,
+                git_ref
+            ),
+            (branch, tag) => branch.or(tag),
+        }
+    };
+    sha.with_context(|| format!("ref `{}` not found in `{}`", git_ref, url))
+}
+
+/// Makes the rules repository at `url`, checked out at `git_ref`, available
+/// under `cache_dir` and returns its path.
+///
+/// Checkouts are cached by commit SHA, so the repository is only cloned
+/// again when `git_ref` points to a new commit.
+pub fn fetch_rules(url: &str, git_ref: &str, cache_dir: &Path) -> anyhow::Result<PathBuf> {
+    let sha = resolve_ref(url, git_ref)?;
+    let checkout = cache_dir.join(&sha);
+    if checkout.join(".git").is_dir() {
+        eprintln!("[+] Using cached rules at commit {}", sha);
+        return Ok(checkout);
+    }
+
+    fs::create_dir_all(cache_dir)
+        .with_context(|| format!("can not create `{}`", cache_dir.display()))?;
+    let partial = cache_dir.join(format!("{}.partial", sha));
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    let partial_str = partial.to_string_lossy();
+
+    eprintln!("[+] Cloning rules from {} at commit {}", url, sha);
+    git(&["clone", "--quiet", "--no-checkout", "--", url, &partial_str])?;
+    git(&["-C", &partial_str, "checkout", "--quiet", &sha])?;
+    fs::rename(&partial, &checkout)?;
+
+    Ok(checkout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a bare repository with a single rule file and returns its
+    /// path and the commit SHA.
+    fn rules_repo(dir: &Path) -> (PathBuf, String) {
+        let work = dir.join("work");
+        let bare = dir.join("rules.git");
+        fs::create_dir_all(&work).unwrap();
+        fs::write(
+            work.join("git.yar"),
+            r#"rule FromGit { strings: $a = "EVIL" condition: $a }"#,
+        )
+        .unwrap();
+
+        let work = work.to_str().unwrap();
+        git(&["-C", work, "init", "--quiet"]).unwrap();
+        git(&["-C", work, "add", "."]).unwrap();
+        git(&[
+            "-C",
+            work,
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "--quiet",
+            "-m",
+            "rules",
+        ])
+        .unwrap();
+        let sha = git(&["-C", work, "rev-parse", "HEAD"]).unwrap();
+        git(&["clone", "--quiet", "--bare", work, bare.to_str().unwrap()]).unwrap();
+
+        (bare, sha.trim().to_string())
+    }
+
+    #[test]
+    fn test_fetch_rules_from_bare_repo() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (bare, sha) = rules_repo(dir.path());
+        let cache = dir.path().join("cache");
+        let url = bare.to_str().unwrap();
+
+        let checkout = fetch_rules(url, "HEAD", &cache)?;
+        assert_eq!(checkout, cache.join(&sha));
+
+        let mut compiler = crate::rules::new_compiler(&Default::default())?;
+        crate::rules::add_rules_from(&mut compiler, &checkout, &Default::default())?;
+        let rules = compiler.build();
+        let mut scanner = yara_x::Scanner::new(&rules);
+        let results = scanner.scan(b"some EVIL content")?;
+        assert_eq!(results.matching_rules().len(), 1);
+
+        // The second fetch is served from the cache.
+        fs::write(checkout.join("marker"), "cached")?;
+        let cached = fetch_rules(url, &sha, &cache)?;
+        assert!(cached.join("marker").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_unknown_ref() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (bare, _) = rules_repo(dir.path());
+
+        assert!(resolve_ref(bare.to_str().unwrap(), "no-such-branch").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_ambiguous_ref() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (bare, sha) = rules_repo(dir.path());
+        let url = bare.to_str().unwrap();
+
+        // A tag named like the branch, on another commit.
+        let work = dir.path().join("work");
+        let work = work.to_str().unwrap();
+        git(&[
+            "-C",
+            work,
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "--quiet",
+            "--allow-empty",
+            "-m",
+            "more",
+        ])?;
+        let branch = git(&["-C", work, "branch", "--show-current"])?;
+        let branch = branch.trim();
+        git(&["-C", work, "tag", branch])?;
+        git(&[
+            "-C",
+            work,
+            "push",
+            "--quiet",
+            url,
+            &format!("refs/tags/{}", branch),
+        ])?;
+
+        assert!(resolve_ref(url, branch).is_err());
+        assert_eq!(resolve_ref(url, &format!("refs/heads/{}", branch))?, sha);
+        assert_ne!(resolve_ref(url, &format!("refs/tags/{}", branch))?, sha);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_is_not_an_option() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let url = format!("--upload-pack=touch {}", marker.display());
+
+        assert!(resolve_ref(&url, "HEAD").is_err());
+        assert!(fetch_rules(&url, &"0".repeat(40), &dir.path().join("cache")).is_err());
+        assert!(!marker.exists());
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{bail, Context};
+
+/// Identifies the cache files, and their format version.
+const MAGIC: &[u8; 8] = b"FXHASH01";
+
+/// Longest path in a cache file, `PATH_MAX` on Linux. Files with longer
+/// ones are rejected rather than trusted with the allocation.
+const MAX_PATH_LEN: usize = 4096;
+
+/// Cached hashes by path, with what they are valid for.
+type Entries = HashMap<PathBuf, (Stamp, [u8; 32])>;
+
+/// What a cached hash is valid for: the file's size and modification time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl Stamp {
+    fn of(metadata: &fs::Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        }
+    }
+}
+
+/// SHA-256 hashes of files, kept across runs so that the unchanged files of
+/// the same evidence are not hashed again. An entry is valid as long as the
+/// file has the same path, size and modification time. Only the entries
+/// used by a run are saved, the others are dropped.
+///
+/// The cache file is a sequence of entries, after [`MAGIC`]: the length of
+/// the path as a little-endian `u32`, the path bytes, the size, seconds and
+/// nanoseconds of the modification time as little-endian 64-bit integers,
+/// and the 32 bytes of the hash.
+#[derive(Debug, Default)]
+pub struct HashCache {
+    /// The entries loaded and not used so far.
+    loaded: Mutex<Entries>,
+    /// The entries used by this run.
+    entries: Mutex<Entries>,
+}
+
+impl HashCache {
+    /// Loads the cache file at `path`, or starts an empty cache if there is
+    /// none yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("can not read `{}`", path.display()))
+            }
+        };
+        let loaded = read_entries(&mut BufReader::new(file))
+            .with_context(|| format!("invalid hash cache `{}`", path.display()))?;
+        Ok(Self {
+            loaded: Mutex::new(loaded),
+            entries: Mutex::default(),
+        })
+    }
+
+    /// Writes the entries used to `path`, replacing the previous file only
+    /// once written in full.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let file = File::create(&temp_path)
+            .with_context(|| format!("can not create `{}`", temp_path.display()))?;
+        let mut writer = BufWriter::new(file);
+        write_entries(&mut writer, &self.entries.lock().unwrap())
+            .and_then(|_| writer.flush())
+            .with_context(|| format!("can not write `{}`", temp_path.display()))?;
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("can not replace `{}`", path.display()))
+    }
+
+    /// Returns the hash of the file at `path`, from the cache if the file
+    /// didn't change, or computed by `digest` and cached otherwise. Empty
+    /// hashes, from files that couldn't be read, are not cached.
+    pub fn digest(&self, path: &Path, digest: impl FnOnce() -> String) -> String {
+        let Ok(metadata) = fs::metadata(path) else {
+            return digest();
+        };
+        let stamp = Stamp::of(&metadata);
+        let loaded = self.loaded.lock().unwrap().remove(path);
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = loaded {
+            entries.insert(path.to_path_buf(), entry);
+        }
+        if let Some((cached, hash)) = entries.get(path) {
+            if *cached == stamp {
+                return to_hex(hash);
+            }
+        }
+        drop(entries);
+        let hash = digest();
+        if let Some(bytes) = from_hex(&hash) {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), (stamp, bytes));
+        }
+        hash
+    }
+}
+
+fn read_entries(reader: &mut impl Read) -> anyhow::Result<Entries> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("not a hash cache");
+    }
+    let mut entries = HashMap::new();
+    loop {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_PATH_LEN {
+            bail!("path of {} bytes, longer than {}", len, MAX_PATH_LEN);
+        }
+        let mut path = vec![0; len];
+        reader.read_exact(&mut path)?;
+        let mut numbers = [0; 24];
+        reader.read_exact(&mut numbers)?;
+        let number = |i: usize| numbers[i * 8..(i + 1) * 8].try_into().unwrap();
+        let stamp = Stamp {
+            size: u64::from_le_bytes(number(0)),
+            mtime: i64::from_le_bytes(number(1)),
+            mtime_nsec: i64::from_le_bytes(number(2)),
+        };
+        let mut hash = [0; 32];
+        reader.read_exact(&mut hash)?;
+        let path = PathBuf::from(std::ffi::OsStr::from_bytes(&path));
+        entries.insert(path, (stamp, hash));
+    }
+    Ok(entries)
+}
+
+fn write_entries(writer: &mut impl Write, entries: &Entries) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    for (path, (stamp, hash)) in entries {
+        let path = path.as_os_str().as_bytes();
+        writer.write_all(&(path.len() as u32).to_le_bytes())?;
+        writer.write_all(path)?;
+        writer.write_all(&stamp.size.to_le_bytes())?;
+        writer.write_all(&stamp.mtime.to_le_bytes())?;
+        writer.write_all(&stamp.mtime_nsec.to_le_bytes())?;
+        writer.write_all(hash)?;
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes a hexadecimal SHA-256 hash, if `hex` is one.
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_unchanged_file_served_from_cache() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("evidence.bin");
+        fs::write(&file, b"EVIL")?;
+        let cache_path = dir.path().join("hashes.cache");
+        let calls = Cell::new(0);
+        let digest = || {
+            calls.set(calls.get() + 1);
+            sha256::try_digest(file.as_path()).unwrap()
+        };
+
+        // First run.
+        let cache = HashCache::load(&cache_path)?;
+        let hash = cache.digest(&file, digest);
+        assert_eq!(calls.get(), 1);
+        cache.save(&cache_path)?;
+
+        // Second run, the file didn't change.
+        let cache = HashCache::load(&cache_path)?;
+        assert_eq!(cache.digest(&file, digest), hash);
+        assert_eq!(calls.get(), 1);
+
+        // The file changed.
+        fs::write(&file, b"EVIL, again")?;
+        assert_ne!(cache.digest(&file, digest), hash);
+        assert_eq!(calls.get(), 2);
+
+        // Unreadable files are not cached.
+        let missing = dir.path().join("missing.bin");
+        assert_eq!(cache.digest(&missing, String::new), "");
+
+        fs::write(&cache_path, b"garbage")?;
+        assert!(HashCache::load(&cache_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unused_entries_dropped() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let kept = dir.path().join("kept.bin");
+        let dropped = dir.path().join("dropped.bin");
+        fs::write(&kept, b"EVIL")?;
+        fs::write(&dropped, b"EVIL")?;
+        let cache_path = dir.path().join("hashes.cache");
+        let digest = |path: &Path| sha256::try_digest(path).unwrap();
+
+        let cache = HashCache::load(&cache_path)?;
+        cache.digest(&kept, || digest(&kept));
+        cache.digest(&dropped, || digest(&dropped));
+        cache.save(&cache_path)?;
+
+        // Only `kept` is hashed by the second run.
+        let cache = HashCache::load(&cache_path)?;
+        cache.digest(&kept, || digest(&kept));
+        cache.save(&cache_path)?;
+
+        let cache = HashCache::load(&cache_path)?;
+        let loaded = cache.loaded.into_inner().unwrap();
+        assert!(loaded.contains_key(&kept));
+        assert!(!loaded.contains_key(&dropped));
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_path_rejected() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache_path = dir.path().join("hashes.cache");
+        let mut content = MAGIC.to_vec();
+        content.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&cache_path, content)?;
+
+        let err = HashCache::load(&cache_path).unwrap_err();
+        assert!(format!("{:#}", err).contains("longer than 4096"));
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::magic;
+use crate::rules::{self, CompilerOptions};
+
+/// Outcome of a successful health check.
+#[derive(Debug)]
+pub struct HealthReport {
+    /// Number of rules that compiled.
+    pub num_rules: usize,
+    /// Number of parsed file magics, or `None` if no magic file was found.
+    pub num_magics: Option<usize>,
+}
+
+/// Checks that everything a scan needs is ready: the rules under
+/// `rules_path` compile without errors into a non-empty rule set, and the
+/// magic files present parse.
+pub fn check(
+    rules_path: &Path,
+    magic_paths: &[PathBuf],
+    options: &CompilerOptions,
+) -> anyhow::Result<HealthReport> {
+    if !rules_path.exists() {
+        bail!("rules path `{}` does not exist", rules_path.display());
+    }
+
+    let mut compiler = rules::new_compiler(options)?;
+    rules::add_rules_from(&mut compiler, rules_path, options)?;
+    if let Some(error) = compiler.errors().first() {
+        bail!(
+            "{} rule error(s), first: {}",
+            compiler.errors().len(),
+            error
+        );
+    }
+    let num_rules = compiler.build().iter().len();
+    if num_rules == 0 {
+        bail!("no rules found under `{}`", rules_path.display());
+    }
+
+    let mut num_magics = None;
+    for path in magic_paths.iter().filter(|path| path.is_file()) {
+        *num_magics.get_or_insert(0) += parse_magic(path)?;
+    }
+
+    Ok(HealthReport {
+        num_rules,
+        num_magics,
+    })
+}
+
+fn parse_magic(path: &Path) -> anyhow::Result<usize> {
+    let reader = BufReader::new(File::open(path)?);
+    let (definitions, _) = magic::parse_definitions_file(reader)
+        .map_err(|err| anyhow!("magic file `{}`: {}", path.display(), err))?;
+    Ok(definitions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_check_healthy() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("ok.yar"), "rule Ok { condition: true }")?;
+        fs::write(dir.path().join("magic.txt"), "4D 5A;Windows Executable\n")?;
+
+        let report = check(
+            dir.path(),
+            &[dir.path().join("magic.txt"), dir.path().join("missing.txt")],
+            &CompilerOptions::default(),
+        )?;
+        assert_eq!(report.num_rules, 1);
+        assert_eq!(report.num_magics, Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_broken_rules() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("ok.yar"), "rule Ok { condition: true }")?;
+        fs::write(dir.path().join("broken.yar"), "rule Broken { condition: }")?;
+
+        assert!(check(dir.path(), &[], &CompilerOptions::default()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_broken_magic() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("ok.yar"), "rule Ok { condition: true }")?;
+        fs::write(dir.path().join("magic.txt"), "not a magic line\n")?;
+
+        let magic = dir.path().join("magic.txt");
+        assert!(check(dir.path(), &[magic], &CompilerOptions::default()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_missing_rules() {
+        let result = check(
+            Path::new("/nonexistent/rules"),
+            &[],
+            &CompilerOptions::default(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::scan::ScanCounters;
+
+/// Touches a file while the scan makes progress, so that orchestration can
+/// tell a stuck worker by the file's stale modification time.
+pub struct Heartbeat {
+    /// Stops the thread when dropped.
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    /// Creates the file at `path`, then checks `counters` every `interval`
+    /// from a thread of its own until the heartbeat is dropped, touching the
+    /// file if more files were scanned since the last check.
+    pub fn start(
+        path: &Path,
+        interval: Duration,
+        counters: Arc<ScanCounters>,
+    ) -> anyhow::Result<Self> {
+        let mut pulse = Pulse::new(path, counters)?;
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(_err) = pulse.beat() {
+                    #[cfg(feature = "logging")]
+                    log::debug!("heartbeat not updated: {:#}", _err);
+                }
+            }
+        });
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The file of a [`Heartbeat`], touched as the scanned files count goes up.
+struct Pulse {
+    path: PathBuf,
+    counters: Arc<ScanCounters>,
+    /// Files scanned when the file was last touched.
+    scanned: usize,
+}
+
+impl Pulse {
+    /// Creates the file at `path`, touched as of the files scanned so far.
+    fn new(path: &Path, counters: Arc<ScanCounters>) -> anyhow::Result<Self> {
+        touch(path)?;
+        let scanned = counters.scanned.load(Ordering::Relaxed);
+        Ok(Self {
+            path: path.to_path_buf(),
+            counters,
+            scanned,
+        })
+    }
+
+    /// Touches the file if more files were scanned since it last was.
+    /// Returns whether it was touched.
+    fn beat(&mut self) -> anyhow::Result<bool> {
+        let scanned = self.counters.scanned.load(Ordering::Relaxed);
+        if scanned == self.scanned {
+            return Ok(false);
+        }
+        touch(&self.path)?;
+        self.scanned = scanned;
+        Ok(true)
+    }
+}
+
+/// Creates the file at `path` if needed and sets its modification time to
+/// now.
+fn touch(path: &Path) -> anyhow::Result<()> {
+    File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+        .with_context(|| format!("can not touch `{}`", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn modified(path: &Path) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    /// Sets the modification time of `path` far in the past.
+    fn make_stale(path: &Path) -> SystemTime {
+        let stale = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(stale)
+            .unwrap();
+        stale
+    }
+
+    #[test]
+    fn test_pulse() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heartbeat");
+        let counters = Arc::new(ScanCounters::default());
+
+        let mut pulse = Pulse::new(&path, counters.clone()).unwrap();
+        let stale = make_stale(&path);
+
+        // The scan is stuck.
+        assert!(!pulse.beat().unwrap());
+        assert_eq!(modified(&path), stale);
+
+        // The scan moves on.
+        counters.count_file(false);
+        assert!(pulse.beat().unwrap());
+        assert!(modified(&path) > stale);
+
+        // Then is done.
+        let stale = make_stale(&path);
+        assert!(!pulse.beat().unwrap());
+        assert_eq!(modified(&path), stale);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heartbeat");
+        let counters = Arc::new(ScanCounters::default());
+
+        // Created right away, and stopped without waiting for the interval.
+        let heartbeat = Heartbeat::start(&path, Duration::from_secs(3600), counters.clone());
+        assert!(path.exists());
+        drop(heartbeat);
+
+        // The parent is missing.
+        assert!(
+            Heartbeat::start(&path.join("heartbeat"), Duration::from_secs(1), counters).is_err()
+        );
+    }
+}
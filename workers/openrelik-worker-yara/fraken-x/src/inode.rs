@@ -0,0 +1,64 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Keeps track of the (device, inode) pairs seen during a walk so that files
+/// with several hardlinks are only scanned once.
+#[derive(Default)]
+pub struct InodeTracker {
+    seen: Mutex<HashMap<(u64, u64), PathBuf>>,
+}
+
+impl InodeTracker {
+    /// Records `path` as the first path seen for (`dev`, `ino`).
+    ///
+    /// Returns `None` if this is the first time the inode is seen, in which
+    /// case the file must be scanned, or the path that was recorded first
+    /// otherwise.
+    pub fn first_seen(&self, dev: u64, ino: u64, path: &Path) -> Option<PathBuf> {
+        let mut seen = self.seen.lock().unwrap();
+        match seen.get(&(dev, ino)) {
+            Some(original) => Some(original.clone()),
+            None => {
+                seen.insert((dev, ino), path.to_path_buf());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+
+    #[test]
+    fn test_first_seen_hardlinks() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let original = dir.path().join("original");
+        let link = dir.path().join("link");
+        let other = dir.path().join("other");
+        fs::write(&original, b"content")?;
+        fs::hard_link(&original, &link)?;
+        fs::write(&other, b"content")?;
+
+        let tracker = InodeTracker::default();
+        for (path, expected) in [
+            (&original, None),
+            (&link, Some(original.clone())),
+            (&other, None),
+        ] {
+            let metadata = fs::metadata(path)?;
+            assert_eq!(
+                tracker.first_seen(metadata.dev(), metadata.ino(), path),
+                expected
+            );
+        }
+
+        Ok(())
+    }
+}
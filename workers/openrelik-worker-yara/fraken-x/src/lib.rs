@@ -1,3 +1,42 @@
+pub mod allowlist;
+pub mod anomaly;
+pub mod blocks;
+pub mod buffer;
+pub mod control;
+pub mod coverage;
+pub mod csv_file;
+pub mod decompress;
+#[cfg(feature = "elasticsearch")]
+pub mod elasticsearch;
+pub mod embedded;
+pub mod empty;
+pub mod encoding;
+pub mod filter;
+pub mod git;
+pub mod hash_cache;
+pub mod health;
+pub mod heartbeat;
+pub mod inode;
 pub mod magic;
+pub mod manifest;
+pub mod merge;
+pub mod output;
+#[cfg(feature = "parquet")]
+pub mod parquet_file;
+pub mod policy;
+pub mod profile;
+pub mod reorder;
+pub mod rescan;
+pub mod retry;
+pub mod rules;
+pub mod scan;
+pub mod semaphore;
+pub mod skips;
+pub mod status;
+pub mod syslog_sink;
+pub mod targets;
+pub mod tui;
 pub mod userid;
 pub mod walk;
+
+pub use scan::{scan, ScanConfig, ScanSummary};
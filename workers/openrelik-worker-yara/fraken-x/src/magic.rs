@@ -1,6 +1,7 @@
 use std::{
     fs::File,
     io::{BufRead, BufReader, Read},
+    path::Path,
 };
 
 // Trait to abstract over different Read types
@@ -8,9 +9,53 @@ pub trait Readable: BufRead {}
 
 impl<T: Read> Readable for BufReader<T> {}
 
+/// A file magic: signature bytes found at a fixed offset in the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Magic {
+    /// The signature, with `None` for the bytes that can have any value.
+    pub bytes: Vec<Option<u8>>,
+    /// Offset of the signature from the start of the file.
+    pub offset: usize,
+    pub description: String,
+}
+
+impl Magic {
+    /// Returns true if `head`, the first bytes of a file, holds the
+    /// signature.
+    pub fn matches(&self, head: &[u8]) -> bool {
+        head.get(self.offset..self.end()).is_some_and(|bytes| {
+            bytes
+                .iter()
+                .zip(&self.bytes)
+                .all(|(byte, expected)| expected.is_none_or(|expected| *byte == expected))
+        })
+    }
+
+    /// Offset just past the signature, the number of bytes of a file needed
+    /// to tell whether it matches.
+    pub fn end(&self) -> usize {
+        self.offset + self.bytes.len()
+    }
+}
+
+/// A list of file magics.
+pub type Definitions = Vec<Magic>;
+
+/// Parses the definitions of a magic file, one per line, as the hex bytes of
+/// the signature, with `??` for any byte, optionally followed by `@` and its
+/// decimal offset, then `;` and the description:
+///
+/// ```text
+/// CA FE;Java Class
+/// 66 74 79 70 @ 4;MP4
+/// FF D8 FF ??;JPEG
+/// ```
+///
+/// Returns the definitions along with the number of bytes to read from a
+/// file to match all of them.
 pub fn parse_definitions_file<R: Readable>(
     reader: R,
-) -> Result<(Vec<(Vec<u8>, String)>, usize), Box<dyn std::error::Error>> {
+) -> Result<(Definitions, usize), Box<dyn std::error::Error>> {
     let mut definitions = Vec::new();
     let mut max_len = 0;
 
@@ -27,25 +72,93 @@ pub fn parse_definitions_file<R: Readable>(
             return Err(format!("Invalid line format: {}", line).into());
         }
 
-        let hex_str = parts[0].trim();
+        let (hex_str, offset) = match parts[0].split_once('@') {
+            Some((hex_str, offset)) => (hex_str.trim(), offset.trim().parse::<usize>()?),
+            None => (parts[0].trim(), 0),
+        };
         let description = parts[1].trim().to_string();
 
         let hex_bytes = hex_str
             .split_whitespace()
-            .map(|byte_str| u8::from_str_radix(byte_str, 16))
-            .collect::<Result<Vec<u8>, _>>()?;
+            .map(|byte_str| match byte_str {
+                "??" => Ok(None),
+                byte_str => u8::from_str_radix(byte_str, 16).map(Some),
+            })
+            .collect::<Result<Vec<Option<u8>>, _>>()?;
 
-        let len = hex_bytes.len();
-        if len > max_len {
-            max_len = len;
+        let magic = Magic {
+            bytes: hex_bytes,
+            offset,
+            description,
+        };
+        if magic.end() > max_len {
+            max_len = magic.end();
         }
 
-        definitions.push((hex_bytes, description));
+        definitions.push(magic);
     }
 
     Ok((definitions, max_len))
 }
 
+/// Loads the magic file at `path`. Unless `required` is set, a missing or
+/// unparseable file only prints a warning and `None` is returned.
+pub fn load_definitions_file(
+    path: &Path,
+    required: bool,
+) -> Result<Option<(Definitions, usize)>, Box<dyn std::error::Error>> {
+    if !path.is_file() {
+        if required {
+            return Err(format!("magic file `{}` not found", path.display()).into());
+        }
+        eprintln!("[-] Magic file specified but file not found.");
+        return Ok(None);
+    }
+    match parse_definitions_file(BufReader::new(File::open(path)?)) {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(err) if required => {
+            Err(format!("magic file `{}` can not be parsed: {}", path.display(), err).into())
+        }
+        Err(err) => {
+            eprintln!("[-] Magic file can not be parsed: {}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// Concatenates the definitions of several magic files, in order, so that
+/// the earlier files take precedence. Returns them with the number of bytes
+/// to read from a file to match all of them.
+pub fn merge_definitions(parsed: Vec<(Definitions, usize)>) -> (Definitions, usize) {
+    let max_len = parsed
+        .iter()
+        .map(|(_, max_len)| *max_len)
+        .max()
+        .unwrap_or(0);
+    let definitions = parsed
+        .into_iter()
+        .flat_map(|(definitions, _)| definitions)
+        .collect();
+    (definitions, max_len)
+}
+
+/// Returns the pairs of magics with the same signature at the same offset
+/// but different descriptions. Only the first of each pair is ever used.
+pub fn conflicting_definitions(definitions: &[Magic]) -> Vec<(&Magic, &Magic)> {
+    let mut conflicts = Vec::new();
+    for (i, first) in definitions.iter().enumerate() {
+        for second in &definitions[i + 1..] {
+            if first.bytes == second.bytes
+                && first.offset == second.offset
+                && first.description != second.description
+            {
+                conflicts.push((first, second));
+            }
+        }
+    }
+    conflicts
+}
+
 pub fn read_first_bytes(
     file_path: &str,
     num_bytes: usize,
@@ -80,6 +193,96 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_definitions_file_offsets() -> Result<(), Box<dyn std::error::Error>> {
+        let test_file_content = "CA FE;Java Class\n66 74 79 70 @ 4;MP4\n";
+        let reader = BufReader::new(Cursor::new(test_file_content.as_bytes()));
+
+        let (definitions, max_len) = parse_definitions_file(reader)?;
+
+        assert_eq!(
+            definitions,
+            [
+                Magic {
+                    bytes: vec![Some(0xCA), Some(0xFE)],
+                    offset: 0,
+                    description: "Java Class".to_string(),
+                },
+                Magic {
+                    bytes: b"ftyp".iter().copied().map(Some).collect(),
+                    offset: 4,
+                    description: "MP4".to_string(),
+                },
+            ]
+        );
+        assert_eq!(max_len, 8);
+
+        let mp4 = b"\x00\x00\x00\x18ftypmp42";
+        assert!(!definitions[0].matches(mp4));
+        assert!(definitions[1].matches(mp4));
+        // Too short to hold the signature.
+        assert!(!definitions[1].matches(b"\x00\x00\x00\x18ft"));
+
+        let reader = BufReader::new(Cursor::new("66 74 @ four;MP4".as_bytes()));
+        assert!(parse_definitions_file(reader).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_definitions_file_wildcards() -> Result<(), Box<dyn std::error::Error>> {
+        let test_file_content = "FF D8 FF ??;JPEG\n4D ?? ?? 5A;Two wildcards\n";
+        let reader = BufReader::new(Cursor::new(test_file_content.as_bytes()));
+
+        let (definitions, max_len) = parse_definitions_file(reader)?;
+
+        assert_eq!(
+            definitions[0].bytes,
+            [Some(0xFF), Some(0xD8), Some(0xFF), None]
+        );
+        assert_eq!(max_len, 4);
+        assert!(definitions[0].matches(b"\xFF\xD8\xFF\xE0"));
+        assert!(definitions[0].matches(b"\xFF\xD8\xFF\xDB\x00"));
+        assert!(!definitions[0].matches(b"\xFF\xD9\xFF\xE0"));
+        // The wildcard byte must still be there.
+        assert!(!definitions[0].matches(b"\xFF\xD8\xFF"));
+        assert!(definitions[1].matches(b"M\x00\x01Z"));
+        assert!(!definitions[1].matches(b"M\x00\x01Y"));
+
+        let reader = BufReader::new(Cursor::new("FF ?;Broken".as_bytes()));
+        assert!(parse_definitions_file(reader).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_definitions() -> Result<(), Box<dyn std::error::Error>> {
+        let parse = |content: &str| parse_definitions_file(BufReader::new(Cursor::new(content)));
+        let (definitions, max_len) = merge_definitions(vec![
+            parse("4D 5A;Windows Executable\n7F 45 4C 46;ELF\n")?,
+            parse("4D 5A;DOS Executable\n66 74 79 70 @ 4;MP4\n7F 45 4C 46;ELF\n")?,
+        ]);
+
+        assert_eq!(max_len, 8);
+        let descriptions: Vec<_> = definitions.iter().map(|m| m.description.as_str()).collect();
+        assert_eq!(
+            descriptions,
+            ["Windows Executable", "ELF", "DOS Executable", "MP4", "ELF"]
+        );
+        // The earlier file wins.
+        let found = definitions.iter().find(|m| m.matches(b"MZ\x90\x00"));
+        assert_eq!(found.unwrap().description, "Windows Executable");
+
+        // Only the same signature with another description conflicts.
+        let conflicts: Vec<_> = conflicting_definitions(&definitions)
+            .into_iter()
+            .map(|(first, second)| (first.description.as_str(), second.description.as_str()))
+            .collect();
+        assert_eq!(conflicts, [("Windows Executable", "DOS Executable")]);
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_definitions_file_empty() -> Result<(), Box<dyn std::error::Error>> {
         let test_file_content = "";
@@ -123,4 +326,28 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_load_definitions_file_missing() {
+        let path = Path::new("/nonexistent/file-type-signatures.txt");
+
+        assert!(load_definitions_file(path, true).is_err());
+        assert!(matches!(load_definitions_file(path, false), Ok(None)));
+    }
+
+    #[test]
+    fn test_load_definitions_file_unparseable() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("magic.txt");
+        std::fs::write(&path, "CA FE Java Class\n")?;
+
+        assert!(load_definitions_file(&path, true).is_err());
+        assert!(load_definitions_file(&path, false)?.is_none());
+
+        std::fs::write(&path, "CA FE;Java Class\n")?;
+        let (definitions, _) = load_definitions_file(&path, true)?.unwrap();
+        assert_eq!(definitions.len(), 1);
+
+        Ok(())
+    }
 }
@@ -1,50 +1,772 @@
 // Some portions Copyright (c) 2024. The YARA-X Authors. All Rights Reserved.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
-use std::os::unix::fs::MetadataExt;
 use std::path::Path;
-use std::{fs, path::PathBuf, process, sync::atomic::Ordering};
+use std::{fs, path::PathBuf, process};
+use std::{io, thread};
 
-use anyhow::Context;
 use crossbeam::channel::Sender;
+use fraken_x::allowlist::PathAllowlist;
+use fraken_x::anomaly::FilenameAnomaly;
+use fraken_x::blocks::BlockBitmap;
+use fraken_x::coverage::RuleCoverage;
+use fraken_x::csv_file;
+use fraken_x::decompress::DecompressLimits;
+#[cfg(feature = "elasticsearch")]
+use fraken_x::elasticsearch::{self, BulkClient};
+use fraken_x::embedded::EmbeddedLimits;
+use fraken_x::empty::EmptyFile;
+use fraken_x::encoding::ByteEncoding;
+use fraken_x::filter;
+use fraken_x::git;
+use fraken_x::hash_cache::HashCache;
+use fraken_x::health;
+use fraken_x::heartbeat::Heartbeat;
 use fraken_x::magic;
-use fraken_x::userid;
-use fraken_x::walk::{Message, ParWalker, Walker};
-use superconsole::{Component, Lines};
+use fraken_x::manifest::Manifest;
+use fraken_x::merge;
+use fraken_x::output::OutputFile;
+#[cfg(feature = "parquet")]
+use fraken_x::parquet_file::{self, MatchRow};
+use fraken_x::policy::Policy;
+use fraken_x::profile::{self, ScanProfile};
+use fraken_x::rules::{self, RuleTarget, RulesInfo};
+use fraken_x::scan::{
+    self, Extracted, OutputHandler, ScanConfig, ScanCounters, ScanSummary, ScannedFile,
+};
+use fraken_x::skips::{FilterTrace, SkipLog};
+use fraken_x::status::StatusFile;
+use fraken_x::syslog_sink::{SyslogSeverity, SyslogSink};
+use fraken_x::targets::{self, Target};
+use fraken_x::tui::{self, KeysEnd, MatchBrowser, RawMode};
+use fraken_x::walk::{Message, WalkOrder, WalkOutput};
 
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use clap::{Args, Parser};
+use clap::{Args, Parser, ValueEnum};
 
-use yara_x::{MatchingRules, MetaValue, Scanner, SourceCode};
-
-use yansi::Color::Red;
-use yansi::Paint;
+use yara_x::{MetaValue, Rule, Scanner};
 
 use sha256::try_digest;
+use syslog::Facility;
+
+use crossterm::tty::IsTty;
+use superconsole::SuperConsole;
 
 #[derive(Parser)]
 #[command(about, long_about = None)]
 struct Cli {
     /// Specify a particular path to a file or folder containing the Yara rules to use
-    rules: PathBuf,
+    #[arg(required_unless_present_any = ["rules_from_git", "use_builtin_rules", "merge_reports", "load_rules"])]
+    rules: Option<PathBuf>,
+
+    /// Also use the rules built into fraken-x, which detect test files like
+    /// EICAR. With no rules path, only these rules are used
+    #[arg(long, conflicts_with_all = ["rules_diff", "healthcheck"])]
+    use_builtin_rules: bool,
+
+    /// Clone the Yara rules from this git repository instead of reading them
+    /// from a local path
+    #[arg(long, value_name = "URL", conflicts_with = "rules")]
+    rules_from_git: Option<String>,
+
+    /// Branch, tag or commit of the rules repository to use
+    #[arg(
+        long,
+        value_name = "REF",
+        default_value = "HEAD",
+        requires = "rules_from_git"
+    )]
+    rules_git_ref: String,
+
+    /// Directory where cloned rules repositories are cached, by commit
+    #[arg(long, value_name = "DIR", requires = "rules_from_git")]
+    rules_cache_dir: Option<PathBuf>,
 
     #[command(flatten)]
     testorscan: TestOrScan,
 
-    /// A path under the rules path that contains File Magics
+    /// A path under the rules path that contains File Magics. Can be given
+    /// several times, the magics of the earlier files taking precedence
     #[arg(long, default_value = "misc/file-type-signatures.txt")]
-    magic: Option<PathBuf>,
+    magic: Vec<PathBuf>,
+
+    /// Fail instead of scanning without file types when the magic file is
+    /// missing or can't be parsed
+    #[arg(long)]
+    require_magic: bool,
+
+    /// When a rule file fails to compile, drop the broken rule and retry the
+    /// rest of the file, dropping at most this many rules in total. Keeps
+    /// the rules a syntax error would otherwise discard
+    #[arg(long, value_name = "MAX")]
+    drop_broken_rules: Option<usize>,
+
+    /// Write the compiled rules to this file, for --load-rules to use on the
+    /// next runs
+    #[arg(long, value_name = "PATH", conflicts_with = "load_rules")]
+    save_rules: Option<PathBuf>,
+
+    /// Load the rules written by --save-rules from this file instead of
+    /// compiling them. The rules path, if given, is only used for its magic
+    /// files, and the compiler options can't be given
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["rules_from_git", "use_builtin_rules", "drop_broken_rules", "rules_diff", "healthcheck", "define", "relaxed_re_syntax", "ignore_module", "enable_feature"]
+    )]
+    load_rules: Option<PathBuf>,
+
+    /// Fail when compiling the rules gives any warning, like a condition
+    /// that isn't a boolean, instead of only printing it
+    #[arg(long)]
+    strict: bool,
+
+    /// Refuse to scan when fewer than this many rules were loaded
+    #[arg(long, value_name = "N")]
+    min_rules: Option<usize>,
 
     /// Only rules with scores greater than this will be output
-    #[arg(long, default_value_t = 40)]
+    #[arg(long, default_value_t = scan::DEFAULT_MINIMUM_SCORE)]
     minscore: u32,
 
     /// Only files less than this size will be scanned
-    #[arg(long, default_value_t = 1073741824)]
+    #[arg(long, default_value_t = scan::DEFAULT_MAX_SIZE)]
     maxsize: u64,
+
+    /// Files smaller than this size will not be scanned
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    minsize: u64,
+
+    /// Maximum number of messages waiting to be output before scanning
+    /// threads block. Unbounded by default
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    output_buffer: Option<u64>,
+
+    /// Also scan the path of each file with the rules whose `target`
+    /// metadata is `path`, to find known bad file or folder names. These
+    /// rules are never reported for the content of the files
+    #[arg(long)]
+    scan_paths: bool,
+
+    /// Scan quickly for the files whose content matches any rule, then scan
+    /// only those again and report their matches with the `full` detail
+    /// level. Their decoded or decompressed content and their paths are only
+    /// scanned then
+    #[arg(long)]
+    rescan_matches: bool,
+
+    /// With --format ndjson, stream the results of each file in the order
+    /// the files are found rather than as soon as they are scanned, holding
+    /// back those of files scanned ahead of their turn
+    #[arg(long)]
+    ordered: bool,
+
+    /// Number of scanning threads, 0 uses all the available cores. Defaults
+    /// to the number of cores
+    #[arg(long, value_name = "N")]
+    threads: Option<u8>,
+
+    /// Scan all the folders at once, sharing the scanning threads, and
+    /// report their matches together
+    #[arg(long)]
+    parallel_folders: bool,
+
+    /// Label of the volume each folder comes from, given once per folder in
+    /// the same order. Reported paths are prefixed with it, like
+    /// `[sda1] /etc/passwd`
+    #[arg(long, value_name = "LABEL")]
+    volume_label: Vec<String>,
+
+    /// Size in bytes of the boot region scanned with --boot-sector
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 512,
+        requires = "boot_sector"
+    )]
+    boot_region_size: u64,
+
+    /// Scan only the parts of the --targets or --boot-sector ranges in the
+    /// blocks marked as changed in this bitmap, one bit per block
+    #[arg(long, value_name = "BITMAP_FILE")]
+    changed_blocks: Option<PathBuf>,
+
+    /// Size in bytes of the blocks of the --changed-blocks bitmap
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 4096,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "changed_blocks"
+    )]
+    block_size: u64,
+
+    /// Read the owners' user names from this passwd file instead of the
+    /// `etc/passwd` under each scanned folder
+    #[arg(long, value_name = "PATH")]
+    passwd_path: Option<PathBuf>,
+
+    /// Write how the run ended, with its exit code, error and file counts,
+    /// as JSON to this file, even if it fails early
+    #[arg(long, value_name = "PATH")]
+    status_file: Option<PathBuf>,
+
+    /// Print a summary of the scan as a last JSON object, with the numbers
+    /// of scanned, matching, too small, too big and failed files and the
+    /// duration
+    #[arg(long)]
+    summary: bool,
+
+    /// Write the summary of `--summary` to this file instead
+    #[arg(long, value_name = "PATH")]
+    summary_path: Option<PathBuf>,
+
+    /// Exit with 1 when a file matched a rule scoring at least the minimum
+    /// score, with 0 when none did, and with 2 on errors. Without it, the
+    /// runs ending without an error exit with 0, and errors with 1
+    #[arg(long)]
+    exit_code: bool,
+
+    /// Pause scanning while this file exists, and resume once it is removed
+    #[arg(long, value_name = "PATH")]
+    control_file: Option<PathBuf>,
+
+    /// Touch this file every --heartbeat-interval seconds while files keep
+    /// being scanned, so that a stuck scan is told by the file's stale
+    /// modification time
+    #[arg(long, value_name = "PATH")]
+    heartbeat_file: Option<PathBuf>,
+
+    /// Seconds between two updates of the --heartbeat-file
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "heartbeat_file"
+    )]
+    heartbeat_interval: u64,
+
+    /// Stop scanning after this many seconds, reporting the matches found
+    /// so far
+    #[arg(long, value_name = "SECONDS")]
+    max_duration: Option<u64>,
+
+    /// Retry scanning a file this many times when it couldn't be opened or
+    /// mapped, which may happen under memory pressure. Timeouts are not
+    /// retried. No retries by default
+    #[arg(long, value_name = "N")]
+    scan_retries: Option<u32>,
+
+    /// Use settings suited to high-latency mounts, like SFTP: retry failed
+    /// scans, give up on files taking too long and keep fewer files open.
+    /// Settings given explicitly are kept
+    #[arg(long)]
+    remote_mode: bool,
+
+    /// Abandon the scan of a file after this many seconds, logging a
+    /// warning and counting it as timed out. No timeout by default
+    #[arg(long = "timeout", value_name = "SECONDS", value_parser = parse_seconds)]
+    scan_timeout: Option<Duration>,
+
+    /// Only scan files with the setuid or setgid bit set
+    #[arg(long)]
+    setuid_only: bool,
+
+    /// Accept regular expressions with YARA's more permissive syntax, like
+    /// unknown escape sequences
+    #[arg(long)]
+    relaxed_re_syntax: bool,
+
+    /// Ignore imports of this module, skipping the rules that use it instead
+    /// of failing. Can be repeated
+    #[arg(long, value_name = "MODULE")]
+    ignore_module: Vec<String>,
+
+    /// Enable a YARA-X compiler feature by name. Can be repeated
+    #[arg(long, value_name = "FEATURE")]
+    enable_feature: Vec<String>,
+
+    /// Define a string external variable with the same value for every
+    /// scanned file, like `case_id=42`, for the rules to reference. Can be
+    /// given several times. The variables set for each file, like
+    /// `filename`, can't be redefined. The values are compiled into the
+    /// rules, so they can't be given with --load-rules
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_define)]
+    define: Vec<(String, String)>,
+
+    /// Match path globs regardless of case, including the `*.yar` and
+    /// `*.yara` globs finding the rule files. Useful for NTFS evidence
+    #[arg(long)]
+    glob_case_insensitive: bool,
+
+    /// Only scan the files matching this glob, like `**/*.exe`, matched
+    /// against their path relative to the scanned folder. Can be repeated
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Don't scan the files matching this glob, like `**/cache/**`, even if
+    /// included. Can be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Maximum number of directory levels walked below each scanned folder,
+    /// 0 only scans the files directly in it. Unlimited by default
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Follow symlinks while walking the scanned folders, scanning the files
+    /// they point to and walking into the directories. Symlinks looping back
+    /// to a parent are reported and not walked into. By default symlinks are
+    /// skipped and not counted as scanned
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Order of the files scanned in each folder: smallest first for quick
+    /// results, or largest first to start the heavy work early. Orders
+    /// other than dfs list every file before scanning the first one
+    #[arg(long, value_enum, default_value_t)]
+    scan_order: WalkOrder,
+
+    /// Don't scan files whose path resolves outside the scanned folder
+    /// through a symlink, like absolute links of a mounted image pointing
+    /// into the host
+    #[arg(long)]
+    contain_symlinks: bool,
+
+    /// Read files fully before scanning and flag those that return fewer
+    /// bytes than their reported size
+    #[arg(long)]
+    detect_truncated: bool,
+
+    /// Scan files sharing a device and inode (hardlinks) only once and
+    /// report their matches under every path
+    #[arg(long)]
+    dedupe_inodes: bool,
+
+    /// Capture messages logged by rules through the `console` module and
+    /// attach them to the file's matches
+    #[arg(long)]
+    rule_console: bool,
+
+    /// Only scan files whose modification time differs from the file at the
+    /// same relative path under this known-good tree, or that are missing
+    /// from it
+    #[arg(long)]
+    baseline_mtime_dir: Option<PathBuf>,
+
+    /// Maximum number of files open at the same time across all scanning
+    /// threads
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_open_files: Option<u64>,
+
+    /// Order in which matches are reported
+    #[arg(long, value_enum)]
+    sort: Option<SortOrder>,
+
+    /// Output format: `json` prints an array of all matches once the scan
+    /// is done, `ndjson` prints each match on its own line as soon as it is
+    /// found, without holding the matches in memory. `ndjson` can't be used
+    /// with --sort, --rules-fired-only or --embed-rules-info
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// With --format ndjson, send the matches this many at a time instead of
+    /// each as soon as it is found, trading latency for throughput. The
+    /// matches left are sent once the scan is done
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    jsonl_flush_every: u32,
+
+    /// Wrap the matches in an object along with the compiled rules, their
+    /// files and the compilation warnings and errors, as
+    /// `{"rules": {...}, "matches": [...]}`
+    #[arg(long)]
+    embed_rules_info: bool,
+
+    /// Group the matches by rule, as `[{"rule": ..., "tags": [...],
+    /// "matches": [{"path": ..., "hash": ..., "score": ...}]}]`, the rules
+    /// sorted by name. The tags are left empty with --detail none
+    #[arg(long, conflicts_with = "rules_fired_only")]
+    group_by_rule: bool,
+
+    /// Also report files with suspicious names, like overlong names, names
+    /// with a right-to-left override or `invoice.pdf.exe`, as matches of a
+    /// `filename-anomaly` signature
+    #[arg(long)]
+    detect_filename_anomalies: bool,
+
+    /// Also report empty files and files holding only zeros, as matches of
+    /// an `empty-file` signature, so that they can be triaged or excluded
+    #[arg(long)]
+    flag_empty: bool,
+
+    /// Report the matches of each file by decreasing `priority` metadata,
+    /// then by decreasing score. Rules without a priority have priority 0
+    #[arg(long)]
+    rule_priority: bool,
+
+    /// Look for base64 and hex encoded blobs in text files and scan their
+    /// decoded contents, reported as `<path>#decoded@<offset>`
+    #[arg(long)]
+    decode_embedded: bool,
+
+    /// Maximum number of encoded blobs decoded per file
+    #[arg(long, default_value_t = 16)]
+    decode_max_blobs: usize,
+
+    /// Maximum size of a decoded blob, longer blobs are cut
+    #[arg(long, default_value_t = 1048576)]
+    decode_max_bytes: usize,
+
+    /// Detect gzip, zstd, bzip2 and xz compressed files by their magic and
+    /// also scan their decompressed content
+    #[arg(long, value_enum)]
+    decompress: Option<DecompressMode>,
+
+    /// Maximum number of decompressed bytes scanned per file, summed over
+    /// all its nested layers
+    #[arg(long, default_value_t = 104857600)]
+    decompress_max_bytes: u64,
+
+    /// Maximum number of nested compressed layers decompressed per file,
+    /// like a gzip inside a gzip
+    #[arg(long, default_value_t = 1)]
+    max_archive_depth: usize,
+
+    /// Time how long each file takes to scan and report the slowest ones
+    /// when done
+    #[arg(long)]
+    profile: bool,
+
+    /// Number of slowest files reported by --profile
+    #[arg(long, default_value_t = 10)]
+    profile_top: usize,
+
+    /// Report the peak resident memory of the scan when done, and in the
+    /// summary. Only available on Linux
+    #[arg(long)]
+    profile_memory: bool,
+
+    /// Soft limit, in bytes, on file contents held in memory at once. Files
+    /// that don't fit in the remaining budget are scanned without buffering
+    #[arg(long, value_name = "BYTES")]
+    max_memory: Option<u64>,
+
+    /// Include the owner's UID, GID and resolved user and group names in
+    /// each match
+    #[arg(long)]
+    include_owner: bool,
+
+    /// Include the rule's `severity` metadata as a separate field in each
+    /// match, next to the score
+    #[arg(long)]
+    include_severity: bool,
+
+    /// Metadata compared to --minscore. Matches of rules without a
+    /// `severity` are compared by their score. Thresholding on the severity
+    /// implies --include-severity
+    #[arg(long, value_enum, default_value_t)]
+    threshold_on: ThresholdField,
+
+    /// How much detail to report for each match: `none` only identifies the
+    /// rule and file, `basic` adds all references, the rule's tags and the
+    /// number of its strings that matched, `strings` the offset and length
+    /// of each matched string and `full` the matched bytes and the rule's
+    /// namespace and metadata
+    #[arg(long, value_enum, default_value_t)]
+    detail: Detail,
+
+    /// Leave out the matched strings whatever the --detail level, to keep
+    /// the output small while reporting the rest of the `full` details
+    #[arg(long)]
+    no_strings: bool,
+
+    /// Drop matches of rules with this metadata, like
+    /// `status=experimental`. Can be given several times
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    exclude_meta: Vec<(String, String)>,
+
+    /// Ignore matches of rules without a `score` or `severity` metadata,
+    /// instead of scoring them 50
+    #[arg(long)]
+    require_score: bool,
+
+    /// Ignore matches of rules without this tag
+    #[arg(long, value_name = "TAG")]
+    require_tag: Option<String>,
+
+    /// JSON detection policy enabling and disabling rules by name or tag,
+    /// and overriding their scores. Its scores replace the rules' `score`
+    /// and `severity` before --require-score, --normalize-scores and
+    /// --minscore apply, and --exclude-meta and --require-tag still drop
+    /// the rules it enables
+    #[arg(long, value_name = "PATH")]
+    policy: Option<PathBuf>,
+
+    /// File of known-good path globs, one per line like `**/bin/nmap`,
+    /// matched against the absolute paths. Unlike files filtered out of the
+    /// walk, their files are still scanned and counted, but their matches
+    /// are not reported
+    #[arg(long, value_name = "PATH")]
+    allowlist_paths: Option<PathBuf>,
+
+    /// Browse the matches live in an interactive list, filtering them by
+    /// score and tag from the keyboard. The list is kept open once the scan
+    /// is done, until `q` is pressed. Needs a terminal, and --output for
+    /// the results
+    #[arg(long, requires = "output")]
+    tui: bool,
+
+    /// Keep the hashes of the files in this cache, loaded at startup and
+    /// saved once the scan is done, so that files with the same path, size
+    /// and modification time are not hashed again on the next runs. The
+    /// hashes not used by a run are dropped from the cache
+    #[arg(long, value_name = "PATH")]
+    hash_cache: Option<PathBuf>,
+
+    /// Clamp the scores of the rules to the 0-100 range before comparing
+    /// them to --minscore: lower scores become 0 and higher ones 100. Scores
+    /// are not rescaled from the range seen in the rules, as the score of a
+    /// match would then depend on which other rules are loaded
+    #[arg(long)]
+    normalize_scores: bool,
+
+    /// What to do with the matches of rules whose `context` metadata is
+    /// `yes`, `true` or `1`: score them 0, tag them `context` or report them
+    /// as usual
+    #[arg(long, value_enum, default_value_t)]
+    context_meta_action: ContextAction,
+
+    /// Only print the sorted list of the rules that matched any file,
+    /// without the matches themselves
+    #[arg(long)]
+    rules_fired_only: bool,
+
+    /// Include all the metadata of the matching rule in each match, whatever
+    /// the `--detail` level
+    #[arg(long)]
+    raw_metadata: bool,
+
+    /// Encoding of raw bytes, like matched data, in the output
+    #[arg(long, value_enum, default_value_t)]
+    byte_encoding: ByteEncoding,
+
+    /// Write the results to this file, creating its parent folders,
+    /// instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Write a JSON list of every file written during the scan, with its
+    /// type and size, to this path
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Write a JSON list of the files that were not scanned, with the reason
+    /// why, to this path
+    #[arg(long, value_name = "PATH")]
+    skips_output: Option<PathBuf>,
+
+    /// Write a JSON list of the filters each file went through, and which
+    /// one skipped it, to this path
+    #[arg(long, value_name = "PATH")]
+    trace_filters: Option<PathBuf>,
+
+    /// Also send each match to syslog, as an RFC 5424 message
+    #[arg(long)]
+    syslog: bool,
+
+    /// Send syslog messages over UDP to this server instead of the local
+    /// syslog daemon
+    #[arg(long, value_name = "HOST:PORT", requires = "syslog")]
+    syslog_server: Option<String>,
+
+    /// Syslog facility of the messages, like `user` or `local0`
+    #[arg(long, default_value = "user", value_parser = parse_facility, requires = "syslog")]
+    syslog_facility: Facility,
+
+    /// Syslog severity of the messages
+    #[arg(long, value_enum, default_value_t, requires = "syslog")]
+    syslog_severity: SyslogSeverity,
+
+    /// Also index the matches into the Elasticsearch cluster at this URL,
+    /// through its bulk API
+    #[cfg(feature = "elasticsearch")]
+    #[arg(long, value_name = "URL")]
+    elasticsearch: Option<String>,
+
+    /// Elasticsearch index the matches are added to
+    #[cfg(feature = "elasticsearch")]
+    #[arg(long, default_value = "fraken-x", requires = "elasticsearch")]
+    elasticsearch_index: String,
+
+    /// Authenticate to Elasticsearch with these basic credentials
+    #[cfg(feature = "elasticsearch")]
+    #[arg(long, value_name = "USER:PASSWORD", requires = "elasticsearch")]
+    elasticsearch_user: Option<String>,
+
+    /// Authenticate to Elasticsearch with this API key
+    #[cfg(feature = "elasticsearch")]
+    #[arg(
+        long,
+        value_name = "KEY",
+        requires = "elasticsearch",
+        conflicts_with = "elasticsearch_user"
+    )]
+    elasticsearch_api_key: Option<String>,
+
+    /// Retry a bulk request this many times when Elasticsearch can't be
+    /// reached or is overloaded
+    #[cfg(feature = "elasticsearch")]
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 3,
+        requires = "elasticsearch"
+    )]
+    elasticsearch_retries: u32,
+}
+
+impl Cli {
+    /// Number of retries of a failed scan with `--remote-mode`.
+    const REMOTE_SCAN_RETRIES: u32 = 5;
+    /// Scan timeout with `--remote-mode`.
+    const REMOTE_SCAN_TIMEOUT: Duration = Duration::from_secs(300);
+    /// Files open at the same time with `--remote-mode`.
+    const REMOTE_MAX_OPEN_FILES: u64 = 8;
+    /// Messages waiting to be output with `--remote-mode`.
+    const REMOTE_OUTPUT_BUFFER: u64 = 256;
+
+    /// Applies the `--remote-mode` preset to the settings left at their
+    /// defaults.
+    fn apply_remote_mode(&mut self) {
+        if !self.remote_mode {
+            return;
+        }
+        self.scan_retries.get_or_insert(Self::REMOTE_SCAN_RETRIES);
+        self.scan_timeout.get_or_insert(Self::REMOTE_SCAN_TIMEOUT);
+        self.max_open_files
+            .get_or_insert(Self::REMOTE_MAX_OPEN_FILES);
+        self.output_buffer.get_or_insert(Self::REMOTE_OUTPUT_BUFFER);
+    }
+}
+
+fn parse_key_value(pair: &str) -> Result<(String, String), String> {
+    pair.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", pair))
+}
+
+fn parse_define(pair: &str) -> Result<(String, String), String> {
+    let (key, value) = parse_key_value(pair)?;
+    rules::check_global_name(&key)?;
+    Ok((key, value))
+}
+
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    match seconds.parse::<u64>() {
+        Ok(0) => Err("must be at least 1 second".to_string()),
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_facility(facility: &str) -> Result<Facility, String> {
+    facility
+        .parse()
+        .map_err(|_| format!("unknown syslog facility `{}`", facility))
+}
+
+/// How much detail is reported for each match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Detail {
+    /// Path, hash, rule name, description, first reference and score.
+    None,
+    /// Also all of the rule's references, its tags and the number of its
+    /// strings that matched.
+    #[default]
+    Basic,
+    /// Also the identifier, offset and length of every matched string.
+    Strings,
+    /// Also the matched bytes and the rule's namespace and metadata.
+    Full,
+}
+
+/// The metadata of the rules compared to the minimum score.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum ThresholdField {
+    /// The `score`, or the `severity` of the rules without a score.
+    #[default]
+    Score,
+    /// The `severity`, reported separately from the score.
+    Severity,
+}
+
+/// What happens to the matches of rules with a true `context` metadata,
+/// which only add context to other matches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum ContextAction {
+    /// Score them 0, so that they fall below --minscore.
+    #[default]
+    Suppress,
+    /// Keep their score and add a `context` tag.
+    Tag,
+    /// Treat them like any other match.
+    Ignore,
+}
+
+/// How compressed files are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DecompressMode {
+    /// Decompress any file whose format is recognised.
+    Auto,
+}
+
+/// How the matches are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// A single JSON array once the scan is done.
+    #[default]
+    Json,
+    /// One JSON object per line, printed as soon as the file is scanned.
+    Ndjson,
+    /// CSV with the path, hash, signature, description, reference and score
+    /// of each match, once the scan is done.
+    Csv,
+    /// A Parquet file with the path, hash, signature, score and tags of
+    /// each match, written to --output once the scan is done.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Ordering applied to the matches before they are reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortOrder {
+    /// By image path, then signature.
+    Path,
+    /// Highest score first, ties broken by image path.
+    ScoreDesc,
+}
+
+impl SortOrder {
+    fn sort(&self, matches: &mut [MatchJson]) {
+        match self {
+            SortOrder::Path => matches.sort_by(|a, b| {
+                a.ImagePath
+                    .cmp(&b.ImagePath)
+                    .then_with(|| a.Signature.cmp(&b.Signature))
+            }),
+            SortOrder::ScoreDesc => matches.sort_by(|a, b| {
+                b.Score
+                    .cmp(&a.Score)
+                    .then_with(|| a.ImagePath.cmp(&b.ImagePath))
+            }),
+        }
+    }
 }
 
 #[derive(Args)]
@@ -54,107 +776,244 @@ struct TestOrScan {
     #[arg(short, long, group = "testorscan")]
     folder: Option<Vec<PathBuf>>,
 
+    /// Specify a single file to be scanned
+    #[arg(long, group = "testorscan", value_name = "PATH")]
+    file: Option<PathBuf>,
+
     /// Test the rules for syntax validity and then exit
     #[arg(long, group = "testorscan")]
     testrules: bool,
-}
 
-// Taken from yara-x/cli/src/commands/scan.rs
-struct ScanState {
-    num_scanned_files: AtomicUsize,
-    num_matching_files: AtomicUsize,
-    definitions: Vec<(Vec<u8>, String)>,
-    users: HashMap<u32, String>,
-}
+    /// Compare the rules against the rules under this path and print the
+    /// added, removed and changed rules as JSON, then exit
+    #[arg(long, group = "testorscan", value_name = "NEW_RULES")]
+    rules_diff: Option<PathBuf>,
 
-impl ScanState {
-    fn new(definitions: Vec<(Vec<u8>, String)>, users: HashMap<u32, String>) -> Self {
-        Self {
-            num_scanned_files: AtomicUsize::new(0),
-            num_matching_files: AtomicUsize::new(0),
-            definitions: definitions,
-            users: users,
-        }
-    }
+    /// Check that the rules compile and the magic file parses, then exit
+    /// with 0 if ready to scan or 1 otherwise
+    #[arg(long, group = "testorscan")]
+    healthcheck: bool,
+
+    /// Scan only the byte ranges listed in this file, one `path:offset:len`
+    /// per line
+    #[arg(long, group = "testorscan", value_name = "TARGETS_FILE")]
+    targets: Option<PathBuf>,
+
+    /// Scan only the boot sector at the start of this device or image, and
+    /// report the offsets of the matching strings
+    #[arg(long, group = "testorscan", value_name = "DEVICE_OR_IMAGE")]
+    boot_sector: Option<PathBuf>,
+
+    /// Merge the matches of these JSON reports from previous runs into a
+    /// single report, listing the reports each match was found in, then exit
+    #[arg(long, group = "testorscan", value_name = "REPORT", num_args = 1..)]
+    merge_reports: Option<Vec<PathBuf>>,
+
+    /// Scan this corpus and print, as JSON, the number of files each rule
+    /// matched whatever its score, to find the dead and overly broad rules,
+    /// then exit
+    #[arg(long, group = "testorscan", value_name = "CORPUS_DIR")]
+    coverage: Option<PathBuf>,
 }
 
-impl Component for ScanState {
-    fn draw_unchecked(
-        &self,
-        _: superconsole::Dimensions,
-        _mode: superconsole::DrawMode,
-    ) -> anyhow::Result<Lines> {
-        let lines = Lines::new();
-        // Supress std output.
-        Ok(lines)
+/// Hashes the scanned data, which is slow for large files, so it is done at
+/// most once per file.
+fn sha256(file: &ScannedFile<'_>) -> String {
+    match &file.extracted {
+        Some(extracted) => sha256::digest(extracted.data),
+        None => try_digest(file.path).unwrap_or_default(),
     }
 }
 
-pub trait OutputHandler: Sync {
-    /// Called for each scanned file.
-    fn on_file_scanned(
-        &self,
-        file_path: &Path,
-        scan_results: MatchingRules<'_, '_>,
-        output: &Sender<Message>,
-        minimum_score: u32,
-    );
-    /// Called when the last file has been scanned.
-    fn on_done(&self, _output: &Sender<Message>);
-}
+/// Hashes the data of a scanned file, like [`sha256`].
+type Digest = dyn Fn(&ScannedFile<'_>) -> String + Send + Sync;
 
+#[derive(Default)]
 pub struct JsonOutputHandler {
     output_buffer: std::sync::Arc<std::sync::Mutex<Vec<MatchJson>>>,
+    /// Paths that share their content with a scanned path, keyed by the
+    /// scanned path.
+    aliases: std::sync::Mutex<HashMap<String, Vec<String>>>,
+    /// Order applied to the matches in `on_done`, if any.
+    sort: Option<SortOrder>,
+    /// Whether the matches of each file are ordered by rule priority.
+    rule_priority: bool,
+    /// Whether the owner fields are filled in.
+    include_owner: bool,
+    /// Whether the `Severity` field is filled in.
+    include_severity: bool,
+    /// What is compared to the minimum score.
+    threshold_on: ThresholdField,
+    /// Where matches are also sent to, if set.
+    syslog: Option<std::sync::Arc<SyslogSink>>,
+    /// Where matches are also indexed to, if set.
+    #[cfg(feature = "elasticsearch")]
+    elasticsearch: Option<std::sync::Arc<BulkClient>>,
+    /// How much detail is reported for each match.
+    detail: Detail,
+    /// Whether the matched strings are left out whatever the `detail`.
+    no_strings: bool,
+    /// Whether the rule's metadata is reported below the `full` level.
+    raw_metadata: bool,
+    /// Rules with any of these metadata key/value pairs are not reported.
+    exclude_meta: Vec<(String, String)>,
+    /// Whether matches of rules without a score are left out.
+    require_score: bool,
+    /// Tag the rule must have for its matches to be reported, if set.
+    require_tag: Option<String>,
+    /// Rules reported and their scores, if set.
+    policy: Option<std::sync::Arc<Policy>>,
+    /// Paths whose matches are not reported, if set.
+    allowlist: Option<std::sync::Arc<PathAllowlist>>,
+    /// Hashes of the files kept across runs, if set.
+    hash_cache: Option<std::sync::Arc<HashCache>>,
+    /// Hashes the scanned data instead of [`sha256`], if set.
+    digest: Option<std::sync::Arc<Digest>>,
+    /// Whether scores are clamped to the 0-100 range.
+    normalize_scores: bool,
+    /// What happens to the matches of context rules.
+    context_action: ContextAction,
+    /// Whether only the names of the matching rules are reported.
+    rules_fired_only: bool,
+    /// Whether the matches are reported grouped by rule.
+    group_by_rule: bool,
+    /// Names of the rules that matched, with `rules_fired_only`.
+    rules_fired: std::sync::Mutex<HashSet<String>>,
+    /// Encoding of the raw bytes in the output.
+    byte_encoding: ByteEncoding,
+    /// The rule set used, reported along with the matches if set.
+    rules_info: Option<std::sync::Arc<serde_json::Value>>,
+    /// Where the results are written instead of stdout, if set.
+    output_file: Option<std::sync::Arc<OutputFile>>,
+    /// Where the matches are also listed live, with --tui.
+    browser: Option<std::sync::Arc<MatchBrowser>>,
+    /// Counters of the scan, counting the files with matches reported.
+    counters: std::sync::Arc<ScanCounters>,
 }
 
-#[derive(serde::Serialize, Clone)]
-#[allow(non_snake_case)]
-struct MatchJson {
-    ImagePath: String,
-    SHA256: String,
-    Signature: String,
-    Description: String,
-    Reference: String,
-    Score: i64,
-}
+impl JsonOutputHandler {
+    /// Hashes `file`, through the hash cache if set.
+    fn sha256(&self, file: &ScannedFile<'_>) -> String {
+        let digest = || match &self.digest {
+            Some(digest) => digest(file),
+            None => sha256(file),
+        };
+        match (&self.hash_cache, &file.extracted) {
+            (Some(hash_cache), None) => hash_cache.digest(file.path, digest),
+            _ => digest(),
+        }
+    }
 
-impl OutputHandler for JsonOutputHandler {
-    fn on_file_scanned(
+    /// Creates a match of `signature` in `file`, reported under `path`, with
+    /// the fields that don't depend on the rule filled in.
+    fn new_match(
         &self,
-        file_path: &Path,
-        scan_results: MatchingRules<'_, '_>,
-        _output: &Sender<Message>,
+        file: &ScannedFile<'_>,
+        path: &str,
+        hash: String,
+        signature: String,
+    ) -> MatchJson {
+        let mut output = MatchJson {
+            ImagePath: path.to_string(),
+            SHA256: hash,
+            Signature: signature,
+            Description: "".to_string(),
+            Reference: "".to_string(),
+            References: None,
+            Score: 50,
+            Severity: None,
+            Size: file.metadata.map_or(0, |metadata| metadata.len()),
+            Mtime: file.metadata.map_or(0, mtime_epoch),
+            Truncated: file.truncated,
+            Console: file.console.to_vec(),
+            OwnerUid: None,
+            OwnerGid: None,
+            OwnerName: None,
+            Group: None,
+            Namespace: None,
+            Tags: None,
+            Metadata: None,
+            MatchedStrings: None,
+            Strings: None,
+            volume_label: file.volume_label.map(str::to_string),
+        };
+        if self.include_owner {
+            output.OwnerUid = file.uid;
+            output.OwnerGid = file.gid;
+            // Empty when the UID couldn't be resolved to a name.
+            output.OwnerName = Some(file.owner.unwrap_or_default().to_string());
+            output.Group = Some(file.group.unwrap_or_default().to_string());
+        }
+        output
+    }
+
+    /// Builds the matches of `file` to report, leaving out those scoring
+    /// below `minimum_score`.
+    fn file_matches(
+        &self,
+        file: &ScannedFile<'_>,
+        scan_results: &[Rule<'_, '_>],
         minimum_score: u32,
-    ) {
-        let path = file_path
-            .canonicalize()
-            .ok()
-            .as_ref()
-            .and_then(|absolute| absolute.to_str())
-            .map(|s| s.to_string())
-            .unwrap_or_default();
+    ) -> Vec<MatchJson> {
+        let file_path = file.path;
+        let mut path = absolute_path(file_path);
+        if self.allowlisted(&path) {
+            return Vec::new();
+        }
+        if let Some(extracted) = &file.extracted {
+            path.push_str(&extracted.suffix);
+        }
+        let path = with_volume_label(file.volume_label, path);
+        let detail = if file.rescanned {
+            Detail::Full
+        } else {
+            self.detail
+        };
 
         let mut matches = Vec::new();
+        // Computed for the first match reported, shared by the others.
+        let mut hash: Option<String> = None;
 
-        for matching_rule in scan_results.into_iter() {
-            let hash = try_digest(file_path).unwrap_or("".to_string());
-            let mut output = MatchJson {
-                ImagePath: path.clone(),
-                SHA256: hash,
-                Signature: matching_rule.identifier().to_string(),
-                Description: "".to_string(),
-                Reference: "".to_string(),
-                Score: 50,
-            };
+        for matching_rule in scan_results {
+            let excluded = matching_rule.metadata().any(|(key, value)| {
+                self.exclude_meta
+                    .iter()
+                    .any(|(k, v)| k == key && meta_equals(&value, v))
+            });
+            if excluded {
+                continue;
+            }
+            let tags: Vec<String> = matching_rule
+                .tags()
+                .map(|tag| tag.identifier().to_string())
+                .collect();
+            if let Some(required) = &self.require_tag {
+                if !tags.contains(required) {
+                    continue;
+                }
+            }
+            let policy = self.policy.as_deref();
+            if policy.is_some_and(|policy| !policy.is_enabled(matching_rule.identifier(), &tags)) {
+                continue;
+            }
+            let mut output = self.new_match(
+                file,
+                &path,
+                String::new(),
+                matching_rule.identifier().to_string(),
+            );
             let metadata = matching_rule.metadata();
+            let mut score = policy.and_then(|policy| policy.score(matching_rule.identifier()));
+            let mut severity = None;
+            let mut priority = 0;
+            let mut is_context = false;
+            let mut references = Vec::new();
             for (key, value) in metadata {
-                if key == "score" || key == "severity" {
-                    // If it's not an Integer or String, ignore it.
-                    if let MetaValue::Integer(value) = value {
-                        output.Score = value;
-                    } else if let MetaValue::String(value) = value {
-                        output.Score = value.parse().unwrap_or(50);
-                    }
+                if key == "score" {
+                    score = score.or(meta_score(&value));
+                }
+                if key == "severity" {
+                    severity = severity.or(meta_score(&value));
                 }
                 if key.starts_with("desc") {
                     if let MetaValue::String(value) = value {
@@ -163,205 +1022,3206 @@ impl OutputHandler for JsonOutputHandler {
                 }
                 if key == "reference" || key.starts_with("report") {
                     if let MetaValue::String(value) = value {
-                        output.Reference = value.to_string();
+                        references.push(value.to_string());
+                    }
+                }
+                if key == "priority" {
+                    if let MetaValue::Integer(value) = value {
+                        priority = value;
                     }
                 }
                 if key == "context" {
                     if let MetaValue::String(value) = value {
                         if value == "yes" || value == "true" || value == "1" {
-                            output.Score = 0;
+                            is_context = true;
                         }
                     }
                 }
             }
-            if output.Score >= minimum_score.into() {
-                matches.push(output);
+            if let Some(reference) = references.first() {
+                output.Reference = reference.clone();
+            }
+            if detail >= Detail::Basic {
+                output.References = Some(references);
+                output.Tags = Some(tags);
+                output.MatchedStrings = Some(
+                    matching_rule
+                        .patterns()
+                        .filter(|pattern| pattern.matches().len() > 0)
+                        .count(),
+                );
+            }
+            if detail >= Detail::Strings && !self.no_strings {
+                output.Strings = Some(self.string_matches(matching_rule, detail));
+            }
+            if detail >= Detail::Full {
+                output.Namespace = Some(matching_rule.namespace().to_string());
+            }
+            if detail >= Detail::Full || self.raw_metadata {
+                output.Metadata = Some(
+                    matching_rule
+                        .metadata()
+                        .map(|(key, value)| (key.to_string(), self.meta_json(&value)))
+                        .collect(),
+                );
+            }
+            // `score` takes precedence over `severity`, whatever their order
+            // in the rule, and context rules don't count unless told so.
+            match score.or(severity) {
+                Some(value) => output.Score = value,
+                None if self.require_score => continue,
+                None => {}
+            }
+            if is_context {
+                match self.context_action {
+                    ContextAction::Suppress => output.Score = 0,
+                    ContextAction::Tag => output
+                        .Tags
+                        .get_or_insert_with(Vec::new)
+                        .push("context".to_string()),
+                    ContextAction::Ignore => {}
+                }
+            }
+            if self.normalize_scores {
+                output.Score = output.Score.clamp(0, 100);
+            }
+            if self.include_severity || self.threshold_on == ThresholdField::Severity {
+                output.Severity = severity;
+            }
+            let threshold = match self.threshold_on {
+                ThresholdField::Score => output.Score,
+                // Rules without a severity are compared by their score.
+                ThresholdField::Severity => match severity {
+                    Some(_) if is_context && self.context_action == ContextAction::Suppress => 0,
+                    Some(severity) if self.normalize_scores => severity.clamp(0, 100),
+                    Some(severity) => severity,
+                    None => output.Score,
+                },
+            };
+            if threshold >= minimum_score.into() {
+                output.SHA256 = hash.get_or_insert_with(|| self.sha256(file)).clone();
+                matches.push((priority, output));
             }
         }
-        let mut lock = self.output_buffer.lock().unwrap();
-        lock.extend(matches);
+        if self.rule_priority {
+            matches.sort_by(|(priority_a, a), (priority_b, b)| {
+                priority_b
+                    .cmp(priority_a)
+                    .then_with(|| b.Score.cmp(&a.Score))
+            });
+        }
+        let matches: Vec<_> = matches.into_iter().map(|(_, output)| output).collect();
+        self.reported(&matches);
+        matches
     }
 
-    fn on_done(&self, output: &Sender<Message>) {
-        let matches = {
-            let mut lock = self.output_buffer.lock().unwrap();
-            std::mem::take(&mut *lock)
-        };
-        if matches.len() == 0 {
-            println!("[]"); // Empty JSON.
-            return;
-        }
-        let rendered_json = serde_json::to_string(&matches).expect("Failed to render JSON");
-        let _ = output.send(Message::Info(rendered_json));
+    /// Returns whether the matches of `path`, an absolute path, are left out
+    /// by the allowlist.
+    fn allowlisted(&self, path: &str) -> bool {
+        self.allowlist
+            .as_ref()
+            .is_some_and(|allowlist| allowlist.contains(Path::new(path)))
     }
-}
-fn main() {
-    let cli = Cli::parse();
 
-    let mut compiler = yara_x::Compiler::new();
-    let mut definitions: Vec<(Vec<u8>, String)> = vec![];
-    let mut max_signature_len = 0;
+    /// Counts the file of `matches`, if any, as reported and lists them in
+    /// the browser, if set.
+    fn reported(&self, matches: &[MatchJson]) {
+        if !matches.is_empty() {
+            self.counters.reported.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(browser) = &self.browser {
+            browser.push(matches.iter().map(|m| tui::MatchRow {
+                path: m.ImagePath.clone(),
+                signature: m.Signature.clone(),
+                score: m.Score,
+                tags: m.Tags.clone().unwrap_or_default(),
+            }));
+        }
+    }
 
-    if cli.magic.is_some() {
-        eprintln!("[+] Testing existence of magic file");
+    /// Builds the match reporting the `anomalies` of the name of `file`,
+    /// unless its score is below `minimum_score`.
+    fn anomaly_match(
+        &self,
+        file: &ScannedFile<'_>,
+        anomalies: &[FilenameAnomaly],
+        minimum_score: u32,
+    ) -> Option<MatchJson> {
+        let path = absolute_path(file.path);
+        if FILENAME_ANOMALY_SCORE < minimum_score.into() || self.allowlisted(&path) {
+            return None;
+        }
+        let path = with_volume_label(file.volume_label, path);
+        let mut anomaly =
+            self.new_match(file, &path, self.sha256(file), FILENAME_ANOMALY.to_string());
+        anomaly.Description = anomalies
+            .iter()
+            .map(FilenameAnomaly::description)
+            .collect::<Vec<_>>()
+            .join(", ");
+        anomaly.Score = FILENAME_ANOMALY_SCORE;
+        if self.detail >= Detail::Basic {
+            anomaly.References = Some(Vec::new());
+            anomaly.Tags = Some(Vec::new());
+        }
+        self.reported(std::slice::from_ref(&anomaly));
+        Some(anomaly)
+    }
 
-        let magic_path = cli.rules.join(cli.magic.unwrap_or("".into()).clone());
-        if !magic_path.exists() || !magic_path.is_file() {
-            eprintln!("[-] Magic file specified but file not found.");
-        } else {
-            let magic_file = File::open(magic_path).expect("Failed to open magic file");
-            let reader = BufReader::new(magic_file);
-            (definitions, max_signature_len) =
-                magic::parse_definitions_file(reader).expect("Failed to parse magic file");
-            eprintln!("[+] {} magics parsed", definitions.len());
+    /// Builds the match reporting that `file` is empty, unless its score is
+    /// below `minimum_score`.
+    fn empty_file_match(
+        &self,
+        file: &ScannedFile<'_>,
+        kind: EmptyFile,
+        minimum_score: u32,
+    ) -> Option<MatchJson> {
+        let path = absolute_path(file.path);
+        if EMPTY_FILE_SCORE < minimum_score.into() || self.allowlisted(&path) {
+            return None;
         }
+        let path = with_volume_label(file.volume_label, path);
+        let mut empty = self.new_match(file, &path, self.sha256(file), EMPTY_FILE.to_string());
+        empty.Description = kind.description().to_string();
+        empty.Score = EMPTY_FILE_SCORE;
+        if self.detail >= Detail::Basic {
+            empty.References = Some(Vec::new());
+            empty.Tags = Some(Vec::new());
+        }
+        self.reported(std::slice::from_ref(&empty));
+        Some(empty)
     }
 
-    // External vars.
-    let vars = vec!["filepath", "filename", "filetype", "extension", "owner"];
-    for ident in vars {
-        let _ = compiler.define_global(ident, "");
+    /// Sends `matches` to syslog, if set, and keeps them for `on_done`.
+    fn report(&self, matches: impl IntoIterator<Item = MatchJson>, messages: &Sender<Message>) {
+        let matches: Vec<_> = matches.into_iter().collect();
+        self.send_to_syslog(&matches, messages);
+        if self.rules_fired_only {
+            let mut rules_fired = self.rules_fired.lock().unwrap();
+            rules_fired.extend(matches.into_iter().map(|m| m.Signature));
+            return;
+        }
+        let mut lock = self.output_buffer.lock().unwrap();
+        lock.extend(matches);
     }
 
-    // Scan the rules dir
-    let mut w = Walker::path(cli.rules.as_path());
-    w.filter("**/*.yar");
-    w.filter("**/*.yara");
-    if let Err(err) = w.walk(
-        |file_path| {
-            eprintln!("[-] Attempting to parse {}", file_path.display());
-            let src = fs::read(file_path)
-                .with_context(|| format!("can not read `{}`", file_path.display()))?;
+    /// Writes `line` of results to the output file if set, or sends it to
+    /// the console.
+    fn send_line(&self, line: String, messages: &Sender<Message>) {
+        match &self.output_file {
+            Some(file) => {
+                if let Err(err) = file.write_line(&line) {
+                    let _ = messages.send(Message::Error(format!("[-] Output error: {:#}", err)));
+                }
+            }
+            None => {
+                let _ = messages.send(Message::Info(line));
+            }
+        }
+    }
 
-            let src = SourceCode::from(src.as_slice())
-                .with_origin(file_path.as_os_str().to_str().unwrap());
-            let _ = compiler.add_source(src);
+    /// Sends `matches` to syslog, if set.
+    fn send_to_syslog(&self, matches: &[MatchJson], messages: &Sender<Message>) {
+        if let Some(syslog) = &self.syslog {
+            for output in matches {
+                let message = serde_json::to_string(output).expect("Failed to render JSON");
+                let params = [
+                    ("path", output.ImagePath.as_str()),
+                    ("rule", output.Signature.as_str()),
+                    ("score", &output.Score.to_string()),
+                    ("sha256", output.SHA256.as_str()),
+                ];
+                // Syslog is best effort, the match is still reported.
+                if let Err(err) = syslog.send(&params, &message) {
+                    let _ = messages.send(Message::Error(format!("[-] {}", err)));
+                }
+            }
+        }
+    }
 
-            Ok(())
-        },
-        Err,
-    ) {
-        eprintln!("Rules parsing error: {}", err);
-        process::exit(1);
+    /// Copies `matches` for each of the aliases of their path recorded by
+    /// `on_file_aliased`, except the allowlisted ones.
+    fn alias_matches(
+        &self,
+        matches: &[MatchJson],
+        aliases: &HashMap<String, Vec<String>>,
+    ) -> Vec<MatchJson> {
+        let mut aliased = Vec::new();
+        for m in matches {
+            let label = m.volume_label.as_deref();
+            let aliases = aliases.get(m.unlabeled_path()).into_iter().flatten();
+            for alias in aliases.filter(|alias| !self.allowlisted(alias)) {
+                aliased.push(MatchJson {
+                    ImagePath: with_volume_label(label, alias.clone()),
+                    ..m.clone()
+                });
+            }
+        }
+        aliased
     }
 
-    for error in compiler.errors() {
-        eprintln!("Rule error: {}", error);
+    /// Lists every match of the rule's strings, with the matched bytes at
+    /// the `full` detail level.
+    fn string_matches(&self, rule: &Rule<'_, '_>, detail: Detail) -> Vec<StringMatchJson> {
+        let mut strings = Vec::new();
+        for pattern in rule.patterns() {
+            for m in pattern.matches() {
+                strings.push(StringMatchJson {
+                    Identifier: pattern.identifier().to_string(),
+                    Offset: m.range().start,
+                    Length: m.range().len(),
+                    Data: (detail >= Detail::Full).then(|| self.byte_encoding.encode(m.data())),
+                });
+            }
+        }
+        strings
     }
 
-    /*for warning in compiler.warnings() {
-        eprintln!("{}", warning);
-    }*/
+    /// Converts a metadata value to JSON, encoding raw bytes with the
+    /// `--byte-encoding`.
+    fn meta_json(&self, value: &MetaValue<'_>) -> serde_json::Value {
+        match value {
+            MetaValue::Integer(value) => (*value).into(),
+            MetaValue::Float(value) => (*value).into(),
+            MetaValue::Bool(value) => (*value).into(),
+            MetaValue::String(value) => (*value).into(),
+            MetaValue::Bytes(value) => self.byte_encoding.encode(value).into(),
+        }
+    }
+}
 
-    eprintln!("[+] Building the rules");
-    // Obtain the compiled YARA rules.
-    let rules = compiler.build();
+/// Returns true if the metadata `value` equals `expected`, compared as text.
+fn meta_equals(value: &MetaValue<'_>, expected: &str) -> bool {
+    match value {
+        MetaValue::Integer(value) => value.to_string() == expected,
+        MetaValue::Float(value) => value.to_string() == expected,
+        MetaValue::Bool(value) => value.to_string() == expected,
+        MetaValue::String(value) => *value == expected,
+        MetaValue::Bytes(value) => *value == expected.as_bytes(),
+    }
+}
 
-    if cli.testorscan.testrules {
-        println!("[+] Rules are valid!");
-        process::exit(0);
+/// Reads a `score` or `severity` metadata value. Strings that aren't numbers
+/// count as 50, other types are ignored.
+fn meta_score(value: &MetaValue<'_>) -> Option<i64> {
+    match value {
+        MetaValue::Integer(value) => Some(*value),
+        MetaValue::String(value) => Some(value.parse().unwrap_or(50)),
+        _ => None,
     }
+}
 
-    eprintln!("[+] Scanning!");
-    let path_vec = cli.testorscan.folder.expect("Needs a path");
+/// Signature of the findings about suspicious file names.
+const FILENAME_ANOMALY: &str = "filename-anomaly";
 
-    for path in path_vec {
-        let joined_path = path.join("etc/passwd");
-        let full_folder_path = joined_path.to_str().unwrap_or("");
-        eprintln!("[+] Parsing /etc/passwd under {}", full_folder_path);
-        let users = userid::get_usernames_from_passwd(full_folder_path).unwrap_or(HashMap::new());
-        if users.len() == 0 {
-            eprintln!("[-] No users found in /etc/passwd");
-        } else {
-            eprintln!("[+] {} users found", users.len());
-        }
+/// Score of the findings about suspicious file names.
+const FILENAME_ANOMALY_SCORE: i64 = 60;
 
-        let state = ScanState::new(definitions.clone(), users);
+/// Signature of the findings about files without content.
+const EMPTY_FILE: &str = "empty-file";
 
-        let w = ParWalker::path(path.as_path());
-        let output_handler = JsonOutputHandler {
-            output_buffer: Default::default(),
-        };
-        w.walk(
-            state,
-            // Init.
-            |_, _output| {
-                let scanner = Scanner::new(&rules);
-                scanner
-            },
-            // File handler
-            |state, output, file_path, scanner| {
-                let metadata = fs::metadata(file_path.clone())?;
-                if metadata.len() > cli.maxsize {
-                    return Ok(());
+/// Score of the findings about files without content, reported at the
+/// default `--minscore`.
+const EMPTY_FILE_SCORE: i64 = 40;
+
+/// Returns the absolute path of `file_path` as a string, or an empty string
+/// if it can't be resolved.
+fn absolute_path(file_path: &Path) -> String {
+    file_path
+        .canonicalize()
+        .ok()
+        .as_ref()
+        .and_then(|absolute| absolute.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the modification time in `metadata` in seconds since the epoch,
+/// or 0 where the filesystem doesn't record it.
+fn mtime_epoch(metadata: &fs::Metadata) -> i64 {
+    match metadata.modified() {
+        Ok(modified) => match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        },
+        Err(_err) => {
+            #[cfg(feature = "logging")]
+            log::debug!("modification time not available: {}", _err);
+            0
+        }
+    }
+}
+
+/// Prefixes `path` with the volume label, if any.
+fn with_volume_label(volume_label: Option<&str>, path: String) -> String {
+    match volume_label {
+        Some(label) => format!("[{}] {}", label, path),
+        None => path,
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+#[allow(non_snake_case)]
+struct MatchJson {
+    ImagePath: String,
+    SHA256: String,
+    Signature: String,
+    Description: String,
+    /// The first of `References`, kept for existing consumers.
+    Reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    References: Option<Vec<String>>,
+    Score: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    Severity: Option<i64>,
+    /// Size of the file in bytes, 0 if unknown.
+    Size: u64,
+    /// Modification time of the file in seconds since the epoch, 0 if
+    /// unknown.
+    Mtime: i64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    Truncated: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    Console: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    OwnerUid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    OwnerGid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    OwnerName: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    Group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    Namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    Tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    Metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Number of the rule's strings with at least one match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    MatchedStrings: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    Strings: Option<Vec<StringMatchJson>>,
+    /// Label prefixed to `ImagePath`, also applied to its aliases.
+    #[serde(skip)]
+    volume_label: Option<String>,
+}
+
+/// The matches of a rule, reported with `--group-by-rule`.
+#[derive(serde::Serialize)]
+struct RuleMatchesJson {
+    rule: String,
+    tags: Vec<String>,
+    matches: Vec<RuleHitJson>,
+}
+
+/// A file matched by the rule of a [`RuleMatchesJson`].
+#[derive(serde::Serialize)]
+struct RuleHitJson {
+    path: String,
+    hash: String,
+    score: i64,
+}
+
+impl RuleMatchesJson {
+    /// Groups `matches` by rule, sorted by name, keeping the order of the
+    /// matches of each rule.
+    fn group(matches: &[MatchJson]) -> Vec<Self> {
+        let mut groups: BTreeMap<&str, Self> = BTreeMap::new();
+        for m in matches {
+            let group = groups.entry(&m.Signature).or_insert_with(|| Self {
+                rule: m.Signature.clone(),
+                tags: m.Tags.clone().unwrap_or_default(),
+                matches: Vec::new(),
+            });
+            group.matches.push(RuleHitJson {
+                path: m.ImagePath.clone(),
+                hash: m.SHA256.clone(),
+                score: m.Score,
+            });
+        }
+        groups.into_values().collect()
+    }
+}
+
+/// The matches as reported by [`JsonOutputHandler::on_done`].
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum MatchesJson<'a> {
+    Flat(&'a [MatchJson]),
+    ByRule(Vec<RuleMatchesJson>),
+}
+
+impl MatchJson {
+    /// The path of the matching file, without the volume label.
+    fn unlabeled_path(&self) -> &str {
+        match &self.volume_label {
+            Some(label) => self
+                .ImagePath
+                .strip_prefix(&format!("[{}] ", label))
+                .unwrap_or(&self.ImagePath),
+            None => &self.ImagePath,
+        }
+    }
+}
+
+/// A single match of one of the rule's strings.
+#[derive(serde::Serialize, Clone)]
+#[allow(non_snake_case)]
+struct StringMatchJson {
+    Identifier: String,
+    Offset: usize,
+    Length: usize,
+    /// The matched bytes, encoded with the `--byte-encoding`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    Data: Option<String>,
+}
+
+impl OutputHandler for JsonOutputHandler {
+    fn on_file_scanned(
+        &self,
+        file: &ScannedFile<'_>,
+        scan_results: &[Rule<'_, '_>],
+        messages: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        self.report(
+            self.file_matches(file, scan_results, minimum_score),
+            messages,
+        );
+    }
+
+    fn on_filename_anomaly(
+        &self,
+        file: &ScannedFile<'_>,
+        anomalies: &[FilenameAnomaly],
+        output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        if let Some(anomaly) = self.anomaly_match(file, anomalies, minimum_score) {
+            self.report([anomaly], output);
+        }
+    }
+
+    fn on_empty_file(
+        &self,
+        file: &ScannedFile<'_>,
+        kind: EmptyFile,
+        output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        if let Some(empty) = self.empty_file_match(file, kind, minimum_score) {
+            self.report([empty], output);
+        }
+    }
+
+    fn on_file_aliased(&self, alias: &Path, original: &Path, _output: &Sender<Message>) {
+        let mut aliases = self.aliases.lock().unwrap();
+        aliases
+            .entry(absolute_path(original))
+            .or_default()
+            .push(absolute_path(alias));
+    }
+
+    fn on_done(&self, output: &Sender<Message>) {
+        if self.rules_fired_only {
+            let mut rules_fired: Vec<_> = std::mem::take(&mut *self.rules_fired.lock().unwrap())
+                .into_iter()
+                .collect();
+            rules_fired.sort();
+            let rendered_json = serde_json::to_string(&rules_fired).expect("Failed to render JSON");
+            self.send_line(rendered_json, output);
+            return;
+        }
+        let mut matches = {
+            let mut lock = self.output_buffer.lock().unwrap();
+            std::mem::take(&mut *lock)
+        };
+        let aliases = std::mem::take(&mut *self.aliases.lock().unwrap());
+        if !aliases.is_empty() {
+            let aliased = self.alias_matches(&matches, &aliases);
+            matches.extend(aliased);
+        }
+        if let Some(sort) = self.sort {
+            sort.sort(&mut matches);
+        }
+        #[cfg(feature = "elasticsearch")]
+        if let Some(elasticsearch) = &self.elasticsearch {
+            // Like syslog, the matches are still reported on failure.
+            if let Err(err) = elasticsearch.index(&matches) {
+                let _ = output.send(Message::Error(format!("[-] Elasticsearch: {:#}", err)));
+            }
+        }
+        let report = if self.group_by_rule {
+            MatchesJson::ByRule(RuleMatchesJson::group(&matches))
+        } else {
+            MatchesJson::Flat(&matches)
+        };
+        if let Some(rules_info) = &self.rules_info {
+            let envelope = serde_json::json!({ "rules": rules_info, "matches": report });
+            self.send_line(envelope.to_string(), output);
+            return;
+        }
+        if matches.is_empty() {
+            self.send_line("[]".to_string(), output); // Empty JSON.
+            return;
+        }
+        let rendered_json = serde_json::to_string(&report).expect("Failed to render JSON");
+        self.send_line(rendered_json, output);
+    }
+}
+
+/// Reports each match as soon as its file is scanned, as one JSON object per
+/// line, instead of a single array once the scan is done.
+pub struct NdJsonOutputHandler {
+    /// Builds the matches and records the aliases, nothing is buffered in it.
+    json: JsonOutputHandler,
+    /// Matches reported so far, kept only when files can have aliases, which
+    /// are only known once every file is scanned.
+    reported: Option<std::sync::Mutex<Vec<MatchJson>>>,
+    /// Number of lines sent at once.
+    flush_every: usize,
+    /// Lines waiting for `flush_every` of them to be sent.
+    pending: std::sync::Mutex<Vec<String>>,
+}
+
+impl NdJsonOutputHandler {
+    /// Creates a handler building its matches like `json`, keeping them
+    /// for the aliases reported at the end if `keep_for_aliases`, and
+    /// sending them `flush_every` at a time.
+    fn new(json: JsonOutputHandler, keep_for_aliases: bool, flush_every: usize) -> Self {
+        Self {
+            json,
+            reported: keep_for_aliases.then(Default::default),
+            flush_every: flush_every.max(1),
+            pending: Default::default(),
+        }
+    }
+
+    /// Sends the lines of `matches` once `flush_every` of them are waiting,
+    /// or all of them if `flush`. Lines from different threads are never
+    /// interleaved.
+    fn send_lines(&self, lines: Vec<String>, flush: bool, messages: &Sender<Message>) {
+        let mut pending = self.pending.lock().unwrap();
+        for line in lines {
+            pending.push(line);
+            if pending.len() >= self.flush_every {
+                self.json
+                    .send_line(std::mem::take(&mut *pending).join("\n"), messages);
+            }
+        }
+        if flush && !pending.is_empty() {
+            self.json
+                .send_line(std::mem::take(&mut *pending).join("\n"), messages);
+        }
+    }
+
+    /// Sends each of `matches` as its own line.
+    fn emit(&self, matches: &[MatchJson], messages: &Sender<Message>) {
+        if matches.is_empty() {
+            return;
+        }
+        self.json.send_to_syslog(matches, messages);
+        #[cfg(feature = "elasticsearch")]
+        if let Some(elasticsearch) = &self.json.elasticsearch {
+            if let Err(err) = elasticsearch.index(matches) {
+                let _ = messages.send(Message::Error(format!("[-] Elasticsearch: {:#}", err)));
+            }
+        }
+        let lines = matches
+            .iter()
+            .map(|m| serde_json::to_string(m).expect("Failed to render JSON"))
+            .collect();
+        self.send_lines(lines, false, messages);
+    }
+
+    fn report(&self, matches: Vec<MatchJson>, messages: &Sender<Message>) {
+        self.emit(&matches, messages);
+        if let Some(reported) = &self.reported {
+            reported.lock().unwrap().extend(matches);
+        }
+    }
+}
+
+impl OutputHandler for NdJsonOutputHandler {
+    fn on_file_scanned(
+        &self,
+        file: &ScannedFile<'_>,
+        scan_results: &[Rule<'_, '_>],
+        messages: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        let matches = self.json.file_matches(file, scan_results, minimum_score);
+        self.report(matches, messages);
+    }
+
+    fn on_file_aliased(&self, alias: &Path, original: &Path, output: &Sender<Message>) {
+        self.json.on_file_aliased(alias, original, output);
+    }
+
+    fn on_filename_anomaly(
+        &self,
+        file: &ScannedFile<'_>,
+        anomalies: &[FilenameAnomaly],
+        output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        if let Some(anomaly) = self.json.anomaly_match(file, anomalies, minimum_score) {
+            self.report(vec![anomaly], output);
+        }
+    }
+
+    fn on_empty_file(
+        &self,
+        file: &ScannedFile<'_>,
+        kind: EmptyFile,
+        output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        if let Some(empty) = self.json.empty_file_match(file, kind, minimum_score) {
+            self.report(vec![empty], output);
+        }
+    }
+
+    fn on_done(&self, output: &Sender<Message>) {
+        let aliases = std::mem::take(&mut *self.json.aliases.lock().unwrap());
+        if let Some(reported) = &self.reported {
+            let reported = std::mem::take(&mut *reported.lock().unwrap());
+            let aliased = self.json.alias_matches(&reported, &aliases);
+            self.emit(&aliased, output);
+        }
+        self.send_lines(Vec::new(), true, output);
+    }
+}
+
+/// Writes the matches, with their aliases, once the scan is done.
+type WriteMatches = fn(&JsonOutputHandler, Vec<MatchJson>, &Sender<Message>);
+
+/// Keeps the matches until the scan is done, for formats written all at
+/// once like CSV and Parquet.
+pub struct BufferedOutputHandler {
+    /// Builds the matches, nothing is buffered in it.
+    json: JsonOutputHandler,
+    matches: std::sync::Mutex<Vec<MatchJson>>,
+    write: WriteMatches,
+}
+
+impl BufferedOutputHandler {
+    /// Creates a handler building its matches like `json`, and writing them
+    /// with `write`.
+    fn new(json: JsonOutputHandler, write: WriteMatches) -> Self {
+        Self {
+            json,
+            matches: Default::default(),
+            write,
+        }
+    }
+
+    fn report(&self, matches: impl IntoIterator<Item = MatchJson>) {
+        self.matches.lock().unwrap().extend(matches);
+    }
+}
+
+impl OutputHandler for BufferedOutputHandler {
+    fn on_file_scanned(
+        &self,
+        file: &ScannedFile<'_>,
+        scan_results: &[Rule<'_, '_>],
+        _output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        self.report(self.json.file_matches(file, scan_results, minimum_score));
+    }
+
+    fn on_file_aliased(&self, alias: &Path, original: &Path, output: &Sender<Message>) {
+        self.json.on_file_aliased(alias, original, output);
+    }
+
+    fn on_filename_anomaly(
+        &self,
+        file: &ScannedFile<'_>,
+        anomalies: &[FilenameAnomaly],
+        _output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        self.report(self.json.anomaly_match(file, anomalies, minimum_score));
+    }
+
+    fn on_empty_file(
+        &self,
+        file: &ScannedFile<'_>,
+        kind: EmptyFile,
+        _output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        self.report(self.json.empty_file_match(file, kind, minimum_score));
+    }
+
+    fn on_done(&self, output: &Sender<Message>) {
+        let mut matches = std::mem::take(&mut *self.matches.lock().unwrap());
+        let aliases = std::mem::take(&mut *self.json.aliases.lock().unwrap());
+        let aliased = self.json.alias_matches(&matches, &aliases);
+        matches.extend(aliased);
+        self.json.send_to_syslog(&matches, output);
+        #[cfg(feature = "elasticsearch")]
+        if let Some(elasticsearch) = &self.json.elasticsearch {
+            if let Err(err) = elasticsearch.index(&matches) {
+                let _ = output.send(Message::Error(format!("[-] Elasticsearch: {:#}", err)));
+            }
+        }
+        (self.write)(&self.json, matches, output);
+    }
+}
+
+/// Reports the matches as CSV, with a header row.
+fn write_csv(json: &JsonOutputHandler, matches: Vec<MatchJson>, output: &Sender<Message>) {
+    // A single message, so that the rows are never interleaved with other
+    // output.
+    let mut lines = vec![csv_file::row(csv_file::HEADER)];
+    lines.extend(matches.iter().map(|m| {
+        let score = m.Score.to_string();
+        csv_file::row([
+            m.ImagePath.as_str(),
+            &m.SHA256,
+            &m.Signature,
+            &m.Description,
+            &m.Reference,
+            &score,
+        ])
+    }));
+    json.send_line(lines.join("\n"), output);
+}
+
+/// Writes the matches to the output file of `json` as Parquet.
+#[cfg(feature = "parquet")]
+fn write_parquet(json: &JsonOutputHandler, matches: Vec<MatchJson>, output: &Sender<Message>) {
+    let rows: Vec<_> = matches
+        .into_iter()
+        .map(|m| MatchRow {
+            path: m.ImagePath,
+            sha256: m.SHA256,
+            signature: m.Signature,
+            score: m.Score,
+            tags: m.Tags.unwrap_or_default(),
+        })
+        .collect();
+    let output_file = json
+        .output_file
+        .as_ref()
+        .expect("Parquet needs an output file");
+    if let Err(err) = output_file.write_with(|file| parquet_file::write_matches(file, &rows)) {
+        let _ = output.send(Message::Error(format!("[-] Output error: {:#}", err)));
+    }
+}
+
+/// Builds the output handler for `format`, with the matches built like
+/// `json`. `dedupe_inodes` tells whether files can have aliases, and
+/// `flush_every` how many lines `ndjson` sends at once.
+fn output_handler(
+    format: OutputFormat,
+    json: JsonOutputHandler,
+    dedupe_inodes: bool,
+    flush_every: usize,
+) -> Box<dyn OutputHandler> {
+    match format {
+        OutputFormat::Json => Box::new(json),
+        OutputFormat::Ndjson => {
+            Box::new(NdJsonOutputHandler::new(json, dedupe_inodes, flush_every))
+        }
+        OutputFormat::Csv => Box::new(BufferedOutputHandler::new(json, write_csv)),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => {
+            assert!(json.output_file.is_some(), "Parquet needs an output file");
+            Box::new(BufferedOutputHandler::new(json, write_parquet))
+        }
+    }
+}
+/// Connects to syslog if `--syslog` is set. Syslog being unavailable only
+/// prints a warning, the scan goes on without it.
+fn connect_syslog(cli: &Cli) -> Option<SyslogSink> {
+    if !cli.syslog {
+        return None;
+    }
+    match SyslogSink::connect(
+        cli.syslog_server.as_deref(),
+        cli.syslog_facility,
+        cli.syslog_severity,
+    ) {
+        Ok(sink) => Some(sink),
+        Err(err) => {
+            eprintln!("[-] Warning: {}, matches won't be sent to syslog", err);
+            None
+        }
+    }
+}
+
+/// Creates the Elasticsearch client if `--elasticsearch` is set, exiting on
+/// invalid credentials.
+#[cfg(feature = "elasticsearch")]
+fn elasticsearch_client(cli: &Cli) -> Option<BulkClient> {
+    let url = cli.elasticsearch.as_deref()?;
+    let auth = match (&cli.elasticsearch_user, &cli.elasticsearch_api_key) {
+        (Some(credentials), _) => match elasticsearch::Auth::basic(credentials) {
+            Ok(auth) => Some(auth),
+            Err(err) => {
+                fail(format!("Elasticsearch error: {}", err));
+            }
+        },
+        (None, Some(key)) => Some(elasticsearch::Auth::ApiKey(key.clone())),
+        (None, None) => None,
+    };
+    let mut client = BulkClient::new(url, &cli.elasticsearch_index, auth);
+    client.retries(cli.elasticsearch_retries);
+    Some(client)
+}
+
+/// Scans the byte range of each target and passes the results to
+/// `handler`, under the file's path suffixed with the range. Targets that
+/// can't be read are reported and skipped. Returns the errors.
+fn scan_targets(
+    scanner: &mut Scanner<'_>,
+    targets: &[Target],
+    handler: &dyn OutputHandler,
+    output: &Sender<Message>,
+    minimum_score: u32,
+    counters: &ScanCounters,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut error = |message: String| {
+        let _ = output.send(Message::Error(message.clone()));
+        errors.push(message);
+    };
+    for target in targets {
+        let data = match target.read() {
+            Ok(data) => data,
+            Err(err) => {
+                error(format!(
+                    "[-] Can not read {}: {}",
+                    target.path.display(),
+                    err
+                ));
+                continue;
+            }
+        };
+        let results = match scanner.scan(&data) {
+            Ok(results) => results,
+            Err(err) => {
+                error(format!(
+                    "[-] Can not scan {}: {}",
+                    target.path.display(),
+                    err
+                ));
+                continue;
+            }
+        };
+        let matched = RuleTarget::Content.matching_rules(&results);
+        counters.count_file(!matched.is_empty());
+        let file = ScannedFile {
+            extracted: Some(Extracted {
+                suffix: format!("#range@{}+{}", target.offset, target.len),
+                data: &data,
+            }),
+            ..ScannedFile::new(&target.path)
+        };
+        handler.on_file_scanned(&file, &matched, output, minimum_score);
+    }
+    errors
+}
+
+/// Prints the rule compilation `warnings`, failing if there are any and
+/// `strict` is set.
+fn report_warnings(warnings: &[String], strict: bool) -> Result<(), String> {
+    for warning in warnings {
+        eprintln!("[!] {}", warning);
+    }
+    if strict && !warnings.is_empty() {
+        return Err(format!(
+            "{} compilation warning(s), treated as errors with --strict",
+            warnings.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that every folder to scan exists and is a directory.
+fn check_folders(folders: &[PathBuf]) -> anyhow::Result<()> {
+    for folder in folders {
+        match fs::metadata(folder) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => anyhow::bail!("`{}` is not a directory", folder.display()),
+            Err(err) => anyhow::bail!("can not access `{}`: {}", folder.display(), err),
+        }
+    }
+    Ok(())
+}
+
+/// Writes `manifest` to `path`, if set, exiting on failure.
+fn write_manifest(path: Option<&Path>, manifest: &Manifest) {
+    if let Some(path) = path {
+        if let Err(err) = manifest.write(path) {
+            fail(format!("Manifest error: {:#}", err));
+        }
+    }
+}
+
+/// Where the outcome of the run is recorded, set by `--status-file`.
+static STATUS_FILE: OnceLock<StatusFile> = OnceLock::new();
+
+/// Exit code of the runs ending with an error, 2 with `--exit-code` so that
+/// errors are told apart from matches.
+static ERROR_EXIT_CODE: AtomicI32 = AtomicI32::new(1);
+
+/// Records `error` as the one ending the run in the status file.
+fn record_error(error: &str) {
+    if let Some(status) = STATUS_FILE.get() {
+        status.record_error(error);
+    }
+}
+
+/// Writes the status file, if set, with `code`.
+fn finish_status(code: i32) {
+    if let Some(status) = STATUS_FILE.get() {
+        if let Err(err) = status.finish(code) {
+            eprintln!("Status file error: {:#}", err);
+        }
+    }
+}
+
+/// Writes the status file, then exits with `code`, out of raw mode.
+fn exit(code: i32) -> ! {
+    RawMode::restore();
+    finish_status(code);
+    process::exit(code);
+}
+
+/// Fails if some results could not be written to `output_file`, rather
+/// than finishing with incomplete results.
+fn check_output_file(output_file: Option<&OutputFile>) {
+    if let Some(output_file) = output_file.filter(|file| file.failed()) {
+        fail(format!(
+            "Output error: the results written to `{}` are incomplete",
+            output_file.path().display()
+        ));
+    }
+}
+
+/// Prints `message`, records it in the status file and exits with the
+/// error exit code.
+fn fail(message: String) -> ! {
+    RawMode::restore();
+    eprintln!("{}", message);
+    record_error(&message);
+    exit(ERROR_EXIT_CODE.load(Ordering::Relaxed));
+}
+
+fn main() {
+    let mut cli = Cli::parse();
+    cli.apply_remote_mode();
+    if cli.exit_code {
+        ERROR_EXIT_CODE.store(2, Ordering::Relaxed);
+    }
+    // Updated while scanning, and read by the status file when the run
+    // ends.
+    let counters = std::sync::Arc::new(ScanCounters::default());
+    if let Some(path) = &cli.status_file {
+        let _ = STATUS_FILE.set(StatusFile::new(path.clone(), counters.clone()));
+    }
+    let _status_guard = STATUS_FILE.get().map(StatusFile::guard);
+    // The files written by the run, listed in --manifest once it's done.
+    let manifest = Manifest::default();
+    if cli.format != OutputFormat::Json
+        && (cli.sort.is_some() || cli.rules_fired_only || cli.embed_rules_info || cli.group_by_rule)
+    {
+        fail(
+            "Output format error: --sort, --rules-fired-only, --embed-rules-info and --group-by-rule need --format json"
+                .to_string(),
+        );
+    }
+    #[cfg(feature = "parquet")]
+    if cli.format == OutputFormat::Parquet && cli.output.is_none() {
+        fail("Output format error: --format parquet needs --output".to_string());
+    }
+    if cli.jsonl_flush_every > 1 && cli.format != OutputFormat::Ndjson {
+        fail("Output format error: --jsonl-flush-every needs --format ndjson".to_string());
+    }
+    // The lines held back would be sent along with the results of another
+    // file.
+    if cli.jsonl_flush_every > 1 && cli.ordered {
+        fail("Output error: --jsonl-flush-every can't be used with --ordered".to_string());
+    }
+    // The json format reports all the matches at the end, in one array.
+    if cli.ordered && cli.format != OutputFormat::Ndjson {
+        fail("Output format error: --ordered needs --format ndjson".to_string());
+    }
+    if cli.ordered && cli.output.is_some() {
+        fail("Output error: --ordered only applies to results written to stdout".to_string());
+    }
+    let compiler_options = rules::CompilerOptions {
+        relaxed_re_syntax: cli.relaxed_re_syntax,
+        ignored_modules: cli.ignore_module.clone(),
+        features: cli.enable_feature.clone(),
+        case_insensitive_globs: cli.glob_case_insensitive,
+        globals: cli.define.clone(),
+    };
+
+    // Catch typos and missing mounts before spending time on the rules.
+    if let Some(folders) = &cli.testorscan.folder {
+        if let Err(err) = check_folders(folders) {
+            fail(format!("Scan folder error: {}", err));
+        }
+        if !cli.volume_label.is_empty() && cli.volume_label.len() != folders.len() {
+            fail(format!(
+                "Scan folder error: {} volume labels given for {} folders",
+                cli.volume_label.len(),
+                folders.len()
+            ));
+        }
+    }
+
+    if let Some(file) = &cli.testorscan.file {
+        match fs::metadata(file) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => {
+                fail(format!(
+                    "Scan file error: `{}` is not a file",
+                    file.display()
+                ));
+            }
+            Err(err) => {
+                fail(format!(
+                    "Scan file error: can not access `{}`: {}",
+                    file.display(),
+                    err
+                ));
+            }
+        }
+        if cli.volume_label.len() > 1 {
+            fail(format!(
+                "Scan file error: {} volume labels given for 1 file",
+                cli.volume_label.len()
+            ));
+        }
+    }
+
+    if let Some(reports) = &cli.testorscan.merge_reports {
+        match merge::merge_reports(reports) {
+            Ok(merged) => {
+                println!(
+                    "{}",
+                    serde_json::to_string(&merged).expect("Failed to render JSON")
+                );
+                exit(0);
+            }
+            Err(err) => {
+                fail(format!("Report merging error: {:#}", err));
+            }
+        }
+    }
+
+    let rules_path = match (&cli.rules, &cli.rules_from_git) {
+        (Some(rules), _) => Some(rules.clone()),
+        (None, Some(url)) => {
+            let cache_dir = cli
+                .rules_cache_dir
+                .clone()
+                .unwrap_or_else(|| std::env::temp_dir().join("fraken-x-rules"));
+            match git::fetch_rules(url, &cli.rules_git_ref, &cache_dir) {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    fail(format!("Rules fetching error: {:#}", err));
                 }
-                if let Some(username) = state.users.get(&metadata.uid()) {
-                    scanner.set_global("owner", username.clone())?;
+            }
+        }
+        // Only the builtin rules are used.
+        (None, None) => None,
+    };
+
+    if let Some(new_rules) = &cli.testorscan.rules_diff {
+        let rules_path = rules_path.as_deref().expect("clap requires a rules path");
+        eprintln!(
+            "[+] Comparing rules in {} with {}",
+            rules_path.display(),
+            new_rules.display()
+        );
+        match rules::diff_rules(rules_path, new_rules, &compiler_options) {
+            Ok(diff) => {
+                println!(
+                    "{}",
+                    serde_json::to_string(&diff).expect("Failed to render JSON")
+                );
+                exit(0);
+            }
+            Err(err) => {
+                fail(format!("Rules parsing error: {}", err));
+            }
+        }
+    }
+
+    if cli.testorscan.healthcheck {
+        let rules_path = rules_path.as_deref().expect("clap requires a rules path");
+        let magic_paths: Vec<_> = cli
+            .magic
+            .iter()
+            .map(|magic| rules_path.join(magic))
+            .collect();
+        match health::check(rules_path, &magic_paths, &compiler_options) {
+            Ok(report) => {
+                let magics = report
+                    .num_magics
+                    .map(|n| format!("{} magics", n))
+                    .unwrap_or("no magic file".to_string());
+                println!("[+] Ready: {} rules, {}", report.num_rules, magics);
+                exit(0);
+            }
+            Err(err) => {
+                println!("[-] Not ready: {}", err);
+                record_error(&format!("Not ready: {}", err));
+                exit(1);
+            }
+        }
+    }
+
+    let mut compiler = rules::new_compiler(&compiler_options)
+        .unwrap_or_else(|err| fail(format!("Rules parsing error: {}", err)));
+
+    // The magic files live under the rules path.
+    let magic_paths: Vec<_> = match &rules_path {
+        Some(rules_path) => cli
+            .magic
+            .iter()
+            .map(|magic| rules_path.join(magic))
+            .collect(),
+        None => Vec::new(),
+    };
+    let mut loaded_magic_paths = Vec::new();
+    let mut parsed_magics = Vec::new();
+    for magic_path in &magic_paths {
+        eprintln!(
+            "[+] Testing existence of magic file {}",
+            magic_path.display()
+        );
+
+        match magic::load_definitions_file(magic_path, cli.require_magic) {
+            Ok(Some(parsed)) => {
+                eprintln!("[+] {} magics parsed", parsed.0.len());
+                parsed_magics.push(parsed);
+                loaded_magic_paths.push(magic_path.clone());
+            }
+            Ok(None) => {}
+            Err(err) => {
+                fail(format!("Magic file error: {}", err));
+            }
+        }
+    }
+    if !parsed_magics.is_empty() {
+        let (definitions, _) = magic::merge_definitions(parsed_magics);
+        for (first, second) in magic::conflicting_definitions(&definitions) {
+            eprintln!(
+                "[-] Magic {:?} is also described as {:?}, which is ignored",
+                first.description, second.description
+            );
+        }
+    }
+
+    if cli.use_builtin_rules {
+        eprintln!("[+] Adding the builtin rules");
+        rules::add_builtin_rules(&mut compiler);
+    }
+
+    let max_dropped = cli.drop_broken_rules.unwrap_or(0);
+    // Compiled rules are loaded instead, the rules path only has magic files.
+    let loaded = rules_path
+        .as_deref()
+        .filter(|_| cli.load_rules.is_none())
+        .map(|rules_path| {
+            rules::add_rules_dropping_broken(
+                &mut compiler,
+                rules_path,
+                &compiler_options,
+                max_dropped,
+            )
+        })
+        .transpose();
+    let num_rule_files = match loaded {
+        Ok(Some((num_rule_files, dropped))) => {
+            if !dropped.is_empty() {
+                eprintln!(
+                    "[-] Dropped {} broken rule(s): {}",
+                    dropped.len(),
+                    dropped.join(", ")
+                );
+            }
+            Some(num_rule_files)
+        }
+        Ok(None) => None,
+        Err(err) => {
+            fail(format!("Rules parsing error: {}", err));
+        }
+    };
+
+    for error in compiler.errors() {
+        eprintln!("Rule error: {}", error);
+    }
+    // Kept for --embed-rules-info, building the rules consumes the compiler.
+    let compile_errors: Vec<_> = compiler.errors().iter().map(|e| e.to_string()).collect();
+    let compile_warnings: Vec<_> = compiler.warnings().iter().map(|w| w.to_string()).collect();
+
+    if let Err(err) = report_warnings(&compile_warnings, cli.strict) {
+        fail(format!("Rules parsing error: {}", err));
+    }
+
+    // Obtain the compiled YARA rules.
+    let rules = match &cli.load_rules {
+        Some(path) => {
+            eprintln!("[+] Loading the compiled rules from {}", path.display());
+            rules::load_rules(path)
+                .unwrap_or_else(|err| fail(format!("Rules loading error: {:#}", err)))
+        }
+        None => {
+            eprintln!("[+] Building the rules");
+            compiler.build()
+        }
+    };
+    if let Some(path) = &cli.save_rules {
+        if let Err(err) = rules::save_rules(&rules, path) {
+            fail(format!("Rules saving error: {:#}", err));
+        }
+        manifest.record(path, "rules");
+        eprintln!("[+] Compiled rules saved to {}", path.display());
+    }
+    let num_rules = rules.iter().len();
+    eprintln!("[+] {} rules loaded", num_rules);
+    if cli.scan_paths {
+        let num_path_rules = rules
+            .iter()
+            .filter(|rule| RuleTarget::of(rule) == RuleTarget::Path)
+            .count();
+        eprintln!("[+] {} path rules loaded", num_path_rules);
+    }
+    let rules_info = cli.embed_rules_info.then(|| {
+        let origins = match rules_path.as_deref() {
+            Some(rules_path) => {
+                rules::rule_origins(rules_path, &compiler_options).unwrap_or_else(|err| {
+                    eprintln!("[-] Rule origins error: {:#}", err);
+                    Default::default()
+                })
+            }
+            None => Default::default(),
+        };
+        let info = RulesInfo {
+            warnings: compile_warnings,
+            errors: compile_errors,
+            ..RulesInfo::new(&rules, &origins)
+        };
+        std::sync::Arc::new(serde_json::to_value(info).expect("Failed to render JSON"))
+    });
+
+    let policy = cli.policy.as_deref().map(|path| match Policy::read(path) {
+        Ok(policy) => {
+            for name in policy.unknown_rules(&rules) {
+                eprintln!("[-] Policy names an unknown rule: {}", name);
+            }
+            std::sync::Arc::new(policy)
+        }
+        Err(err) => fail(format!("Policy error: {:#}", err)),
+    });
+    let path_globs =
+        (!cli.include.is_empty() || !cli.exclude.is_empty()).then(|| match filter::PathGlobs::new(
+            &cli.include,
+            &cli.exclude,
+            cli.glob_case_insensitive,
+        ) {
+            Ok(path_globs) => path_globs,
+            Err(err) => fail(format!("Path filter error: {:#}", err)),
+        });
+    let allowlist = cli.allowlist_paths.as_deref().map(|path| {
+        match PathAllowlist::read(path, cli.glob_case_insensitive) {
+            Ok(allowlist) => std::sync::Arc::new(allowlist),
+            Err(err) => fail(format!("Allowlist error: {:#}", err)),
+        }
+    });
+    let hash_cache = cli
+        .hash_cache
+        .as_deref()
+        .map(|path| match HashCache::load(path) {
+            Ok(hash_cache) => std::sync::Arc::new(hash_cache),
+            Err(err) => fail(format!("Hash cache error: {:#}", err)),
+        });
+
+    if let (Some(rules_path), Some(num_rule_files)) = (&rules_path, num_rule_files) {
+        if let Some(warning) = rules::empty_rules_warning(rules_path, num_rule_files, num_rules) {
+            eprintln!("[-] Warning: {}", warning);
+        }
+    }
+    if let Some(min_rules) = cli.min_rules.filter(|min_rules| num_rules < *min_rules) {
+        fail(format!(
+            "Only {} rules loaded, at least {} required by --min-rules",
+            num_rules, min_rules
+        ));
+    }
+
+    if cli.testorscan.testrules {
+        println!("[+] Rules are valid!");
+        exit(0);
+    }
+
+    if let Some(corpus) = &cli.testorscan.coverage {
+        eprintln!("[+] Computing the rule coverage of {}", corpus.display());
+        let config = ScanConfig {
+            max_size: cli.maxsize,
+            magic: loaded_magic_paths.clone(),
+            ..ScanConfig::new(rules_path.clone().unwrap_or_default(), vec![corpus.clone()])
+        };
+        let coverage = RuleCoverage::new(&rules);
+        match scan::scan_with_rules(&rules, &config, &coverage) {
+            Ok(summary) => {
+                eprintln!("[+] {} files scanned", summary.scanned_files);
+                println!(
+                    "{}",
+                    serde_json::to_string(&coverage.into_counts()).expect("Failed to render JSON")
+                );
+                exit(0);
+            }
+            Err(err) => fail(format!("Coverage error: {:#}", err)),
+        }
+    }
+
+    let syslog = connect_syslog(&cli).map(std::sync::Arc::new);
+    #[cfg(feature = "elasticsearch")]
+    let elasticsearch = elasticsearch_client(&cli).map(std::sync::Arc::new);
+    let output_file = cli
+        .output
+        .as_deref()
+        .map(|path| match OutputFile::create(path) {
+            Ok(output_file) => {
+                manifest.record(path, "results");
+                std::sync::Arc::new(output_file)
+            }
+            Err(err) => fail(format!("Output error: {:#}", err)),
+        });
+
+    let targets = if let Some(targets_path) = &cli.testorscan.targets {
+        match File::open(targets_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| targets::parse_targets(BufReader::new(file)))
+        {
+            Ok(targets) => Some(targets),
+            Err(err) => {
+                fail(format!("Targets parsing error: {:#}", err));
+            }
+        }
+    } else {
+        cli.testorscan.boot_sector.as_ref().map(|device| {
+            vec![Target {
+                path: device.clone(),
+                offset: 0,
+                len: cli.boot_region_size,
+            }]
+        })
+    };
+
+    let targets = match (targets, &cli.changed_blocks) {
+        (Some(targets), Some(bitmap_path)) => {
+            match BlockBitmap::read(bitmap_path, cli.block_size) {
+                Ok(bitmap) => Some(bitmap.intersect(&targets)),
+                Err(err) => {
+                    fail(format!(
+                        "Changed blocks error: {}: {}",
+                        bitmap_path.display(),
+                        err
+                    ));
+                }
+            }
+        }
+        (None, Some(_)) => {
+            fail(
+                "Changed blocks error: --changed-blocks needs --targets or --boot-sector"
+                    .to_string(),
+            );
+        }
+        (targets, None) => targets,
+    };
+
+    let started = Instant::now();
+    let skip_log = cli
+        .skips_output
+        .is_some()
+        .then(|| std::sync::Arc::new(SkipLog::default()));
+    let filter_trace = cli
+        .trace_filters
+        .is_some()
+        .then(|| std::sync::Arc::new(FilterTrace::default()));
+    let heartbeat = cli.heartbeat_file.as_deref().map(|path| {
+        let heartbeat = Heartbeat::start(
+            path,
+            Duration::from_secs(cli.heartbeat_interval),
+            counters.clone(),
+        )
+        .unwrap_or_else(|err| fail(format!("Heartbeat file error: {:#}", err)));
+        manifest.record(path, "heartbeat");
+        heartbeat
+    });
+    let profile = cli
+        .profile
+        .then(|| std::sync::Arc::new(ScanProfile::new(cli.profile_top)));
+    let deadline = cli
+        .max_duration
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    // Matches are listed on the console while scanning, and the keys
+    // read from a thread of their own until the browser is closed.
+    let raw_mode = cli.tui.then(|| {
+        if !io::stdin().is_tty() || !io::stdout().is_tty() {
+            fail("TUI error: --tui needs a terminal".to_string());
+        }
+        RawMode::enable().unwrap_or_else(|err| fail(format!("TUI error: {}", err)))
+    });
+    let browser = raw_mode
+        .is_some()
+        .then(|| std::sync::Arc::new(MatchBrowser::default()));
+    let stop_keys = std::sync::Arc::new(AtomicBool::new(false));
+    let keys = browser.clone().map(|browser| {
+        let stop_keys = stop_keys.clone();
+        thread::spawn(move || {
+            // Raw mode turns Ctrl-C into a key instead of a signal.
+            if let Ok(KeysEnd::Interrupted) = browser.read_keys(&stop_keys) {
+                exit(130);
+            }
+        })
+    });
+
+    let json_handler = JsonOutputHandler {
+        sort: cli.sort,
+        rule_priority: cli.rule_priority,
+        include_owner: cli.include_owner,
+        include_severity: cli.include_severity,
+        threshold_on: cli.threshold_on,
+        syslog,
+        #[cfg(feature = "elasticsearch")]
+        elasticsearch,
+        detail: cli.detail,
+        no_strings: cli.no_strings,
+        raw_metadata: cli.raw_metadata,
+        exclude_meta: cli.exclude_meta.clone(),
+        rules_fired_only: cli.rules_fired_only,
+        group_by_rule: cli.group_by_rule,
+        require_score: cli.require_score,
+        require_tag: cli.require_tag.clone(),
+        policy: policy.clone(),
+        allowlist: allowlist.clone(),
+        hash_cache: hash_cache.clone(),
+        normalize_scores: cli.normalize_scores,
+        context_action: cli.context_meta_action,
+        byte_encoding: cli.byte_encoding,
+        rules_info: rules_info.clone(),
+        output_file: output_file.clone(),
+        browser: browser.clone(),
+        counters: counters.clone(),
+        ..Default::default()
+    };
+    let summary = match targets {
+        Some(targets) => {
+            eprintln!("[+] Scanning {} targets", targets.len());
+            // The offsets are what matter when hunting in the boot sector.
+            let detail = match &cli.testorscan.boot_sector {
+                Some(_) => cli.detail.max(Detail::Strings),
+                None => cli.detail,
+            };
+            let handler = JsonOutputHandler {
+                detail,
+                ..json_handler
+            };
+            let handler =
+                output_handler(cli.format, handler, false, cli.jsonl_flush_every as usize);
+            let (send, recv) = crossbeam::channel::unbounded();
+            let errors = scan_targets(
+                &mut Scanner::new(&rules),
+                &targets,
+                handler.as_ref(),
+                &send,
+                cli.minscore,
+                &counters,
+            );
+            handler.on_done(&send);
+            drop(send);
+            for message in recv {
+                match message {
+                    Message::Info(s) => println!("{}", s),
+                    Message::Error(s) => eprintln!("{}", s),
+                    Message::Abort => {}
+                }
+            }
+            counters.summary(rules.iter().len(), errors, started.elapsed())
+        }
+        None => {
+            eprintln!("[+] Scanning!");
+            let scan_file = cli.testorscan.file.is_some();
+            let folders = cli
+                .testorscan
+                .folder
+                .clone()
+                .or_else(|| cli.testorscan.file.clone().map(|file| vec![file]))
+                .expect("Needs a path");
+
+            let config = ScanConfig {
+                volume_labels: cli.volume_label.clone(),
+                single_file: scan_file,
+                passwd: cli.passwd_path.clone(),
+                parallel_folders: cli.parallel_folders,
+                minimum_score: cli.minscore,
+                max_size: cli.maxsize,
+                min_size: cli.minsize,
+                setuid_only: cli.setuid_only,
+                magic: loaded_magic_paths.clone(),
+                threads: cli.threads,
+                output_buffer: cli.output_buffer.map(|n| n as usize),
+                max_depth: cli.max_depth,
+                deadline,
+                ordered_output: cli.ordered,
+                follow_links: cli.follow_symlinks,
+                order: cli.scan_order,
+                path_globs: path_globs.map(std::sync::Arc::new),
+                contain_symlinks: cli.contain_symlinks,
+                baseline_mtime_dir: cli.baseline_mtime_dir.clone(),
+                dedupe_inodes: cli.dedupe_inodes,
+                max_open_files: cli.max_open_files.map(|n| n as usize),
+                max_memory: cli.max_memory,
+                detect_truncated: cli.detect_truncated,
+                scan_retries: cli.scan_retries.unwrap_or(0),
+                scan_timeout: cli.scan_timeout,
+                control_file: cli.control_file.clone(),
+                rule_console: cli.rule_console,
+                filename_anomalies: cli.detect_filename_anomalies,
+                flag_empty: cli.flag_empty,
+                decode_embedded: cli.decode_embedded.then_some(EmbeddedLimits {
+                    max_blobs: cli.decode_max_blobs,
+                    max_decoded_len: cli.decode_max_bytes,
+                    ..Default::default()
+                }),
+                decompress: cli.decompress.map(|_| DecompressLimits {
+                    max_depth: cli.max_archive_depth,
+                    max_bytes: cli.decompress_max_bytes,
+                }),
+                scan_paths: cli.scan_paths,
+                rescan_matches: cli.rescan_matches,
+                skip_log: skip_log.clone(),
+                filter_trace: filter_trace.clone(),
+                profile: profile.clone(),
+                view: browser.clone().map(|browser| {
+                    browser as std::sync::Arc<dyn superconsole::Component + Send + Sync>
+                }),
+                counters: counters.clone(),
+                output: WalkOutput::Console,
+                ..ScanConfig::new(rules_path.clone().unwrap_or_default(), folders)
+            };
+            let output_handler = output_handler(
+                cli.format,
+                json_handler,
+                cli.dedupe_inodes,
+                cli.jsonl_flush_every as usize,
+            );
+            scan::scan_with_rules(&rules, &config, output_handler.as_ref())
+                .unwrap_or_else(|err| fail(format!("Scan error: {:#}", err)))
+        }
+    };
+
+    // The scan is done, the heartbeat file goes stale from now on.
+    drop(heartbeat);
+
+    if let (Some(browser), Some(keys)) = (&browser, keys) {
+        // Kept open for browsing the matches until closed.
+        if let Some(mut console) = SuperConsole::new() {
+            while !browser.is_closed() && !keys.is_finished() {
+                let _ = console.render(browser.as_ref());
+                thread::sleep(Duration::from_millis(150));
+            }
+            let _ = console.finalize(browser.as_ref());
+        }
+        stop_keys.store(true, Ordering::Relaxed);
+        let _ = keys.join();
+    }
+    drop(raw_mode);
+
+    if let (Some(path), Some(hash_cache)) = (&cli.hash_cache, &hash_cache) {
+        match hash_cache.save(path) {
+            Ok(()) => manifest.record(path, "hash-cache"),
+            Err(err) => eprintln!("[-] Hash cache error: {:#}", err),
+        }
+    }
+
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        eprintln!("[-] Maximum scan duration reached, the scan stopped early");
+    }
+
+    let timed_out_files = counters.timed_out.load(Ordering::Relaxed);
+    if timed_out_files > 0 {
+        eprintln!(
+            "[-] {} file(s) timed out and were not fully scanned",
+            timed_out_files
+        );
+    }
+
+    if let Some(profile) = &profile {
+        eprintln!("[+] Slowest files to scan:");
+        for (elapsed, path) in profile.slowest() {
+            eprintln!(
+                "    {:>10.3} ms  {}",
+                elapsed.as_secs_f64() * 1000.0,
+                path.display()
+            );
+        }
+    }
+
+    let peak_rss = cli.profile_memory.then(profile::peak_rss).flatten();
+    if cli.profile_memory {
+        match peak_rss {
+            Some(peak) => eprintln!(
+                "[+] Peak resident memory: {:.1} MiB",
+                peak as f64 / (1024.0 * 1024.0)
+            ),
+            None => eprintln!("[-] Peak resident memory is not available on this system"),
+        }
+    }
+
+    if let (Some(path), Some(skip_log)) = (&cli.skips_output, &skip_log) {
+        if let Err(err) = skip_log.write(path) {
+            fail(format!("Skips output error: {:#}", err));
+        }
+        manifest.record(path, "skips");
+    }
+    if let (Some(path), Some(filter_trace)) = (&cli.trace_filters, &filter_trace) {
+        if let Err(err) = filter_trace.write(path) {
+            fail(format!("Filter trace error: {:#}", err));
+        }
+        manifest.record(path, "filter-trace");
+    }
+
+    if cli.summary || cli.summary_path.is_some() {
+        // The files matching count as reported, like for --exit-code.
+        let summary = ScanSummary {
+            matching_files: counters.reported.load(Ordering::Relaxed),
+            duration: started.elapsed(),
+            peak_rss,
+            ..summary
+        };
+        let json = serde_json::to_string(&summary).expect("Failed to render JSON");
+        match &cli.summary_path {
+            Some(path) => {
+                if let Err(err) = fs::write(path, json + "\n") {
+                    fail(format!("Summary error: {:#}", err));
                 }
+                manifest.record(path, "summary");
+            }
+            None => println!("{}", json),
+        }
+    }
+
+    check_output_file(output_file.as_deref());
+
+    // The status is written before the manifest, which lists it.
+    let code = i32::from(cli.exit_code && counters.reported.load(Ordering::Relaxed) > 0);
+    if let Some(path) = &cli.status_file {
+        finish_status(code);
+        manifest.record(path, "status");
+    }
+    write_manifest(cli.manifest.as_deref(), &manifest);
+    if code != 0 {
+        exit(code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::atomic::AtomicUsize;
+
+    use fraken_x::{anomaly, empty};
+
+    use super::*;
+
+    const TEST_RULE: &str = r#"
+rule TestRule {
+    meta:
+        score = 60
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#;
+
+    /// Scans `file` with `rules` and passes the results to `handler`.
+    fn scan_into(
+        handler: &dyn OutputHandler,
+        rules: &yara_x::Rules,
+        file: &ScannedFile<'_>,
+        output: &Sender<Message>,
+    ) {
+        let mut scanner = Scanner::new(rules);
+        let results = scanner.scan_file(file.path).unwrap();
+        let matched: Vec<_> = results.matching_rules().collect();
+        handler.on_file_scanned(file, &matched, output, 40);
+    }
+
+    /// Writes `content` to a file named `name` in a directory of its own,
+    /// removed once the returned guard is dropped.
+    fn sample(name: &str, content: &[u8]) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        (dir, path)
+    }
+
+    /// Scans the file at `path` with `rules` into `handler`, returning the
+    /// matches reported.
+    fn scan_matches(
+        handler: &dyn OutputHandler,
+        rules: &yara_x::Rules,
+        path: &Path,
+    ) -> Vec<serde_json::Value> {
+        let (send, _recv) = crossbeam::channel::unbounded();
+        scan_into(handler, rules, &ScannedFile::new(path), &send);
+        render(handler)
+    }
+
+    /// Runs `on_done` and parses the JSON sent through the output channel.
+    fn render(handler: &dyn OutputHandler) -> Vec<serde_json::Value> {
+        let (send, recv) = crossbeam::channel::unbounded();
+        handler.on_done(&send);
+        drop(send);
+        match recv.iter().next() {
+            Some(Message::Info(json)) => serde_json::from_str(&json).unwrap(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_rule_console_reported() {
+        let (_dir, path) = sample("sample.bin", b"xx EVIL xx");
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let console = ["found evil at 3".to_string()];
+        let file = ScannedFile {
+            console: &console,
+            ..ScannedFile::new(&path)
+        };
+        scan_into(&handler, &rules, &file, &send);
+
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            matches[0]["Console"],
+            serde_json::json!(["found evil at 3"])
+        );
+    }
+
+    #[test]
+    fn test_sort_score_desc() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = yara_x::compile(
+            r#"
+rule Low { meta: score = 45 strings: $a = "LOW" condition: $a }
+rule High { meta: score = 90 strings: $a = "HIGH" condition: $a }
+rule Mid { meta: score = "70" strings: $a = "MID" condition: $a }
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            sort: Some(SortOrder::ScoreDesc),
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+
+        for (name, content) in [("a", "LOW"), ("b", "HIGH MID"), ("c", "LOW HIGH")] {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            let file = ScannedFile::new(&path);
+            scan_into(&handler, &rules, &file, &send);
+        }
+
+        let order: Vec<(i64, String)> = render(&handler)
+            .iter()
+            .map(|m| {
+                let path = m["ImagePath"].as_str().unwrap();
+                let name = Path::new(path).file_name().unwrap().to_str().unwrap();
+                (m["Score"].as_i64().unwrap(), name.to_string())
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (90, "b".to_string()),
+                (90, "c".to_string()),
+                (70, "b".to_string()),
+                (45, "a".to_string()),
+                (45, "c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include_owner() {
+        let (_dir, path) = sample("owned.bin", b"EVIL");
+        let metadata = fs::metadata(&path).unwrap();
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler {
+            include_owner: true,
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let file = ScannedFile {
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+            owner: Some("analyst"),
+            group: Some("staff"),
+            ..ScannedFile::new(&path)
+        };
+        scan_into(&handler, &rules, &file, &send);
 
-                scanner.set_global("filepath", file_path.to_str().unwrap())?;
-                scanner.set_global("filename", file_path.file_name().unwrap().to_str().unwrap())?;
-                scanner.set_global(
-                    "extension",
-                    file_path
-                        .extension()
-                        .map(|name| name.to_string_lossy().into_owned())
-                        .unwrap_or("".to_string()),
-                )?;
-
-                // Magics
-                let target_bytes =
-                // Anyhow
-                    magic::read_first_bytes(file_path.to_str().unwrap_or(""), max_signature_len).unwrap_or(vec![]);
-                if target_bytes.len() > 0 {
-                    for (hex_bytes, description) in &state.definitions {
-                        if target_bytes.starts_with(&hex_bytes) {
-                            scanner.set_global("filetype", description.clone())?;
-                            break;
-                        }
-                    }
-                }
+        let matches = render(&handler);
+        assert_eq!(matches[0]["OwnerUid"], metadata.uid());
+        assert_eq!(matches[0]["OwnerGid"], metadata.gid());
+        assert_eq!(matches[0]["OwnerName"], "analyst");
+        assert_eq!(matches[0]["Group"], "staff");
+    }
 
-                let scan_results = scanner.scan_file(file_path.as_path());
-                let scan_results = scan_results?;
-                let matched_count = scan_results.matching_rules().len();
-                let matched = scan_results.matching_rules();
+    #[test]
+    fn test_owner_omitted_by_default() {
+        let (_dir, path) = sample("owned.bin", b"EVIL");
 
-                output_handler.on_file_scanned(file_path.as_path(), matched, output, cli.minscore);
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let file = ScannedFile {
+            uid: Some(1000),
+            ..ScannedFile::new(&path)
+        };
+        scan_into(&handler, &rules, &file, &send);
 
-                state.num_scanned_files.fetch_add(1, Ordering::Relaxed);
-                if matched_count > 0 {
-                    state.num_matching_files.fetch_add(1, Ordering::Relaxed);
-                }
+        let matches = render(&handler);
+        assert!(matches[0].get("OwnerUid").is_none());
+        assert!(matches[0].get("OwnerName").is_none());
+    }
+
+    #[test]
+    fn test_multiple_references() {
+        let (_dir, path) = sample("sample.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule Referenced {
+    meta:
+        reference = "https://example.com/advisory"
+        report_url = "https://example.com/report"
+        reference_count = 2
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler::default();
+        let matches = scan_matches(&handler, &rules, &path);
+        assert_eq!(
+            matches[0]["References"],
+            serde_json::json!(["https://example.com/advisory", "https://example.com/report"])
+        );
+        assert_eq!(matches[0]["Reference"], "https://example.com/advisory");
+    }
+
+    #[test]
+    fn test_score_takes_precedence_over_severity() {
+        let (_dir, path) = sample("scored.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule SeverityFirst {
+    meta:
+        severity = 90
+        score = 45
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+rule ScoreFirst {
+    meta:
+        score = 45
+        severity = 90
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            include_severity: true,
+            ..Default::default()
+        };
+        let matches = scan_matches(&handler, &rules, &path);
+        assert_eq!(matches.len(), 2);
+        for m in &matches {
+            assert_eq!(m["Score"], 45);
+            assert_eq!(m["Severity"], 90);
+        }
+    }
+
+    #[test]
+    fn test_threshold_on() {
+        let (_dir, path) = sample("scored.bin", b"EVIL");
+        let rules = yara_x::compile(
+            r#"
+rule Severe { meta: score = 30 severity = 90 strings: $a = "EVIL" condition: $a }
+rule Scored { meta: score = 80 severity = 20 strings: $a = "EVIL" condition: $a }
+rule ScoreOnly { meta: score = 60 strings: $a = "EVIL" condition: $a }
+"#,
+        )
+        .unwrap();
+        let reported = |threshold_on| {
+            let handler = JsonOutputHandler {
+                threshold_on,
+                ..Default::default()
+            };
+            scan_matches(&handler, &rules, &path)
+        };
+
+        let matches = reported(ThresholdField::Score);
+        let signatures: Vec<_> = matches.iter().map(|m| &m["Signature"]).collect();
+        assert_eq!(signatures, ["Scored", "ScoreOnly"]);
+        assert!(matches[0].get("Severity").is_none());
+
+        // Both are reported, the severity deciding.
+        let matches = reported(ThresholdField::Severity);
+        let fields: Vec<_> = matches
+            .iter()
+            .map(|m| {
+                (
+                    m["Signature"].clone(),
+                    m["Score"].clone(),
+                    m.get("Severity").cloned(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("Severe".into(), 30.into(), Some(90.into())),
+                ("ScoreOnly".into(), 60.into(), None),
+            ]
+        );
+    }
 
-                // Reset globals
-                scanner.set_global("owner", "")?;
-                scanner.set_global("filepath", "")?;
-                scanner.set_global("filename", "")?;
-                scanner.set_global("extension", "")?;
-                scanner.set_global("filetype", "")?;
+    #[test]
+    fn test_match_sent_to_syslog() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let address = server.local_addr().unwrap().to_string();
 
-                Ok(())
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let sink = SyslogSink::connect(Some(&address), Facility::LOG_USER, SyslogSeverity::Warning)
+            .unwrap();
+        let handler = JsonOutputHandler {
+            syslog: Some(std::sync::Arc::new(sink)),
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+        scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+
+        let mut buf = [0; 4096];
+        let len = server.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]);
+        assert!(message.contains(r#"rule="TestRule""#), "{}", message);
+        assert!(message.contains(r#"score="60""#));
+        assert!(message.contains(r#""ImagePath":"#));
+
+        // The match is still part of the normal output.
+        assert_eq!(render(&handler).len(), 1);
+    }
+
+    #[test]
+    fn test_scan_targets_only_scans_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.bin");
+        let mut content = vec![0u8; 4096];
+        content[1000..1004].copy_from_slice(b"EVIL");
+        fs::write(&path, &content).unwrap();
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let targets = [
+            Target {
+                path: path.clone(),
+                offset: 512,
+                len: 1024,
+            },
+            Target {
+                path: path.clone(),
+                offset: 2048,
+                len: 2048,
             },
-            // Finalisation
-            |_, _| {},
-            // Walk done.
-            |output| output_handler.on_done(output),
-            // Error handler
-            |err, _| {
-                let error = err.to_string();
-                let root_cause = err.root_cause().to_string();
-                let msg = if error != root_cause {
-                    format!("{} {}: {}", "error: ".paint(Red).bold(), error, root_cause,)
-                } else {
-                    format!("{}: {}", "error: ".paint(Red).bold(), error)
+        ];
+
+        let counters = ScanCounters::default();
+        let errors = scan_targets(
+            &mut Scanner::new(&rules),
+            &targets,
+            &handler,
+            &send,
+            40,
+            &counters,
+        );
+
+        assert!(errors.is_empty());
+        assert_eq!(counters.matched.load(Ordering::Relaxed), 1);
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        let image_path = matches[0]["ImagePath"].as_str().unwrap();
+        assert!(image_path.ends_with("image.bin#range@512+1024"));
+    }
+
+    #[test]
+    fn test_detail_levels() {
+        let (_dir, path) = sample("evil.bin", b"--EVIL--");
+
+        let rules = yara_x::compile(
+            r#"
+rule Detailed : tag1 {
+    meta:
+        score = 60
+        reference = "https://example.com/evil"
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#,
+        )
+        .unwrap();
+        let fields = |detail| {
+            let handler = JsonOutputHandler {
+                detail,
+                ..Default::default()
+            };
+            let matches = scan_matches(&handler, &rules, &path);
+            let mut keys: Vec<String> = matches[0].as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            (keys, matches[0].clone())
+        };
+        let base = [
+            "Description",
+            "ImagePath",
+            "Mtime",
+            "Reference",
+            "SHA256",
+            "Score",
+            "Signature",
+            "Size",
+        ];
+        let with = |extra: &[&str]| {
+            let mut keys: Vec<String> = base.iter().chain(extra).map(|k| k.to_string()).collect();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(fields(Detail::None).0, with(&[]));
+        assert_eq!(
+            fields(Detail::Basic).0,
+            with(&["MatchedStrings", "References", "Tags"])
+        );
+
+        let (keys, strings) = fields(Detail::Strings);
+        assert_eq!(
+            keys,
+            with(&["MatchedStrings", "References", "Strings", "Tags"])
+        );
+        assert_eq!(
+            strings["Strings"],
+            serde_json::json!([{"Identifier": "$a", "Offset": 2, "Length": 4}])
+        );
+
+        let (keys, full) = fields(Detail::Full);
+        assert_eq!(
+            keys,
+            with(&[
+                "MatchedStrings",
+                "Metadata",
+                "Namespace",
+                "References",
+                "Strings",
+                "Tags"
+            ])
+        );
+        assert_eq!(full["Strings"][0]["Data"], "4556494c");
+        assert_eq!(full["Namespace"], "default");
+        assert_eq!(full["Tags"], serde_json::json!(["tag1"]));
+        assert_eq!(full["Metadata"]["score"], 60);
+    }
+
+    #[test]
+    fn test_check_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.bin");
+        fs::write(&file, b"data").unwrap();
+        let missing = dir.path().join("typo");
+
+        assert!(check_folders(&[dir.path().to_path_buf()]).is_ok());
+
+        let err = check_folders(&[dir.path().to_path_buf(), missing.clone()]).unwrap_err();
+        assert!(err.to_string().contains(missing.to_str().unwrap()));
+
+        let err = check_folders(&[file]).unwrap_err();
+        assert!(err.to_string().contains("is not a directory"));
+    }
+
+    #[test]
+    fn test_raw_metadata() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule Rich {
+    meta:
+        score = 60
+        author = "analyst"
+        confidence = 0.75
+        tlp_clear = true
+        x_custom = "nonstandard key"
+        raw = "\x00\xff"
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            raw_metadata: true,
+            ..Default::default()
+        };
+        let matches = scan_matches(&handler, &rules, &path);
+        assert_eq!(
+            matches[0]["Metadata"],
+            serde_json::json!({
+                "score": 60,
+                "author": "analyst",
+                "confidence": 0.75,
+                "tlp_clear": true,
+                "x_custom": "nonstandard key",
+                "raw": "00ff",
+            })
+        );
+        // Only the metadata is added, not the rest of the `full` details.
+        assert!(matches[0].get("Strings").is_none());
+    }
+
+    #[test]
+    fn test_exclude_meta() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule Experimental {
+    meta:
+        score = 60
+        status = "experimental"
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+rule Stable {
+    meta:
+        score = 60
+        status = "stable"
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            exclude_meta: vec![parse_key_value("status=experimental").unwrap()],
+            ..Default::default()
+        };
+        let matches = scan_matches(&handler, &rules, &path);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["Signature"], "Stable");
+    }
+
+    #[test]
+    fn test_volume_label_prefixes_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("evil.bin");
+        let link = dir.path().join("link.bin");
+        fs::write(&original, b"xx EVIL xx").unwrap();
+        fs::hard_link(&original, &link).unwrap();
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let file = ScannedFile {
+            volume_label: Some("sda1"),
+            ..ScannedFile::new(&original)
+        };
+        scan_into(&handler, &rules, &file, &send);
+        handler.on_file_aliased(&link, &original, &send);
+
+        let mut paths: Vec<_> = render(&handler)
+            .iter()
+            .map(|m| m["ImagePath"].as_str().unwrap().to_string())
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                format!("[sda1] {}", absolute_path(&original)),
+                format!("[sda1] {}", absolute_path(&link)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rules_fired_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("a.bin", "EVIL"),
+            ("b.bin", "EVIL WICKED"),
+            ("c.bin", "clean"),
+        ];
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+
+        let rules = yara_x::compile(
+            r#"
+rule Wicked { strings: $a = "WICKED" condition: $a }
+rule Evil { strings: $a = "EVIL" condition: $a }
+rule Never { strings: $a = "NEVER" condition: $a }
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            rules_fired_only: true,
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+        for (name, _) in files {
+            let path = dir.path().join(name);
+            scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+        }
+
+        assert_eq!(render(&handler), ["Evil", "Wicked"]);
+    }
+
+    #[test]
+    fn test_require_score() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule Scored { meta: score = 70 strings: $a = "EVIL" condition: $a }
+rule Unscored { strings: $a = "EVIL" condition: $a }
+"#,
+        )
+        .unwrap();
+        let (send, _recv) = crossbeam::channel::unbounded();
+
+        let handler = JsonOutputHandler::default();
+        scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+        assert_eq!(render(&handler).len(), 2);
+
+        let handler = JsonOutputHandler {
+            require_score: true,
+            ..Default::default()
+        };
+        scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["Signature"], "Scored");
+    }
+
+    #[test]
+    fn test_boot_sector_match_reported_with_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        let mut data = vec![0; 4096];
+        data[446..450].copy_from_slice(b"EVIL");
+        data[2048..2052].copy_from_slice(b"EVIL");
+        fs::write(&image, &data).unwrap();
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler {
+            detail: Detail::Strings,
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let boot_sector = Target {
+            path: image,
+            offset: 0,
+            len: 512,
+        };
+        let counters = ScanCounters::default();
+        let errors = scan_targets(
+            &mut Scanner::new(&rules),
+            &[boot_sector],
+            &handler,
+            &send,
+            0,
+            &counters,
+        );
+        assert!(errors.is_empty());
+        assert_eq!(counters.matched.load(Ordering::Relaxed), 1);
+
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0]["ImagePath"]
+            .as_str()
+            .unwrap()
+            .ends_with("disk.img#range@0+512"));
+        let offsets: Vec<_> = matches[0]["Strings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["Offset"].as_u64().unwrap())
+            .collect();
+        assert_eq!(offsets, [446]);
+    }
+
+    #[test]
+    fn test_rule_priority_order() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule Unprioritized { meta: score = 90 strings: $a = "EVIL" condition: $a }
+rule Low { meta: priority = 1 score = 80 strings: $a = "EVIL" condition: $a }
+rule High { meta: priority = 5 score = 60 strings: $a = "EVIL" condition: $a }
+rule LowerScore { meta: priority = 1 score = 70 strings: $a = "EVIL" condition: $a }
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            rule_priority: true,
+            ..Default::default()
+        };
+        let signatures: Vec<_> = scan_matches(&handler, &rules, &path)
+            .iter()
+            .map(|m| m["Signature"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(signatures, ["High", "Low", "LowerScore", "Unprioritized"]);
+    }
+
+    #[test]
+    fn test_filename_anomaly_reported() {
+        let (_dir, path) = sample("invoice\u{202e}fdp.exe", b"harmless");
+
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let anomalies = anomaly::filename_anomalies(&path);
+        handler.on_filename_anomaly(&ScannedFile::new(&path), &anomalies, &send, 40);
+
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["Signature"], FILENAME_ANOMALY);
+        assert_eq!(
+            matches[0]["Description"],
+            "bidirectional override character in file name"
+        );
+        assert_eq!(matches[0]["ImagePath"], absolute_path(&path));
+    }
+
+    #[test]
+    fn test_browser_lists_anomalies_and_empty_files() {
+        use superconsole::{Component, Dimensions, DrawMode};
+
+        let dir = tempfile::tempdir().unwrap();
+        let disguised = dir.path().join("invoice\u{202e}fdp.exe");
+        let empty = dir.path().join("empty.bin");
+        fs::write(&disguised, b"harmless").unwrap();
+        fs::write(&empty, b"").unwrap();
+
+        let browser = std::sync::Arc::new(MatchBrowser::default());
+        let handler = JsonOutputHandler {
+            browser: Some(browser.clone()),
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let anomalies = anomaly::filename_anomalies(&disguised);
+        handler.on_filename_anomaly(&ScannedFile::new(&disguised), &anomalies, &send, 40);
+        handler.on_empty_file(&ScannedFile::new(&empty), EmptyFile::ZeroBytes, &send, 0);
+
+        let dimensions = Dimensions {
+            width: 200,
+            height: 10,
+        };
+        let lines: Vec<_> = browser
+            .draw(dimensions, DrawMode::Normal)
+            .unwrap()
+            .iter()
+            .map(|line| line.to_unstyled())
+            .collect();
+        assert!(lines.iter().any(|line| line.contains(FILENAME_ANOMALY)));
+        assert!(lines.iter().any(|line| line.contains(EMPTY_FILE)));
+
+        // The results can't share the terminal with the list.
+        assert!(Cli::try_parse_from(["fraken-x", "rules", "--folder", "/mnt", "--tui"]).is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let cli = Cli::parse_from(["fraken-x", "rules", "--folder", "/mnt"]);
+        assert_eq!(cli.scan_timeout, None);
+        assert!(
+            Cli::try_parse_from(["fraken-x", "rules", "--folder", "/mnt", "--timeout", "0"])
+                .is_err()
+        );
+
+        // An explicit timeout wins over the remote mode one.
+        let mut cli = Cli::parse_from([
+            "fraken-x",
+            "rules",
+            "--folder",
+            "/mnt",
+            "--remote-mode",
+            "--timeout",
+            "30",
+        ]);
+        cli.apply_remote_mode();
+        assert_eq!(cli.scan_timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_remote_mode_preset() {
+        let mut cli = Cli::parse_from(["fraken-x", "rules", "--folder", "/mnt", "--remote-mode"]);
+        cli.apply_remote_mode();
+        assert_eq!(cli.scan_retries, Some(Cli::REMOTE_SCAN_RETRIES));
+        assert_eq!(cli.scan_timeout, Some(Cli::REMOTE_SCAN_TIMEOUT));
+        assert_eq!(cli.max_open_files, Some(Cli::REMOTE_MAX_OPEN_FILES));
+        assert_eq!(cli.output_buffer, Some(Cli::REMOTE_OUTPUT_BUFFER));
+
+        let mut cli = Cli::parse_from([
+            "fraken-x",
+            "rules",
+            "--folder",
+            "/mnt",
+            "--remote-mode",
+            "--scan-retries",
+            "2",
+        ]);
+        cli.apply_remote_mode();
+        assert_eq!(cli.scan_retries, Some(2));
+
+        // Explicitly without retries.
+        let mut cli = Cli::parse_from([
+            "fraken-x",
+            "rules",
+            "--folder",
+            "/mnt",
+            "--remote-mode",
+            "--scan-retries",
+            "0",
+        ]);
+        cli.apply_remote_mode();
+        assert_eq!(cli.scan_retries, Some(0));
+
+        let mut cli = Cli::parse_from(["fraken-x", "rules", "--folder", "/mnt"]);
+        cli.apply_remote_mode();
+        assert_eq!(cli.scan_retries, None);
+        assert_eq!(cli.scan_timeout, None);
+    }
+
+    #[test]
+    fn test_matched_strings_count() {
+        let (_dir, path) = sample("evil.bin", b"EVIL and WORSE");
+
+        let rules = yara_x::compile(
+            r#"
+rule TwoOfThree {
+    strings:
+        $a = "EVIL"
+        $b = "WORSE"
+        $c = "ABSENT"
+    condition:
+        2 of them
+}
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler::default();
+        let matches = scan_matches(&handler, &rules, &path);
+        assert_eq!(matches[0]["MatchedStrings"], 2);
+    }
+
+    #[test]
+    fn test_digest_once_per_file() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule First { strings: $a = "EVIL" condition: $a }
+rule Second { strings: $a = "EVIL" condition: $a }
+rule Third { strings: $a = "EVIL" condition: $a }
+"#,
+        )
+        .unwrap();
+        let digest_calls = std::sync::Arc::new(AtomicUsize::new(0));
+        let calls = digest_calls.clone();
+        let handler = JsonOutputHandler {
+            digest: Some(std::sync::Arc::new(move |file: &ScannedFile<'_>| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                sha256(file)
+            })),
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+
+        scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+        assert_eq!(digest_calls.swap(0, Ordering::Relaxed), 1);
+
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 3);
+        let hash = sha256::digest(b"EVIL".as_slice());
+        assert!(matches.iter().all(|m| m["SHA256"] == hash.as_str()));
+
+        // None of the matches is reported, so the file isn't hashed.
+        let mut scanner = Scanner::new(&rules);
+        let results = scanner.scan_file(&path).unwrap();
+        let matched: Vec<_> = results.matching_rules().collect();
+        handler.on_file_scanned(&ScannedFile::new(&path), &matched, &send, 90);
+        assert_eq!(digest_calls.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_ndjson_streams_one_line_per_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.bin");
+        let second = dir.path().join("second.bin");
+        let link = dir.path().join("link.bin");
+        fs::write(&first, b"EVIL").unwrap();
+        fs::write(&second, b"EVIL").unwrap();
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = NdJsonOutputHandler::new(JsonOutputHandler::default(), true, 1);
+        let (send, recv) = crossbeam::channel::unbounded();
+
+        scan_into(&handler, &rules, &ScannedFile::new(&first), &send);
+        handler.on_file_aliased(&link, &first, &send);
+        // Sent right away, without waiting for the scan to be done.
+        let lines: Vec<_> = recv.try_iter().collect();
+        assert_eq!(lines.len(), 1);
+
+        scan_into(&handler, &rules, &ScannedFile::new(&second), &send);
+        handler.on_done(&send);
+        drop(send);
+        let lines: Vec<_> = lines.into_iter().chain(recv).collect();
+
+        let paths: Vec<_> = lines
+            .iter()
+            .map(|message| {
+                let Message::Info(line) = message else {
+                    panic!("unexpected message");
                 };
+                assert!(!line.contains('\n'));
+                let m: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert_eq!(m["Signature"], "TestRule");
+                m["ImagePath"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                absolute_path(&first),
+                absolute_path(&second),
+                absolute_path(&link)
+            ]
+        );
+    }
 
-                eprintln!("{}", msg);
+    #[test]
+    fn test_ndjson_flush_every() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = NdJsonOutputHandler::new(JsonOutputHandler::default(), false, 2);
+        let (send, recv) = crossbeam::channel::unbounded();
 
-                Ok(())
-            },
+        let mut paths = Vec::new();
+        for name in ["a.bin", "b.bin", "c.bin", "d.bin", "e.bin"] {
+            let path = dir.path().join(name);
+            fs::write(&path, b"EVIL").unwrap();
+            scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+            paths.push(absolute_path(&path));
+        }
+        // Two batches of two, the last match is held back.
+        assert_eq!(recv.try_iter().count(), 2);
+        handler.on_done(&send);
+        assert_eq!(recv.try_iter().count(), 1);
+
+        // Every match is delivered, whatever the cadence.
+        for flush_every in [1, 2, 3, 10] {
+            let handler =
+                NdJsonOutputHandler::new(JsonOutputHandler::default(), false, flush_every);
+            let (send, recv) = crossbeam::channel::unbounded();
+            for path in &paths {
+                scan_into(&handler, &rules, &ScannedFile::new(Path::new(path)), &send);
+            }
+            handler.on_done(&send);
+            drop(send);
+            let reported: Vec<_> = recv
+                .into_iter()
+                .flat_map(|message| {
+                    let Message::Info(lines) = message else {
+                        panic!("unexpected message");
+                    };
+                    lines
+                        .lines()
+                        .map(|line| {
+                            let m: serde_json::Value = serde_json::from_str(line).unwrap();
+                            m["ImagePath"].as_str().unwrap().to_string()
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            assert_eq!(reported, paths);
+        }
+    }
+
+    #[test]
+    fn test_rescanned_reported_in_full() {
+        let (_dir, path) = sample("evil.txt", b"EVIL");
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let file = ScannedFile {
+            rescanned: true,
+            ..ScannedFile::new(&path)
+        };
+        scan_into(&handler, &rules, &file, &send);
+
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["Signature"], "TestRule");
+        assert_eq!(matches[0]["Strings"][0]["Data"], "4556494c");
+        assert_eq!(matches[0]["Metadata"]["score"], 60);
+    }
+
+    #[test]
+    fn test_define() {
+        let cli = Cli::parse_from([
+            "fraken-x",
+            "rules",
+            "--folder",
+            "/mnt",
+            "--define",
+            "case_id=42",
+            "--define",
+            "host=web=01",
+        ]);
+        assert_eq!(
+            cli.define,
+            [
+                ("case_id".to_string(), "42".to_string()),
+                ("host".to_string(), "web=01".to_string()),
+            ]
+        );
+        for define in ["case_id", "filename=x", "case id=42"] {
+            assert!(Cli::try_parse_from([
+                "fraken-x", "rules", "--folder", "/mnt", "--define", define
+            ])
+            .is_err());
+        }
+        // The values of the saved rules can't be changed, nor how they were
+        // compiled.
+        for option in [
+            "--define=case_id=42",
+            "--relaxed-re-syntax",
+            "--ignore-module=pe",
+            "--enable-feature=foo",
+        ] {
+            assert!(Cli::try_parse_from([
+                "fraken-x",
+                "rules",
+                "--folder",
+                "/mnt",
+                "--load-rules",
+                "rules.bin",
+                option,
+            ])
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_threads() {
+        let cli = Cli::parse_from(["fraken-x", "rules", "--folder", "/mnt", "--threads", "0"]);
+        assert_eq!(cli.threads, Some(0));
+        assert!(
+            Cli::try_parse_from(["fraken-x", "rules", "--folder", "/mnt", "--threads", "-1"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_embed_rules_info() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let info = RulesInfo::new(&rules, &Default::default());
+        let handler = JsonOutputHandler {
+            rules_info: Some(std::sync::Arc::new(serde_json::to_value(info).unwrap())),
+            ..Default::default()
+        };
+        let (send, recv) = crossbeam::channel::unbounded();
+        scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+        handler.on_done(&send);
+        drop(send);
+
+        let Some(Message::Info(json)) = recv.into_iter().next() else {
+            panic!("no report");
+        };
+        let report: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            report["rules"]["rules"],
+            serde_json::json!([{"name": "TestRule", "namespace": "default"}])
+        );
+        assert_eq!(report["matches"][0]["Signature"], "TestRule");
+    }
+
+    #[test]
+    fn test_strict_fails_on_warnings() {
+        let mut compiler = rules::new_compiler(&Default::default()).unwrap();
+        compiler
+            .add_source(r#"rule NotBool { condition: 1 }"#)
+            .unwrap();
+        let warnings: Vec<_> = compiler.warnings().iter().map(|w| w.to_string()).collect();
+        assert!(!warnings.is_empty());
+
+        assert!(report_warnings(&warnings, false).is_ok());
+        assert!(report_warnings(&warnings, true).is_err());
+        assert!(report_warnings(&[], true).is_ok());
+    }
+
+    #[test]
+    fn test_group_by_rule() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.bin");
+        let second = dir.path().join("second.bin");
+        fs::write(&first, b"EVIL").unwrap();
+        fs::write(&second, b"more EVIL").unwrap();
+
+        let rules = yara_x::compile(
+            r#"
+rule TestRule : linux { meta: score = 60 strings: $a = "EVIL" condition: $a }
+rule Other { meta: score = 40 strings: $a = "more" condition: $a }
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            group_by_rule: true,
+            sort: Some(SortOrder::Path),
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+        scan_into(&handler, &rules, &ScannedFile::new(&first), &send);
+        scan_into(&handler, &rules, &ScannedFile::new(&second), &send);
+
+        let groups = render(&handler);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0]["rule"], "Other");
+        assert_eq!(groups[0]["matches"].as_array().unwrap().len(), 1);
+        assert_eq!(groups[1]["rule"], "TestRule");
+        assert_eq!(groups[1]["tags"], serde_json::json!(["linux"]));
+        assert_eq!(
+            groups[1]["matches"],
+            serde_json::json!([
+                {"path": absolute_path(&first), "hash": sha256::digest("EVIL"), "score": 60},
+                {"path": absolute_path(&second), "hash": sha256::digest("more EVIL"), "score": 60},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_normalize_scores() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule Huge { meta: score = 850 strings: $a = "EVIL" condition: $a }
+rule Negative { meta: score = -20 strings: $a = "EVIL" condition: $a }
+rule InRange { meta: score = 70 strings: $a = "EVIL" condition: $a }
+"#,
+        )
+        .unwrap();
+        let handler = JsonOutputHandler {
+            normalize_scores: true,
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+        let mut scanner = Scanner::new(&rules);
+        let results = scanner.scan_file(&path).unwrap();
+        let matched: Vec<_> = results.matching_rules().collect();
+        handler.on_file_scanned(&ScannedFile::new(&path), &matched, &send, 0);
+
+        let scores: Vec<_> = render(&handler)
+            .iter()
+            .map(|m| (m["Signature"].clone(), m["Score"].clone()))
+            .collect();
+        assert_eq!(
+            scores,
+            [
+                ("Huge".into(), 100.into()),
+                ("Negative".into(), 0.into()),
+                ("InRange".into(), 70.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_file_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let zeros = dir.path().join("carved.bin");
+        let normal = dir.path().join("normal.bin");
+        fs::write(&zeros, [0; 4096]).unwrap();
+        fs::write(&normal, b"harmless").unwrap();
+
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+        for path in [&zeros, &normal] {
+            let len = fs::metadata(path).unwrap().len();
+            if let Some(kind) = empty::check_empty(path, len).unwrap() {
+                handler.on_empty_file(&ScannedFile::new(path), kind, &send, 40);
+            }
+        }
+
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["Signature"], EMPTY_FILE);
+        assert_eq!(matches[0]["Description"], "file filled with zeros");
+        assert_eq!(matches[0]["ImagePath"], absolute_path(&zeros));
+    }
+
+    #[test]
+    fn test_string_offsets_and_no_strings() {
+        let (_dir, path) = sample("evil.bin", b"xxEVILyyEVIL");
+
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let strings = |no_strings: bool| {
+            let handler = JsonOutputHandler {
+                detail: Detail::Full,
+                no_strings,
+                ..Default::default()
+            };
+            scan_matches(&handler, &rules, &path)[0]
+                .get("Strings")
+                .cloned()
+        };
+
+        assert_eq!(
+            strings(false),
+            Some(serde_json::json!([
+                {"Identifier": "$a", "Offset": 2, "Length": 4, "Data": "4556494c"},
+                {"Identifier": "$a", "Offset": 8, "Length": 4, "Data": "4556494c"},
+            ]))
+        );
+        assert_eq!(strings(true), None);
+    }
+
+    #[test]
+    fn test_tags_and_require_tag() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+
+        let rules = yara_x::compile(
+            r#"
+rule Tagged : malware apt { strings: $a = "EVIL" condition: $a }
+rule Untagged { strings: $a = "EVIL" condition: $a }
+"#,
+        )
+        .unwrap();
+        let tags = |require_tag: Option<&str>| {
+            let handler = JsonOutputHandler {
+                require_tag: require_tag.map(str::to_string),
+                ..Default::default()
+            };
+            scan_matches(&handler, &rules, &path)
+                .iter()
+                .map(|m| (m["Signature"].clone(), m["Tags"].clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            tags(None),
+            [
+                ("Tagged".into(), serde_json::json!(["malware", "apt"])),
+                ("Untagged".into(), serde_json::json!([])),
+            ]
+        );
+        assert_eq!(
+            tags(Some("apt")),
+            [("Tagged".into(), serde_json::json!(["malware", "apt"]))]
+        );
+        assert_eq!(tags(Some("ransomware")), []);
+    }
+
+    #[test]
+    fn test_context_meta_action() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+        let rules = yara_x::compile(
+            r#"rule Context { meta: score = 70 context = "yes" strings: $a = "EVIL" condition: $a }"#,
+        )
+        .unwrap();
+        let (send, _recv) = crossbeam::channel::unbounded();
+
+        let matches = |context_action| {
+            let handler = JsonOutputHandler {
+                context_action,
+                ..Default::default()
+            };
+            scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+            render(&handler)
+        };
+
+        // Scored 0, below the minimum score.
+        assert!(matches(ContextAction::Suppress).is_empty());
+
+        let tagged = matches(ContextAction::Tag);
+        assert_eq!(tagged[0]["Score"], 70);
+        assert_eq!(tagged[0]["Tags"], serde_json::json!(["context"]));
+
+        let ignored = matches(ContextAction::Ignore);
+        assert_eq!(ignored[0]["Score"], 70);
+        assert_eq!(ignored[0]["Tags"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_policy_disables_and_rescores_rules() {
+        let (dir, path) = sample("evil.bin", b"EVIL");
+        let policy_path = dir.path().join("policy.json");
+        fs::write(
+            &policy_path,
+            r#"{"disable_rules": ["Noisy"], "scores": {"Quiet": 80}}"#,
+        )
+        .unwrap();
+
+        let rules = yara_x::compile(
+            r#"
+rule Noisy { meta: score = 70 strings: $a = "EVIL" condition: $a }
+rule Quiet { meta: score = 10 strings: $a = "EVIL" condition: $a }
+rule Other { meta: score = 60 strings: $a = "EVIL" condition: $a }
+"#,
         )
         .unwrap();
+        let handler = JsonOutputHandler {
+            policy: Some(std::sync::Arc::new(Policy::read(&policy_path).unwrap())),
+            ..Default::default()
+        };
+        let matches: Vec<_> = scan_matches(&handler, &rules, &path)
+            .iter()
+            .map(|m| (m["Signature"].clone(), m["Score"].clone()))
+            .collect();
+        assert_eq!(
+            matches,
+            [
+                ("Quiet".into(), serde_json::json!(80)),
+                ("Other".into(), serde_json::json!(60)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output_file_replaces_console() {
+        let (dir, path) = sample("evil.bin", b"EVIL");
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+
+        let scan_to_file = |format: OutputFormat| {
+            let output_path = dir.path().join(format!("out/{:?}.json", format));
+            let handler = JsonOutputHandler {
+                output_file: Some(std::sync::Arc::new(
+                    OutputFile::create(&output_path).unwrap(),
+                )),
+                ..Default::default()
+            };
+            let handler = output_handler(format, handler, false, 1);
+            let (send, recv) = crossbeam::channel::unbounded();
+            scan_into(handler.as_ref(), &rules, &ScannedFile::new(&path), &send);
+            scan_into(handler.as_ref(), &rules, &ScannedFile::new(&path), &send);
+            handler.on_done(&send);
+            drop(send);
+            assert_eq!(recv.into_iter().count(), 0);
+            fs::read_to_string(output_path).unwrap()
+        };
+
+        let json = scan_to_file(OutputFormat::Json);
+        let matches: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(matches.as_array().unwrap().len(), 2);
+
+        let ndjson = scan_to_file(OutputFormat::Ndjson);
+        assert_eq!(ndjson.lines().count(), 2);
+        for line in ndjson.lines() {
+            let m: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(m["Signature"], "TestRule");
+        }
+    }
+
+    #[test]
+    fn test_size_and_mtime() {
+        let (_dir, path) = sample("evil.bin", b"xxEVILxx");
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler::default();
+        let (send, _recv) = crossbeam::channel::unbounded();
+
+        let file = ScannedFile {
+            metadata: Some(&metadata),
+            ..ScannedFile::new(&path)
+        };
+        scan_into(&handler, &rules, &file, &send);
+        // Without metadata, like the byte ranges of --targets.
+        scan_into(&handler, &rules, &ScannedFile::new(&path), &send);
+
+        let matches = render(&handler);
+        assert_eq!(matches[0]["Size"], 8);
+        assert_eq!(matches[0]["Mtime"], 1_700_000_000);
+        assert_eq!(matches[1]["Size"], 0);
+        assert_eq!(matches[1]["Mtime"], 0);
+    }
+
+    #[test]
+    fn test_allowlist_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("tools")).unwrap();
+        let allowed = dir.path().join("tools/nmap");
+        let other = dir.path().join("evil.bin");
+        fs::write(&allowed, b"EVIL").unwrap();
+        fs::write(&other, b"EVIL").unwrap();
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+        let handler = JsonOutputHandler {
+            allowlist: Some(std::sync::Arc::new(
+                PathAllowlist::new(["**/tools/nmap"], false).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let (send, _recv) = crossbeam::channel::unbounded();
+
+        // The allowlisted file is still scanned, and matches.
+        let mut scanner = Scanner::new(&rules);
+        let results = scanner.scan_file(&allowed).unwrap();
+        let matched: Vec<_> = results.matching_rules().collect();
+        assert_eq!(matched.len(), 1);
+        handler.on_file_scanned(&ScannedFile::new(&allowed), &matched, &send, 40);
+        scan_into(&handler, &rules, &ScannedFile::new(&other), &send);
+        // Nor are its other matches, or those of the aliases in the
+        // allowlist.
+        let allowed = ScannedFile::new(&allowed);
+        handler.on_filename_anomaly(&allowed, &[FilenameAnomaly::DoubleExtension], &send, 0);
+        handler.on_empty_file(&allowed, EmptyFile::AllZeros, &send, 0);
+        handler.on_file_aliased(&dir.path().join("tools/nmap"), &other, &send);
+
+        let matches = render(&handler);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["ImagePath"], absolute_path(&other));
+        assert_eq!(handler.counters.reported.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_csv_output() {
+        let (_dir, path) = sample("evil.bin", b"EVIL");
+        let rules = yara_x::compile(
+            r#"
+rule Quoted {
+    meta:
+        score = 70
+        description = "Evil, really"
+        reference = "https://example.com"
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#,
+        )
+        .unwrap();
+
+        let handler = output_handler(OutputFormat::Csv, JsonOutputHandler::default(), false, 1);
+        let (send, recv) = crossbeam::channel::unbounded();
+        scan_into(handler.as_ref(), &rules, &ScannedFile::new(&path), &send);
+        assert!(recv.is_empty());
+        handler.on_done(&send);
+        drop(send);
+        let csv = match recv.iter().next() {
+            Some(Message::Info(csv)) => csv,
+            _ => panic!("No CSV sent"),
+        };
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "ImagePath,SHA256,Signature,Description,Reference,Score".to_string(),
+                format!(
+                    "{},{},Quoted,\"Evil, really\",https://example.com,70",
+                    absolute_path(&path),
+                    try_digest(path.as_path()).unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_output() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::RowAccessor;
+
+        let (dir, path) = sample("evil.bin", b"EVIL");
+        let output_path = dir.path().join("results/matches.parquet");
+        let rules = yara_x::compile(TEST_RULE).unwrap();
+
+        let handler = JsonOutputHandler {
+            output_file: Some(std::sync::Arc::new(
+                OutputFile::create(&output_path).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let handler = output_handler(OutputFormat::Parquet, handler, false, 1);
+        let (send, recv) = crossbeam::channel::unbounded();
+        scan_into(handler.as_ref(), &rules, &ScannedFile::new(&path), &send);
+        handler.on_done(&send);
+        drop(send);
+        assert_eq!(recv.into_iter().count(), 0);
+
+        let reader = SerializedFileReader::new(File::open(&output_path).unwrap()).unwrap();
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                let row = row.unwrap();
+                (
+                    row.get_string(0).unwrap().clone(),
+                    row.get_string(2).unwrap().clone(),
+                    row.get_long(3).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(rows, [(absolute_path(&path), "TestRule".to_string(), 60)]);
     }
 }
@@ -0,0 +1,104 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+
+/// A file written by fraken-x, as listed in the manifest.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    /// What the file contains, like `results` or `status`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub size: u64,
+}
+
+/// Keeps track of the files written during a run so that they can be listed
+/// in a single index for the orchestrator to register.
+#[derive(Default)]
+pub struct Manifest {
+    files: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl Manifest {
+    /// Records that the file at `path`, containing `kind`, was written.
+    /// Recording the same path again replaces its kind.
+    pub fn record(&self, path: &Path, kind: &str) {
+        let mut files = self.files.lock().unwrap();
+        files.retain(|(recorded, _)| recorded != path);
+        files.push((path.to_path_buf(), kind.to_string()));
+    }
+
+    /// Lists the recorded files with their current size. Files that no
+    /// longer exist are left out.
+    pub fn entries(&self) -> Vec<ManifestEntry> {
+        self.files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(path, kind)| {
+                let metadata = fs::metadata(path).ok()?;
+                Some(ManifestEntry {
+                    path: path.clone(),
+                    kind: kind.clone(),
+                    size: metadata.len(),
+                })
+            })
+            .collect()
+    }
+
+    /// Writes the manifest to `path` as a JSON array.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string(&self.entries())?;
+        fs::write(path, json).with_context(|| format!("can not write `{}`", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_lists_written_files() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let results = dir.path().join("results.json");
+        let status = dir.path().join("status.json");
+        fs::write(&results, "[]")?;
+        fs::write(&status, r#"{"exit_code":0}"#)?;
+
+        let manifest = Manifest::default();
+        manifest.record(&results, "results");
+        manifest.record(&status, "status");
+        manifest.record(&dir.path().join("never-written"), "results");
+
+        let manifest_path = dir.path().join("manifest.json");
+        manifest.write(&manifest_path)?;
+
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+        assert_eq!(
+            written,
+            serde_json::json!([
+                {"path": results, "type": "results", "size": 2},
+                {"path": status, "type": "status", "size": 15},
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_same_path_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out");
+        fs::write(&path, "x").unwrap();
+
+        let manifest = Manifest::default();
+        manifest.record(&path, "results");
+        manifest.record(&path, "results");
+
+        assert_eq!(manifest.entries().len(), 1);
+    }
+}
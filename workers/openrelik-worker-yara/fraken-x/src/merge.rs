@@ -0,0 +1,123 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{bail, Context};
+use serde_json::{Map, Value};
+
+/// Field added to every merged match, listing the reports it was found in.
+pub const RUNS_FIELD: &str = "Runs";
+
+/// Merges the matches of the JSON reports at `paths` into a single list.
+///
+/// A report holds one JSON array of matches per scanned folder, or, in
+/// later versions, objects with the array under `matches`. Matches of the
+/// same rule on the same content at the same path are merged, and every
+/// match gets a [`RUNS_FIELD`] listing the reports it appears in. Fields
+/// missing from older reports are left out, fields unknown to this version
+/// are kept as they are.
+pub fn merge_reports<P: AsRef<Path>>(paths: &[P]) -> anyhow::Result<Vec<Value>> {
+    let mut merged: Vec<Map<String, Value>> = Vec::new();
+    let mut index: HashMap<(String, String, String), usize> = HashMap::new();
+
+    for path in paths {
+        let path = path.as_ref();
+        let run = path.display().to_string();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("can not read `{}`", path.display()))?;
+
+        for value in serde_json::Deserializer::from_str(&content).into_iter::<Value>() {
+            let value = value.with_context(|| format!("invalid report `{}`", path.display()))?;
+            let matches = match value {
+                Value::Array(matches) => matches,
+                Value::Object(mut report) => match report.remove("matches") {
+                    Some(Value::Array(matches)) => matches,
+                    _ => bail!("no matches in report `{}`", path.display()),
+                },
+                _ => bail!("unexpected JSON in report `{}`", path.display()),
+            };
+            for m in matches {
+                let Value::Object(mut m) = m else {
+                    continue;
+                };
+                let field = |name: &str| m.get(name).and_then(Value::as_str).map(str::to_string);
+                let (Some(image_path), Some(signature)) = (field("ImagePath"), field("Signature"))
+                else {
+                    continue;
+                };
+                let key = (image_path, field("SHA256").unwrap_or_default(), signature);
+                let position = *index.entry(key).or_insert_with(|| {
+                    m.insert(RUNS_FIELD.to_string(), Value::Array(Vec::new()));
+                    merged.push(m);
+                    merged.len() - 1
+                });
+                if let Some(Value::Array(runs)) = merged[position].get_mut(RUNS_FIELD) {
+                    let run = Value::String(run.clone());
+                    if !runs.contains(&run) {
+                        runs.push(run);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(merged.into_iter().map(Value::Object).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_reports() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first.json");
+        let second = dir.path().join("second.json");
+        // Two folders scanned in the first run, each with its own array.
+        fs::write(
+            &first,
+            r#"[{"ImagePath":"/a","SHA256":"aa","Signature":"Evil","Score":70}]
+[{"ImagePath":"/b","SHA256":"bb","Signature":"Evil","Score":70}]
+"#,
+        )?;
+        // A later version wrapping the matches, with a new field.
+        fs::write(
+            &second,
+            r#"{"version":2,"matches":[
+                {"ImagePath":"/a","SHA256":"aa","Signature":"Evil","Score":70,"Tags":["apt"]},
+                {"ImagePath":"/a","SHA256":"aa","Signature":"Other","Score":40}
+            ]}"#,
+        )?;
+
+        let merged = merge_reports(&[&first, &second])?;
+
+        let runs = |paths: &[&Path]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect()
+        };
+        assert_eq!(
+            merged,
+            [
+                json!({"ImagePath":"/a","SHA256":"aa","Signature":"Evil","Score":70,
+                       "Runs": runs(&[&first, &second])}),
+                json!({"ImagePath":"/b","SHA256":"bb","Signature":"Evil","Score":70,
+                       "Runs": runs(&[&first])}),
+                json!({"ImagePath":"/a","SHA256":"aa","Signature":"Other","Score":40,
+                       "Runs": runs(&[&second])}),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_invalid_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = dir.path().join("report.json");
+        fs::write(&report, "[+] not JSON").unwrap();
+
+        assert!(merge_reports(&[&report]).is_err());
+    }
+}
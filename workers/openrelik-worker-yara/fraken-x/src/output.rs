@@ -0,0 +1,104 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Context;
+
+/// The file the results are written to instead of stdout, one line at a
+/// time so that lines written by different threads never interleave.
+pub struct OutputFile {
+    path: PathBuf,
+    file: Mutex<File>,
+    /// Whether a write failed, leaving the results incomplete.
+    failed: AtomicBool,
+}
+
+impl OutputFile {
+    /// Creates, or truncates, the file at `path`, along with its missing
+    /// parent folders.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("can not create `{}`", parent.display()))?;
+        }
+        let file =
+            File::create(path).with_context(|| format!("can not create `{}`", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `line` to the file.
+    pub fn write_line(&self, line: &str) -> anyhow::Result<()> {
+        self.write_with(|file| Ok(file.write_all(format!("{}\n", line).as_bytes())?))
+    }
+
+    /// Appends to the file with `write`, given the file as created.
+    pub fn write_with(
+        &self,
+        write: impl FnOnce(&mut File) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let result =
+            write(&mut file).with_context(|| format!("can not write `{}`", self.path.display()));
+        if result.is_err() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Whether a write failed.
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("results/scan.json");
+
+        let output = OutputFile::create(&path)?;
+        output.write_line("[1]")?;
+        output.write_line("[2]")?;
+        assert_eq!(fs::read_to_string(&path)?, "[1]\n[2]\n");
+
+        // An earlier run is overwritten.
+        OutputFile::create(&path)?.write_line("[3]")?;
+        assert_eq!(fs::read_to_string(&path)?, "[3]\n");
+
+        // Written through the file as created, and failures are remembered.
+        let output = OutputFile::create(&path)?;
+        output.write_with(|file| Ok(file.write_all(b"[4]")?))?;
+        assert!(!output.failed());
+        assert!(output
+            .write_with(|_| Err(anyhow::anyhow!("disk full")))
+            .is_err());
+        assert!(output.failed());
+        assert_eq!(fs::read_to_string(&path)?, "[4]");
+
+        // The parent is a file.
+        assert!(OutputFile::create(&path.join("scan.json")).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::{io::Write, sync::Arc};
+
+use parquet::{
+    data_type::{ByteArray, ByteArrayType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+/// Schema of the Parquet file, given explicitly so that the columns keep
+/// their types whatever the matches.
+pub const SCHEMA: &str = "
+message fraken_match {
+    REQUIRED BYTE_ARRAY path (UTF8);
+    REQUIRED BYTE_ARRAY sha256 (UTF8);
+    REQUIRED BYTE_ARRAY signature (UTF8);
+    REQUIRED INT64 score;
+    REQUIRED GROUP tags (LIST) {
+        REPEATED GROUP list {
+            REQUIRED BYTE_ARRAY element (UTF8);
+        }
+    }
+}
+";
+
+/// A match, as a row of the Parquet file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchRow {
+    pub path: String,
+    pub sha256: String,
+    pub signature: String,
+    pub score: i64,
+    pub tags: Vec<String>,
+}
+
+/// Writes `rows` as a Parquet file to `file`, in a single row group.
+pub fn write_matches<W: Write + Send>(file: W, rows: &[MatchRow]) -> anyhow::Result<()> {
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+
+    if !rows.is_empty() {
+        let strings = |field: fn(&MatchRow) -> &str| -> Vec<ByteArray> {
+            rows.iter().map(|row| field(row).into()).collect()
+        };
+        // The tags of every row one after the other. A row without tags
+        // only has a level, with no value.
+        let mut tags = Vec::new();
+        let mut definition_levels = Vec::new();
+        let mut repetition_levels = Vec::new();
+        for row in rows {
+            if row.tags.is_empty() {
+                definition_levels.push(0);
+                repetition_levels.push(0);
+            }
+            for (i, tag) in row.tags.iter().enumerate() {
+                tags.push(ByteArray::from(tag.as_str()));
+                definition_levels.push(1);
+                repetition_levels.push(i16::from(i > 0));
+            }
+        }
+
+        let mut row_group = writer.next_row_group()?;
+        let mut column_index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match column_index {
+                0 => column.typed::<ByteArrayType>().write_batch(
+                    &strings(|row| &row.path),
+                    None,
+                    None,
+                )?,
+                1 => column.typed::<ByteArrayType>().write_batch(
+                    &strings(|row| &row.sha256),
+                    None,
+                    None,
+                )?,
+                2 => column.typed::<ByteArrayType>().write_batch(
+                    &strings(|row| &row.signature),
+                    None,
+                    None,
+                )?,
+                3 => {
+                    let scores: Vec<i64> = rows.iter().map(|row| row.score).collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&scores, None, None)?
+                }
+                _ => column.typed::<ByteArrayType>().write_batch(
+                    &tags,
+                    Some(&definition_levels),
+                    Some(&repetition_levels),
+                )?,
+            };
+            column.close()?;
+            column_index += 1;
+        }
+        row_group.close()?;
+    }
+
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use parquet::{
+        basic::Type,
+        file::reader::{FileReader, SerializedFileReader},
+        record::{ListAccessor, RowAccessor},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_write_matches() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("matches.parquet");
+        let rows = [
+            MatchRow {
+                path: "/evil.bin".to_string(),
+                sha256: "aa".to_string(),
+                signature: "Evil".to_string(),
+                score: 70,
+                tags: vec!["malware".to_string(), "apt".to_string()],
+            },
+            MatchRow {
+                path: "/other.bin".to_string(),
+                sha256: "bb".to_string(),
+                signature: "Other".to_string(),
+                score: 40,
+                tags: vec![],
+            },
+        ];
+        write_matches(File::create(&path)?, &rows)?;
+
+        let reader = SerializedFileReader::new(File::open(&path)?)?;
+        let schema = reader.metadata().file_metadata().schema_descr();
+        let types: Vec<_> = schema.columns().iter().map(|c| c.physical_type()).collect();
+        assert_eq!(
+            types,
+            [
+                Type::BYTE_ARRAY,
+                Type::BYTE_ARRAY,
+                Type::BYTE_ARRAY,
+                Type::INT64,
+                Type::BYTE_ARRAY
+            ]
+        );
+
+        let read: Vec<MatchRow> = reader
+            .get_row_iter(None)?
+            .map(|row| -> anyhow::Result<MatchRow> {
+                let row = row?;
+                let tags = row.get_list(4)?;
+                Ok(MatchRow {
+                    path: row.get_string(0)?.clone(),
+                    sha256: row.get_string(1)?.clone(),
+                    signature: row.get_string(2)?.clone(),
+                    score: row.get_long(3)?,
+                    tags: (0..tags.len())
+                        .map(|i| tags.get_string(i).cloned())
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        assert_eq!(read, rows);
+
+        // Without any match, the file still has the columns.
+        write_matches(File::create(&path)?, &[])?;
+        let reader = SerializedFileReader::new(File::open(&path)?)?;
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 0);
+        assert_eq!(
+            reader
+                .metadata()
+                .file_metadata()
+                .schema_descr()
+                .num_columns(),
+            5
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Context;
+use yara_x::Rules;
+
+/// A detection policy, tuning which rules are reported and with which
+/// score, read from a JSON file like:
+///
+/// ```json
+/// {
+///   "enable_tags": ["linux"],
+///   "disable_rules": ["NoisyRule"],
+///   "scores": {"WebShell": 90}
+/// }
+/// ```
+///
+/// Rules are named by their identifier. When any rule or tag is enabled,
+/// only the enabled ones are reported. Disabling wins over enabling.
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub enable_rules: Vec<String>,
+    pub enable_tags: Vec<String>,
+    pub disable_rules: Vec<String>,
+    pub disable_tags: Vec<String>,
+    /// Scores replacing the ones in the rules' metadata, by rule.
+    pub scores: HashMap<String, i64>,
+}
+
+impl Policy {
+    /// Reads the policy file at `path`.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content =
+            fs::read(path).with_context(|| format!("can not read `{}`", path.display()))?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("invalid policy `{}`", path.display()))
+    }
+
+    /// Returns whether the matches of the rule `name`, with `tags`, are
+    /// reported.
+    pub fn is_enabled(&self, name: &str, tags: &[String]) -> bool {
+        let named = |rules: &[String], tag_list: &[String]| {
+            rules.iter().any(|rule| rule == name) || tags.iter().any(|tag| tag_list.contains(tag))
+        };
+        if named(&self.disable_rules, &self.disable_tags) {
+            return false;
+        }
+        let allow_list = !self.enable_rules.is_empty() || !self.enable_tags.is_empty();
+        !allow_list || named(&self.enable_rules, &self.enable_tags)
+    }
+
+    /// Returns the score the policy gives to the rule `name`, if any.
+    pub fn score(&self, name: &str) -> Option<i64> {
+        self.scores.get(name).copied()
+    }
+
+    /// Returns the rules named by the policy that are not in `rules`,
+    /// likely typos or rules since renamed.
+    pub fn unknown_rules(&self, rules: &Rules) -> Vec<String> {
+        let known: Vec<&str> = rules.iter().map(|rule| rule.identifier()).collect();
+        let mut unknown: Vec<String> = self
+            .enable_rules
+            .iter()
+            .chain(&self.disable_rules)
+            .chain(self.scores.keys())
+            .filter(|name| !known.contains(&name.as_str()))
+            .cloned()
+            .collect();
+        unknown.sort();
+        unknown.dedup();
+        unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_enabled() {
+        let policy = Policy {
+            enable_tags: tags(&["linux"]),
+            enable_rules: tags(&["Extra"]),
+            disable_rules: tags(&["Noisy"]),
+            ..Default::default()
+        };
+
+        assert!(policy.is_enabled("Rootkit", &tags(&["linux"])));
+        assert!(policy.is_enabled("Extra", &[]));
+        assert!(!policy.is_enabled("Noisy", &tags(&["linux"])));
+        assert!(!policy.is_enabled("Windows", &tags(&["windows"])));
+        // Without anything enabled, everything not disabled is.
+        assert!(Policy::default().is_enabled("Windows", &[]));
+    }
+
+    #[test]
+    fn test_read_policy() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("policy.json");
+        fs::write(
+            &path,
+            r#"{"disable_tags": ["test"], "scores": {"Evil": 90}}"#,
+        )?;
+        let policy = Policy::read(&path)?;
+        assert_eq!(policy.disable_tags, ["test"]);
+        assert_eq!(policy.score("Evil"), Some(90));
+        assert_eq!(policy.score("Other"), None);
+
+        fs::write(&path, r#"{"disabled_rules": ["Evil"]}"#)?;
+        assert!(Policy::read(&path).is_err());
+
+        let rules = yara_x::compile("rule Evil { condition: true }")?;
+        let policy = Policy {
+            disable_rules: tags(&["Evil", "Typo"]),
+            ..Default::default()
+        };
+        assert_eq!(policy.unknown_rules(&rules), ["Typo"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Records how long each file took to scan and keeps the slowest ones.
+pub struct ScanProfile {
+    /// Number of files kept.
+    top: usize,
+    /// The slowest files seen so far, slowest first.
+    slowest: Mutex<Vec<(Duration, PathBuf)>>,
+}
+
+impl ScanProfile {
+    /// Creates a profile that keeps the `top` slowest files.
+    pub fn new(top: usize) -> Self {
+        Self {
+            top,
+            slowest: Mutex::new(Vec::with_capacity(top + 1)),
+        }
+    }
+
+    /// Records that scanning `path` took `elapsed`.
+    pub fn record(&self, path: &Path, elapsed: Duration) {
+        let mut slowest = self.slowest.lock().unwrap();
+        if slowest.len() == self.top && slowest.last().is_some_and(|(d, _)| *d >= elapsed) {
+            return;
+        }
+        let pos = slowest.partition_point(|(d, _)| *d >= elapsed);
+        slowest.insert(pos, (elapsed, path.to_path_buf()));
+        slowest.truncate(self.top);
+    }
+
+    /// Returns the slowest files recorded, slowest first.
+    pub fn slowest(&self) -> Vec<(Duration, PathBuf)> {
+        self.slowest.lock().unwrap().clone()
+    }
+}
+
+/// Returns the peak resident memory of this process in bytes, as tracked
+/// by the kernel. Only available on Linux.
+pub fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status_bytes(&status, "VmHWM")
+}
+
+/// Reads the `field` of a `/proc/<pid>/status` file, given in kB, as bytes.
+fn status_bytes(status: &str, field: &str) -> Option<u64> {
+    status.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kb: u64 = value.trim().strip_suffix("kB")?.trim().parse().ok()?;
+        Some(kb * 1024)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_slowest_files() {
+        let profile = ScanProfile::new(2);
+        for (name, ms) in [("a", 5), ("b", 50), ("c", 1), ("d", 20)] {
+            profile.record(Path::new(name), Duration::from_millis(ms));
+        }
+
+        assert_eq!(
+            profile.slowest(),
+            vec![
+                (Duration::from_millis(50), PathBuf::from("b")),
+                (Duration::from_millis(20), PathBuf::from("d")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status_bytes() {
+        let status = "Name:\tfraken-x\nVmPeak:\t  20480 kB\nVmHWM:\t    1024 kB\n";
+        assert_eq!(status_bytes(status, "VmHWM"), Some(1024 * 1024));
+        assert_eq!(status_bytes(status, "VmRSS"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_peak_rss_after_scan() {
+        let rules = yara_x::compile(r#"rule Evil { strings: $a = "EVIL" condition: $a }"#).unwrap();
+        let data = vec![b'A'; 16 * 1024 * 1024];
+        yara_x::Scanner::new(&rules).scan(&data).unwrap();
+
+        let peak = peak_rss().unwrap();
+        // At least the scanned data, and far from anything a test uses.
+        assert!(peak >= data.len() as u64, "peak {} too low", peak);
+        assert!(peak < 16 * 1024 * 1024 * 1024, "peak {} too high", peak);
+    }
+
+    #[test]
+    fn test_empty_profile() {
+        let profile = ScanProfile::new(10);
+        assert!(profile.slowest().is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crossbeam::channel::Sender;
+
+/// Holds back items that complete out of order and releases them in the
+/// order of their index, as soon as all the items before them are in.
+///
+/// Indices start at 0 and every index must be pushed exactly once, a
+/// missing index holds back all the items after it.
+#[derive(Debug)]
+pub struct ReorderBuffer<T> {
+    next: u64,
+    pending: BTreeMap<u64, T>,
+}
+
+impl<T> Default for ReorderBuffer<T> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the item with `index`, returning the items that are now ready,
+    /// in order. This is empty while an earlier item is missing.
+    pub fn push(&mut self, index: u64, item: T) -> Vec<T> {
+        self.pending.insert(index, item);
+        let mut ready = Vec::new();
+        while let Some(item) = self.pending.remove(&self.next) {
+            ready.push(item);
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// Number of items held back.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Sends batches of items, pushed in any order from any thread, to a
+/// channel in the order of their index.
+pub struct OrderedSender<T> {
+    buffer: Mutex<ReorderBuffer<Vec<T>>>,
+    output: Sender<T>,
+}
+
+impl<T> OrderedSender<T> {
+    pub fn new(output: Sender<T>) -> Self {
+        Self {
+            buffer: Mutex::new(ReorderBuffer::new()),
+            output,
+        }
+    }
+
+    /// Adds the batch with `index` and sends the items of the batches now
+    /// ready. The lock is held while sending, so that the batches released
+    /// by different threads don't interleave.
+    pub fn send(&self, index: u64, batch: Vec<T>) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for item in buffer.push(index, batch).into_iter().flatten() {
+            let _ = self.output.send(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorder_buffer() {
+        let mut buffer = ReorderBuffer::new();
+        assert_eq!(buffer.push(2, "c"), Vec::<&str>::new());
+        assert_eq!(buffer.push(1, "b"), Vec::<&str>::new());
+        assert_eq!(buffer.pending(), 2);
+        assert_eq!(buffer.push(0, "a"), ["a", "b", "c"]);
+        assert_eq!(buffer.push(3, "d"), ["d"]);
+        assert_eq!(buffer.pending(), 0);
+    }
+
+    #[test]
+    fn test_ordered_sender() {
+        let (send, recv) = crossbeam::channel::unbounded();
+        let sender = OrderedSender::new(send);
+
+        // Each batch is sent from its own thread once released, the first
+        // batches last.
+        std::thread::scope(|s| {
+            let (done_send, done_recv) = crossbeam::channel::unbounded();
+            let releases: Vec<_> = (0..6u64)
+                .map(|index| {
+                    let (release, released) = crossbeam::channel::bounded::<()>(0);
+                    let (sender, done_send) = (&sender, done_send.clone());
+                    s.spawn(move || {
+                        released.recv().unwrap();
+                        sender.send(index, vec![index * 10, index * 10 + 1]);
+                        done_send.send(()).unwrap();
+                    });
+                    release
+                })
+                .collect();
+
+            for release in releases[1..].iter().rev() {
+                release.send(()).unwrap();
+                done_recv.recv().unwrap();
+            }
+            // Held back until the first batch is in.
+            assert!(recv.is_empty());
+            releases[0].send(()).unwrap();
+            done_recv.recv().unwrap();
+        });
+        drop(sender);
+
+        let received: Vec<u64> = recv.into_iter().collect();
+        assert_eq!(received, [0, 1, 10, 11, 20, 21, 30, 31, 40, 41, 50, 51]);
+    }
+}
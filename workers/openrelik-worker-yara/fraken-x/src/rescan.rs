@@ -0,0 +1,153 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crossbeam::channel::Sender;
+use yara_x::Rule;
+
+use crate::{
+    anomaly::FilenameAnomaly,
+    empty::EmptyFile,
+    scan::{OutputHandler, ScannedFile},
+    walk::Message,
+};
+
+/// Handler of a first, quick scan, keeping the paths of the files that
+/// matched any rule to scan them again once every file is scanned.
+///
+/// The aliases, filename anomalies and empty files, only found by the first
+/// scan, are passed to `handler` as they come.
+pub struct MatchedFiles<'h> {
+    handler: &'h dyn OutputHandler,
+    paths: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl<'h> MatchedFiles<'h> {
+    pub fn new(handler: &'h dyn OutputHandler) -> Self {
+        Self {
+            handler,
+            paths: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Returns the paths of the files that matched, sorted.
+    pub fn into_paths(self) -> Vec<PathBuf> {
+        self.paths.into_inner().unwrap().into_iter().collect()
+    }
+}
+
+impl OutputHandler for MatchedFiles<'_> {
+    /// Keeps the file if any rule matched, whatever its score.
+    fn on_file_scanned(
+        &self,
+        file: &ScannedFile<'_>,
+        scan_results: &[Rule<'_, '_>],
+        _output: &Sender<Message>,
+        _minimum_score: u32,
+    ) {
+        if !scan_results.is_empty() {
+            self.paths.lock().unwrap().insert(file.path.to_path_buf());
+        }
+    }
+
+    fn on_file_aliased(&self, alias: &Path, original: &Path, output: &Sender<Message>) {
+        self.handler.on_file_aliased(alias, original, output);
+    }
+
+    fn on_filename_anomaly(
+        &self,
+        file: &ScannedFile<'_>,
+        anomalies: &[FilenameAnomaly],
+        output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        self.handler
+            .on_filename_anomaly(file, anomalies, output, minimum_score);
+    }
+
+    fn on_empty_file(
+        &self,
+        file: &ScannedFile<'_>,
+        kind: EmptyFile,
+        output: &Sender<Message>,
+        minimum_score: u32,
+    ) {
+        self.handler
+            .on_empty_file(file, kind, output, minimum_score);
+    }
+
+    /// The handler is done once the files are scanned again.
+    fn on_done(&self, _output: &Sender<Message>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use yara_x::Scanner;
+
+    use super::*;
+
+    /// Counts the files and anomalies it is passed.
+    #[derive(Default)]
+    struct Counter {
+        files: AtomicUsize,
+        anomalies: AtomicUsize,
+    }
+
+    impl OutputHandler for Counter {
+        fn on_file_scanned(
+            &self,
+            _file: &ScannedFile<'_>,
+            _scan_results: &[Rule<'_, '_>],
+            _output: &Sender<Message>,
+            _minimum_score: u32,
+        ) {
+            self.files.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_filename_anomaly(
+            &self,
+            _file: &ScannedFile<'_>,
+            _anomalies: &[FilenameAnomaly],
+            _output: &Sender<Message>,
+            _minimum_score: u32,
+        ) {
+            self.anomalies.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_done(&self, _output: &Sender<Message>) {}
+    }
+
+    #[test]
+    fn test_matched_files() {
+        let rules = yara_x::compile(r#"rule Evil { strings: $a = "EVIL" condition: $a }"#).unwrap();
+        let mut scanner = Scanner::new(&rules);
+        let counter = Counter::default();
+        let matched = MatchedFiles::new(&counter);
+        let (send, _recv) = crossbeam::channel::unbounded();
+
+        for (name, content) in [("b.bin", "EVIL"), ("clean.bin", "clean"), ("a.bin", "EVIL")] {
+            let path = Path::new(name);
+            let results = scanner.scan(content.as_bytes()).unwrap();
+            let rules: Vec<_> = results.matching_rules().collect();
+            matched.on_file_scanned(&ScannedFile::new(path), &rules, &send, 40);
+        }
+        // Anomalies are passed on.
+        matched.on_filename_anomaly(
+            &ScannedFile::new(Path::new("invoice.pdf.exe")),
+            &[FilenameAnomaly::DoubleExtension],
+            &send,
+            40,
+        );
+
+        assert_eq!(counter.files.load(Ordering::Relaxed), 0);
+        assert_eq!(counter.anomalies.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            matched.into_paths(),
+            [PathBuf::from("a.bin"), PathBuf::from("b.bin")]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use yara_x::ScanError;
+
+/// Returns true if a scan that failed with `err` may succeed when tried
+/// again, like when the file couldn't be opened or mapped under memory
+/// pressure. Timeouts would only time out again.
+pub fn is_transient(err: &ScanError) -> bool {
+    matches!(
+        err,
+        ScanError::OpenError { .. } | ScanError::MapError { .. }
+    )
+}
+
+/// Keeps count of the retries left for scanning a file.
+///
+/// The scan itself stays at the call site, as the results borrow the
+/// scanner:
+///
+/// ```ignore
+/// let mut retries = ScanRetries::new(3);
+/// let results = loop {
+///     match scanner.scan_file(path) {
+///         Err(err) if retries.retry(&err) => continue,
+///         results => break results,
+///     }
+/// };
+/// ```
+pub struct ScanRetries {
+    left: u32,
+}
+
+impl ScanRetries {
+    pub fn new(retries: u32) -> Self {
+        Self { left: retries }
+    }
+
+    /// Returns true if the scan that failed with `err` should be tried
+    /// again, using up one retry.
+    pub fn retry(&mut self, err: &ScanError) -> bool {
+        if self.left > 0 && is_transient(err) {
+            self.left -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::PathBuf};
+
+    use super::*;
+
+    /// A scanner that fails to open the file `failures` times.
+    struct StubScanner {
+        failures: u32,
+        attempts: u32,
+    }
+
+    impl StubScanner {
+        fn scan_file(&mut self) -> Result<(), ScanError> {
+            self.attempts += 1;
+            if self.attempts <= self.failures {
+                return Err(ScanError::OpenError {
+                    path: PathBuf::from("evidence.bin"),
+                    source: io::Error::from(io::ErrorKind::OutOfMemory),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    fn scan_with_retries(scanner: &mut StubScanner, retries: u32) -> Result<(), ScanError> {
+        let mut retries = ScanRetries::new(retries);
+        loop {
+            match scanner.scan_file() {
+                Err(err) if retries.retry(&err) => continue,
+                results => break results,
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_transient_error() {
+        let mut scanner = StubScanner {
+            failures: 1,
+            attempts: 0,
+        };
+        assert!(scan_with_retries(&mut scanner, 2).is_ok());
+        assert_eq!(scanner.attempts, 2);
+
+        let mut scanner = StubScanner {
+            failures: 1,
+            attempts: 0,
+        };
+        assert!(scan_with_retries(&mut scanner, 0).is_err());
+        assert_eq!(scanner.attempts, 1);
+    }
+
+    #[test]
+    fn test_timeout_is_not_retried() {
+        let mut retries = ScanRetries::new(3);
+        assert!(!retries.retry(&ScanError::Timeout));
+    }
+}
@@ -0,0 +1,890 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, Metadata},
+    ops::Range,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use yara_x::{
+    errors::{CompileError, VariableError},
+    Compiler, MetaValue, Rule, Rules, ScanResults, Scanner, SourceCode,
+};
+
+use crate::walk::Walker;
+
+/// Rules built into the binary, usable without any rule files.
+pub const BUILTIN_RULES: &str = include_str!("../builtin/eicar.yar");
+
+/// External variables that are set for every scanned file.
+pub const EXTERNAL_VARIABLES: [&str; 6] = [
+    "filepath",
+    "filename",
+    "filetype",
+    "extension",
+    "owner",
+    "group",
+];
+
+/// Integer external variables set from the metadata of every scanned file:
+/// its mode including the file type bits, its size and its modification
+/// time in seconds since the epoch.
+pub const METADATA_VARIABLES: [&str; 3] = ["filemode", "filesize_bytes", "mtime_epoch"];
+
+/// Creates a [`Walker`] over the YARA rule files found under `path`, with
+/// their extensions matched regardless of case if `case_insensitive`.
+pub fn rules_walker(path: &Path, case_insensitive: bool) -> Walker<'_> {
+    let mut w = Walker::path(path);
+    w.filter("**/*.yar");
+    w.filter("**/*.yara");
+    w.case_insensitive(case_insensitive);
+    w
+}
+
+/// Compiler settings that change which rules are accepted.
+#[derive(Clone, Debug, Default)]
+pub struct CompilerOptions {
+    /// Accept regular expressions that YARA accepts but YARA-X rejects by
+    /// default, like unknown escape sequences.
+    pub relaxed_re_syntax: bool,
+    /// Modules whose imports are ignored. Rules using them are skipped
+    /// instead of failing the compilation.
+    pub ignored_modules: Vec<String>,
+    /// Additional YARA-X compiler features to enable.
+    pub features: Vec<String>,
+    /// Find rule files regardless of the case of their extension, like
+    /// `RULES.YAR`.
+    pub case_insensitive_globs: bool,
+    /// Scan-wide string external variables, like `case_id`, with their
+    /// value. Their names are checked with [`check_global_name`].
+    pub globals: Vec<(String, String)>,
+}
+
+impl CompilerOptions {
+    /// Applies the options to `compiler`, which must not have any rules yet.
+    pub fn apply(&self, compiler: &mut Compiler<'_>) {
+        compiler.relaxed_re_syntax(self.relaxed_re_syntax);
+        for module in &self.ignored_modules {
+            compiler.ignore_module(module.as_str());
+        }
+        for feature in &self.features {
+            compiler.enable_feature(feature.as_str());
+        }
+    }
+}
+
+/// Creates a compiler configured with `options` and with the external
+/// variables already defined. Fails when a scan-wide variable can't be
+/// defined, like one given twice.
+pub fn new_compiler<'a>(options: &CompilerOptions) -> Result<Compiler<'a>, VariableError> {
+    let mut compiler = Compiler::new();
+    options.apply(&mut compiler);
+    for ident in EXTERNAL_VARIABLES {
+        compiler.define_global(ident, "")?;
+    }
+    for ident in METADATA_VARIABLES {
+        compiler.define_global(ident, 0)?;
+    }
+    // Their value is the same for every scan, so it is compiled in, which
+    // is why they can't be changed for rules saved with `save_rules`.
+    for (ident, value) in &options.globals {
+        compiler.define_global(ident, value.as_str())?;
+    }
+    Ok(compiler)
+}
+
+/// Checks that `name` can be used for a scan-wide external variable: an
+/// identifier that isn't one of the variables set for every scanned file.
+pub fn check_global_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(format!("`{}` is not a valid variable name", name));
+    }
+    if EXTERNAL_VARIABLES.contains(&name) || METADATA_VARIABLES.contains(&name) {
+        return Err(format!("`{}` is set for every scanned file", name));
+    }
+    Ok(())
+}
+
+/// Sets the [`METADATA_VARIABLES`] of `scanner` from the scanned file's
+/// `metadata`.
+pub fn set_metadata_variables(
+    scanner: &mut Scanner<'_>,
+    metadata: &Metadata,
+) -> Result<(), VariableError> {
+    scanner.set_global("filemode", i64::from(metadata.mode()))?;
+    scanner.set_global("filesize_bytes", metadata.len() as i64)?;
+    scanner.set_global("mtime_epoch", metadata.mtime())?;
+    Ok(())
+}
+
+/// Adds every rule file found under `path` to `compiler`.
+///
+/// Sources that fail to compile are left out of the compiler, their errors
+/// are available through [`Compiler::errors`]. Returns the number of rule
+/// files found.
+pub fn add_rules_from(
+    compiler: &mut Compiler<'_>,
+    path: &Path,
+    options: &CompilerOptions,
+) -> anyhow::Result<usize> {
+    let mut num_files = 0;
+    rules_walker(path, options.case_insensitive_globs).walk(
+        |file_path| {
+            eprintln!("[-] Attempting to parse {}", file_path.display());
+            let src = fs::read(file_path)
+                .with_context(|| format!("can not read `{}`", file_path.display()))?;
+
+            let src = SourceCode::from(src.as_slice())
+                .with_origin(file_path.as_os_str().to_str().unwrap());
+            let _ = compiler.add_source(src);
+            num_files += 1;
+
+            Ok(())
+        },
+        Err,
+    )?;
+    Ok(num_files)
+}
+
+/// Like [`add_rules_from`], but the rules with a syntax error are removed
+/// from the file's source until the rest parses, dropping at most
+/// `max_dropped` rules in total. This keeps the rules that a syntax error
+/// would otherwise take down with it, like an unterminated string.
+///
+/// Other errors are left to `compiler`, which only leaves out the rule they
+/// are in. Every file is first compiled on its own with `options` to find
+/// its syntax errors, as sources can't be removed from `compiler` once
+/// added; its other errors there may come from using rules or includes
+/// only known to `compiler`. Returns the number of rule files found and the
+/// names of the dropped rules.
+pub fn add_rules_dropping_broken(
+    compiler: &mut Compiler<'_>,
+    path: &Path,
+    options: &CompilerOptions,
+    max_dropped: usize,
+) -> anyhow::Result<(usize, Vec<String>)> {
+    let mut num_files = 0;
+    let mut dropped = Vec::new();
+    rules_walker(path, options.case_insensitive_globs).walk(
+        |file_path| {
+            eprintln!("[-] Attempting to parse {}", file_path.display());
+            let src = fs::read(file_path)
+                .with_context(|| format!("can not read `{}`", file_path.display()))?;
+            let origin = file_path.as_os_str().to_str().unwrap();
+            num_files += 1;
+
+            // Sources that aren't UTF-8 are added as they are, to get the error.
+            let src = match String::from_utf8(src) {
+                Ok(mut text) => {
+                    while dropped.len() < max_dropped {
+                        let mut trial = new_compiler(options)?;
+                        let _ = trial.add_source(SourceCode::from(text.as_str()));
+                        let Some(err) = trial
+                            .errors()
+                            .iter()
+                            .find(|err| matches!(err, CompileError::SyntaxError(_)))
+                        else {
+                            break;
+                        };
+                        let Some((range, name)) =
+                            error_offset(err).and_then(|at| rule_at(&text, at))
+                        else {
+                            break;
+                        };
+                        eprintln!("[-] Dropping rule {} from {}", name, file_path.display());
+                        text.replace_range(range, "");
+                        dropped.push(name);
+                    }
+                    text.into_bytes()
+                }
+                Err(err) => err.into_bytes(),
+            };
+            let _ = compiler.add_source(SourceCode::from(src.as_slice()).with_origin(origin));
+
+            Ok(())
+        },
+        Err,
+    )?;
+    Ok((num_files, dropped))
+}
+
+/// Returns the byte offset in the source where `err` was found.
+fn error_offset(err: &yara_x::errors::CompileError) -> Option<usize> {
+    // The spans of the labels are only exposed through serde.
+    let report = serde_json::to_value(err).ok()?;
+    let start = report["labels"].get(0)?["span"]["start"].as_u64()?;
+    Some(start as usize)
+}
+
+/// Finds the rule declared in `src` that contains the byte `offset`, and
+/// returns its byte range, up to the next rule, along with its name.
+fn rule_at(src: &str, offset: usize) -> Option<(Range<usize>, String)> {
+    let mut declarations = Vec::new();
+    let mut line_start = 0;
+    for line in src.split_inclusive('\n') {
+        let mut rest = line.trim_start();
+        while let Some(after) = rest
+            .strip_prefix("private ")
+            .or_else(|| rest.strip_prefix("global "))
+        {
+            rest = after.trim_start();
+        }
+        if let Some(after) = rest.strip_prefix("rule") {
+            if after.starts_with(char::is_whitespace) {
+                let name: String = after
+                    .trim_start()
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                    .collect();
+                declarations.push((line_start, name));
+            }
+        }
+        line_start += line.len();
+    }
+
+    let index = declarations
+        .iter()
+        .rposition(|(start, _)| *start <= offset)?;
+    let end = declarations
+        .get(index + 1)
+        .map_or(src.len(), |(start, _)| *start);
+    let (start, name) = declarations.swap_remove(index);
+    Some((start..end, name))
+}
+
+/// Writes the compiled `rules` to `path`, to be loaded by [`load_rules`]
+/// instead of compiling them again. The previous file is only replaced once
+/// written in full.
+pub fn save_rules(rules: &Rules, path: &Path) -> anyhow::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let file = fs::File::create(&temp_path)
+        .with_context(|| format!("can not create `{}`", temp_path.display()))?;
+    rules
+        .serialize_into(file)
+        .with_context(|| format!("can not write `{}`", temp_path.display()))?;
+    fs::rename(&temp_path, path).with_context(|| format!("can not replace `{}`", path.display()))
+}
+
+/// Reads the compiled rules written to `path` by [`save_rules`], with the
+/// same version of YARA-X.
+pub fn load_rules(path: &Path) -> anyhow::Result<Rules> {
+    let file =
+        fs::File::open(path).with_context(|| format!("can not read `{}`", path.display()))?;
+    Rules::deserialize_from(file)
+        .with_context(|| format!("invalid compiled rules `{}`", path.display()))
+}
+
+/// Adds the [`BUILTIN_RULES`] to `compiler`.
+pub fn add_builtin_rules(compiler: &mut Compiler<'_>) {
+    let src = SourceCode::from(BUILTIN_RULES).with_origin("builtin");
+    compiler
+        .add_source(src)
+        .expect("the builtin rules must compile");
+}
+
+/// What a rule is meant to match, from its `target` metadata.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RuleTarget {
+    /// The content of the files.
+    #[default]
+    Content,
+    /// The paths of the files, for rules with `target = "path"`.
+    Path,
+}
+
+impl RuleTarget {
+    /// Returns the target of `rule`.
+    pub fn of(rule: &Rule<'_, '_>) -> Self {
+        let is_path = rule
+            .metadata()
+            .any(|(key, value)| key == "target" && matches!(value, MetaValue::String("path")));
+        if is_path {
+            Self::Path
+        } else {
+            Self::Content
+        }
+    }
+
+    /// Returns the rules of `results` with this target, leaving out the
+    /// others, like the rules on the paths matching the content of a file.
+    pub fn matching_rules<'a, 'r>(self, results: &'a ScanResults<'a, 'r>) -> Vec<Rule<'a, 'r>> {
+        results
+            .matching_rules()
+            .filter(|rule| Self::of(rule) == self)
+            .collect()
+    }
+}
+
+/// Modules parsing the structure of the whole file, which can't do their job
+/// with only part of it.
+const FULL_FILE_MODULES: [&str; 5] = ["pe", "elf", "macho", "dotnet", "lnk"];
+
+/// Returns true if `rules` import a module parsing the whole file, like
+/// `pe`, so that scanning part of a file may miss their matches.
+pub fn needs_full_file(rules: &Rules) -> bool {
+    rules
+        .imports()
+        .any(|module| FULL_FILE_MODULES.contains(&module))
+}
+
+/// Returns a warning when `path` yielded no rules, which would otherwise make
+/// every scan silently match nothing. `num_files` is the number of rule files
+/// found by [`add_rules_from`] and `num_rules` the number of rules compiled.
+pub fn empty_rules_warning(path: &Path, num_files: usize, num_rules: usize) -> Option<String> {
+    if num_files == 0 {
+        Some(format!(
+            "no .yar or .yara files found under `{}`",
+            path.display()
+        ))
+    } else if num_rules == 0 {
+        Some(format!(
+            "{} rule file(s) under `{}` contain no rules",
+            num_files,
+            path.display()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Differences between two rule sets, as produced by [`diff_rules`].
+#[derive(serde::Serialize, Debug, Default, PartialEq, Eq)]
+pub struct RulesDiff {
+    /// Rules only present in the new rule set.
+    pub added: Vec<String>,
+    /// Rules only present in the old rule set.
+    pub removed: Vec<String>,
+    /// Rules present in both whose source file contents differ.
+    pub changed: Vec<String>,
+}
+
+/// Compiles every rule file under `path` on its own, so that its rules can
+/// be attributed to it, and calls `f` with the file's path, its contents and
+/// its rules. Files that don't compile on their own are reported on stderr
+/// and left out.
+fn for_each_rule_file<F>(path: &Path, options: &CompilerOptions, mut f: F) -> anyhow::Result<()>
+where
+    F: FnMut(&Path, &[u8], &Rules),
+{
+    rules_walker(path, options.case_insensitive_globs).walk(
+        |file_path| {
+            let src = fs::read(file_path)
+                .with_context(|| format!("can not read `{}`", file_path.display()))?;
+
+            let mut compiler = new_compiler(options)?;
+            let source = SourceCode::from(src.as_slice())
+                .with_origin(file_path.as_os_str().to_str().unwrap_or_default());
+            if let Err(err) = compiler.add_source(source) {
+                eprintln!("[-] Skipping {}: {}", file_path.display(), err);
+                return Ok(());
+            }
+            f(file_path, &src, &compiler.build());
+
+            Ok(())
+        },
+        Err,
+    )
+}
+
+/// Maps each rule found under `path` to the SHA256 of the file defining it.
+pub fn rule_fingerprints(
+    path: &Path,
+    options: &CompilerOptions,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut fingerprints = BTreeMap::new();
+    for_each_rule_file(path, options, |_, src, rules| {
+        let hash = sha256::digest(src);
+        for rule in rules.iter() {
+            let name = format!("{}:{}", rule.namespace(), rule.identifier());
+            fingerprints.insert(name, hash.clone());
+        }
+    })?;
+    Ok(fingerprints)
+}
+
+/// Maps each rule found under `path` to the path of the file defining it.
+pub fn rule_origins(
+    path: &Path,
+    options: &CompilerOptions,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut origins = BTreeMap::new();
+    for_each_rule_file(path, options, |file_path, _, rules| {
+        for rule in rules.iter() {
+            let name = format!("{}:{}", rule.namespace(), rule.identifier());
+            origins.insert(name, file_path.display().to_string());
+        }
+    })?;
+    Ok(origins)
+}
+
+/// A compiled rule, as listed with `--embed-rules-info`.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct RuleInfo {
+    pub name: String,
+    pub namespace: String,
+    /// The file defining the rule, unknown for the builtin rules.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+/// The rule set a scan used, to tie its findings to it.
+#[derive(serde::Serialize, Debug, Default, PartialEq, Eq)]
+pub struct RulesInfo {
+    pub rules: Vec<RuleInfo>,
+    /// Compilation warnings.
+    pub warnings: Vec<String>,
+    /// Compilation errors of the rules left out of the rule set.
+    pub errors: Vec<String>,
+}
+
+impl RulesInfo {
+    /// Lists the compiled `rules`, taking their files from `origins`, as
+    /// returned by [`rule_origins`].
+    pub fn new(rules: &Rules, origins: &BTreeMap<String, String>) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let name = format!("{}:{}", rule.namespace(), rule.identifier());
+                RuleInfo {
+                    name: rule.identifier().to_string(),
+                    namespace: rule.namespace().to_string(),
+                    origin: origins.get(&name).cloned(),
+                }
+            })
+            .collect();
+        Self {
+            rules,
+            ..Default::default()
+        }
+    }
+}
+
+/// Compares the rules under `old` and `new` by name and source hash.
+pub fn diff_rules(old: &Path, new: &Path, options: &CompilerOptions) -> anyhow::Result<RulesDiff> {
+    let old = rule_fingerprints(old, options)?;
+    let new = rule_fingerprints(new, options)?;
+    let mut diff = RulesDiff::default();
+
+    for (name, hash) in &new {
+        match old.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(old_hash) if old_hash != hash => diff.changed.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .keys()
+        .filter(|name| !new.contains_key(*name))
+        .cloned()
+        .collect();
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHARED: &str = r#"rule Shared { strings: $a = "shared" condition: $a }"#;
+
+    #[test]
+    fn test_rule_target() -> anyhow::Result<()> {
+        let rules = yara_x::compile(
+            r#"
+rule OnPath { meta: target = "path" condition: true }
+rule OnContent { meta: target = "memory" condition: true }
+"#,
+        )?;
+        let targets: Vec<_> = rules.iter().map(|rule| RuleTarget::of(&rule)).collect();
+        assert_eq!(targets, [RuleTarget::Path, RuleTarget::Content]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_needs_full_file() -> anyhow::Result<()> {
+        assert!(!needs_full_file(&yara_x::compile(SHARED)?));
+        assert!(!needs_full_file(&yara_x::compile(
+            r#"import "hash" rule Hashed { condition: hash.md5(0, 4) == "" }"#
+        )?));
+        assert!(needs_full_file(&yara_x::compile(
+            r#"import "pe" rule IsPe { condition: pe.is_pe }"#
+        )?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_rules() -> anyhow::Result<()> {
+        let old = tempfile::tempdir()?;
+        let new = tempfile::tempdir()?;
+        fs::write(old.path().join("shared.yar"), SHARED)?;
+        fs::write(new.path().join("shared.yar"), SHARED)?;
+        fs::write(
+            old.path().join("tuned.yar"),
+            r#"rule Tuned { strings: $a = "old" condition: $a }"#,
+        )?;
+        fs::write(
+            new.path().join("tuned.yar"),
+            r#"rule Tuned { strings: $a = "new" condition: $a }"#,
+        )?;
+        fs::write(
+            old.path().join("gone.yara"),
+            r#"rule Gone { condition: filename == "gone" }"#,
+        )?;
+        fs::write(
+            new.path().join("extra.yar"),
+            r#"rule Extra { condition: false }"#,
+        )?;
+
+        let diff = diff_rules(old.path(), new.path(), &CompilerOptions::default())?;
+
+        assert_eq!(
+            diff,
+            RulesDiff {
+                added: vec!["default:Extra".to_string()],
+                removed: vec!["default:Gone".to_string()],
+                changed: vec!["default:Tuned".to_string()],
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_rules_identical() -> anyhow::Result<()> {
+        let old = tempfile::tempdir()?;
+        fs::write(old.path().join("shared.yar"), SHARED)?;
+
+        assert_eq!(
+            diff_rules(old.path(), old.path(), &CompilerOptions::default())?,
+            RulesDiff::default()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_load_rules() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rules.bin");
+        let mut compiler = new_compiler(&CompilerOptions::default()).unwrap();
+        compiler.add_source(
+            r#"
+rule Evil { meta: score = 60 strings: $a = "EVIL" condition: $a }
+rule Named { condition: filename == "evil.bin" }
+rule Clean { strings: $a = "CLEAN" condition: $a }
+"#,
+        )?;
+        let compiled = compiler.build();
+        save_rules(&compiled, &path)?;
+        let loaded = load_rules(&path)?;
+
+        let matching = |rules: &Rules| {
+            let mut scanner = Scanner::new(rules);
+            scanner.set_global("filename", "evil.bin").unwrap();
+            let results = scanner.scan(b"xxEVILxx").unwrap();
+            results
+                .matching_rules()
+                .map(|rule| {
+                    let metadata: Vec<_> = rule
+                        .metadata()
+                        .map(|(key, value)| format!("{}={:?}", key, value))
+                        .collect();
+                    (rule.identifier().to_string(), metadata)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matching(&loaded), matching(&compiled));
+        assert_eq!(matching(&loaded).len(), 2);
+
+        // Saved again over the previous file.
+        save_rules(&compiled, &path)?;
+        assert_eq!(matching(&load_rules(&path)?), matching(&compiled));
+        assert!(!dir.path().join("rules.bin.tmp").exists());
+
+        fs::write(&path, b"not rules")?;
+        assert!(load_rules(&path).is_err());
+        assert!(load_rules(&dir.path().join("missing.bin")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_globals() {
+        let options = CompilerOptions {
+            globals: vec![("case_id".to_string(), "42".to_string())],
+            ..Default::default()
+        };
+        let mut compiler = new_compiler(&options).unwrap();
+        compiler
+            .add_source(r#"rule Case { condition: case_id == "42" and filename == "" }"#)
+            .unwrap();
+        let rules = compiler.build();
+        let mut scanner = yara_x::Scanner::new(&rules);
+        assert_eq!(scanner.scan(b"").unwrap().matching_rules().len(), 1);
+
+        // Given twice.
+        let options = CompilerOptions {
+            globals: vec![
+                ("case_id".to_string(), "42".to_string()),
+                ("case_id".to_string(), "43".to_string()),
+            ],
+            ..Default::default()
+        };
+        assert!(new_compiler(&options).is_err());
+
+        assert!(check_global_name("case_id").is_ok());
+        assert!(check_global_name("_host2").is_ok());
+        assert!(check_global_name("owner").is_err());
+        assert!(check_global_name("filesize_bytes").is_err());
+        assert!(check_global_name("2host").is_err());
+        assert!(check_global_name("case-id").is_err());
+        assert!(check_global_name("").is_err());
+    }
+
+    #[test]
+    fn test_relaxed_re_syntax_toggle() {
+        // `\R` is not a valid escape sequence for YARA-X, YARA treats it as
+        // a literal `R`.
+        let src = r#"rule Relaxed { strings: $a = /foo\Rbar/ condition: $a }"#;
+
+        let mut strict = new_compiler(&CompilerOptions::default()).unwrap();
+        assert!(strict.add_source(src).is_err());
+
+        let options = CompilerOptions {
+            relaxed_re_syntax: true,
+            ..Default::default()
+        };
+        let mut relaxed = new_compiler(&options).unwrap();
+        assert!(relaxed.add_source(src).is_ok());
+
+        let rules = relaxed.build();
+        let mut scanner = yara_x::Scanner::new(&rules);
+        let results = scanner.scan(b"fooRbar").unwrap();
+        assert_eq!(results.matching_rules().len(), 1);
+    }
+
+    #[test]
+    fn test_ignored_module_toggle() {
+        let src = r#"
+import "unknown_module"
+rule UsesModule { condition: unknown_module.value == 1 }
+rule Plain { condition: true }
+"#;
+
+        let mut default = new_compiler(&CompilerOptions::default()).unwrap();
+        assert!(default.add_source(src).is_err());
+
+        let options = CompilerOptions {
+            ignored_modules: vec!["unknown_module".to_string()],
+            ..Default::default()
+        };
+        let mut ignoring = new_compiler(&options).unwrap();
+        assert!(ignoring.add_source(src).is_ok());
+        let rules = ignoring.build();
+        let names: Vec<&str> = rules.iter().map(|r| r.identifier()).collect();
+        assert_eq!(names, vec!["Plain"]);
+    }
+
+    #[test]
+    fn test_empty_rules_dir_warns() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("README.md"), "not a rule")?;
+
+        let options = CompilerOptions::default();
+        let mut compiler = new_compiler(&options).unwrap();
+        let num_files = add_rules_from(&mut compiler, dir.path(), &options)?;
+        let num_rules = compiler.build().iter().len();
+
+        assert_eq!((num_files, num_rules), (0, 0));
+        let warning = empty_rules_warning(dir.path(), num_files, num_rules).unwrap();
+        assert!(warning.starts_with("no .yar or .yara files"));
+        assert!(empty_rules_warning(dir.path(), 1, 1).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtin_rules_match() -> anyhow::Result<()> {
+        let mut compiler = new_compiler(&CompilerOptions::default()).unwrap();
+        add_builtin_rules(&mut compiler);
+        let rules = compiler.build();
+        assert!(rules.iter().len() > 0);
+
+        let eicar = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+        let mut scanner = yara_x::Scanner::new(&rules);
+        let results = scanner.scan(eicar)?;
+        let matched: Vec<_> = results
+            .matching_rules()
+            .map(|rule| rule.identifier())
+            .collect();
+        assert_eq!(matched, ["EICAR_Test_File"]);
+
+        assert_eq!(scanner.scan(b"harmless")?.matching_rules().len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_broken_rules() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(
+            dir.path().join("pack.yar"),
+            r#"
+rule Good { strings: $a = "good" condition: $a }
+
+rule Broken { strings: $a = "unterminated condition: $a }
+
+rule Other { strings: $a = "other" condition: $a }
+"#,
+        )?;
+        let options = CompilerOptions::default();
+
+        let mut compiler = new_compiler(&options).unwrap();
+        let (num_files, dropped) =
+            add_rules_dropping_broken(&mut compiler, dir.path(), &options, 5)?;
+        assert_eq!(num_files, 1);
+        assert_eq!(dropped, ["Broken"]);
+
+        let rules = compiler.build();
+        assert_eq!(rules.iter().len(), 2);
+        let mut scanner = yara_x::Scanner::new(&rules);
+        let results = scanner.scan(b"something good")?;
+        let matched: Vec<_> = results
+            .matching_rules()
+            .map(|rule| rule.identifier())
+            .collect();
+        assert_eq!(matched, ["Good"]);
+
+        // Without dropping, the unterminated string swallows `Other`.
+        let mut strict = new_compiler(&options).unwrap();
+        let (_, dropped) = add_rules_dropping_broken(&mut strict, dir.path(), &options, 0)?;
+        assert!(dropped.is_empty());
+        assert_eq!(strict.build().iter().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_broken_rules_keeps_references() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(
+            dir.path().join("pack.yar"),
+            r#"
+rule UsesBase { condition: Base }
+
+rule Broken { strings: $a = "unterminated condition: $a }
+
+rule Other { strings: $a = "other" condition: $a }
+"#,
+        )?;
+        let options = CompilerOptions::default();
+
+        // `Base` comes from another source, unknown when the file is
+        // compiled on its own.
+        let mut compiler = new_compiler(&options).unwrap();
+        compiler.add_source(r#"rule Base { strings: $a = "base" condition: $a }"#)?;
+        let (_, dropped) = add_rules_dropping_broken(&mut compiler, dir.path(), &options, 5)?;
+        assert_eq!(dropped, ["Broken"]);
+
+        let rules = compiler.build();
+        let mut scanner = yara_x::Scanner::new(&rules);
+        let results = scanner.scan(b"base")?;
+        let mut matched: Vec<_> = results
+            .matching_rules()
+            .map(|rule| rule.identifier())
+            .collect();
+        matched.sort();
+        assert_eq!(matched, ["Base", "UsesBase"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_variables() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut compiler = new_compiler(&CompilerOptions::default()).unwrap();
+        // 0x49 is 0o111, executable by anyone.
+        compiler.add_source("rule Executable { condition: filemode & 0x49 != 0 }")?;
+        let rules = compiler.build();
+
+        let dir = tempfile::tempdir()?;
+        let matches = |mode: u32| -> anyhow::Result<usize> {
+            let path = dir.path().join(format!("file-{:o}", mode));
+            fs::write(&path, "#!/bin/sh")?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            let mut scanner = Scanner::new(&rules);
+            set_metadata_variables(&mut scanner, &path.metadata()?)?;
+            Ok(scanner.scan_file(&path)?.matching_rules().len())
+        };
+
+        assert_eq!(matches(0o755)?, 1);
+        assert_eq!(matches(0o644)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rules_info_lists_rules() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first.yar");
+        let second = dir.path().join("second.yar");
+        fs::write(&first, "rule First { condition: true }")?;
+        fs::write(&second, "rule Second { condition: true }")?;
+
+        let options = CompilerOptions::default();
+        let mut compiler = new_compiler(&options).unwrap();
+        add_rules_from(&mut compiler, dir.path(), &options)?;
+        let rules = compiler.build();
+        let mut info = RulesInfo::new(&rules, &rule_origins(dir.path(), &options)?);
+        info.rules.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(
+            info.rules,
+            [
+                RuleInfo {
+                    name: "First".to_string(),
+                    namespace: "default".to_string(),
+                    origin: Some(first.display().to_string()),
+                },
+                RuleInfo {
+                    name: "Second".to_string(),
+                    namespace: "default".to_string(),
+                    origin: Some(second.display().to_string()),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive_rule_globs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(
+            dir.path().join("UPPER.YAR"),
+            "rule Upper { condition: true }",
+        )?;
+
+        let mut options = CompilerOptions::default();
+        assert_eq!(
+            add_rules_from(&mut new_compiler(&options).unwrap(), dir.path(), &options)?,
+            0
+        );
+
+        options.case_insensitive_globs = true;
+        assert_eq!(
+            add_rules_from(&mut new_compiler(&options).unwrap(), dir.path(), &options)?,
+            1
+        );
+
+        Ok(())
+    }
+}
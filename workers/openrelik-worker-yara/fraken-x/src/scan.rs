@@ -0,0 +1,1974 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    fs::{self, Metadata},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use crossbeam::channel::Sender;
+use serde::Serializer;
+use superconsole::{Component, DrawMode, Lines};
+use yansi::{Color::Red, Paint};
+use yara_x::{Rule, Rules, ScanError, ScanResults, Scanner};
+
+use crate::{
+    anomaly::{self, FilenameAnomaly},
+    buffer::{self, FileBuffer, MemoryBudget, MemoryReservation},
+    control::ControlFile,
+    decompress::{self, Compression, DecompressLimits},
+    embedded::{self, EmbeddedLimits},
+    empty::{self, EmptyFile},
+    filter::{self, PathGlobs},
+    inode::InodeTracker,
+    magic::{self, Definitions},
+    profile::ScanProfile,
+    rescan::MatchedFiles,
+    retry::ScanRetries,
+    rules::{self, CompilerOptions, RuleTarget},
+    semaphore::Semaphore,
+    skips::{self, FilterStage, FilterTrace, SkipLog, SkipReason},
+    userid,
+    walk::{Message, ParWalker, WalkOrder, WalkOutput},
+};
+
+/// Matches of rules scoring this or less are not reported by default.
+pub const DEFAULT_MINIMUM_SCORE: u32 = 40;
+
+/// Files bigger than this are not scanned by default.
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// What [`scan`] scans, with which rules and how.
+#[derive(Clone)]
+pub struct ScanConfig {
+    /// Rule file, or folder of rule files.
+    pub rules: PathBuf,
+    pub folders: Vec<PathBuf>,
+    /// Labels of the volumes the folders come from, in the same order.
+    pub volume_labels: Vec<String>,
+    /// Whether the folders are single files, each scanned with its parent
+    /// as the root.
+    pub single_file: bool,
+    /// The users are read from this file rather than from the `etc/passwd`
+    /// of each folder.
+    pub passwd: Option<PathBuf>,
+    /// Whether the folders are walked at once, sharing the scanning threads,
+    /// rather than one after the other.
+    pub parallel_folders: bool,
+    /// Only matches of rules scoring more than this are reported.
+    pub minimum_score: u32,
+    /// Files bigger than this are skipped.
+    pub max_size: u64,
+    /// Files smaller than this are skipped.
+    pub min_size: u64,
+    /// Only the setuid and setgid files are scanned.
+    pub setuid_only: bool,
+    /// Magic files setting the `filetype` of the scanned files, the
+    /// earlier ones taking precedence.
+    pub magic: Vec<PathBuf>,
+    /// Number of scanning threads, as many as cores by default.
+    pub threads: Option<u8>,
+    /// Number of messages that can be waiting to be output, unbounded by
+    /// default.
+    pub output_buffer: Option<usize>,
+    pub max_depth: Option<usize>,
+    /// No more files are scanned once this is reached.
+    pub deadline: Option<Instant>,
+    /// Whether the messages of each file are output in the order the files
+    /// are found.
+    pub ordered_output: bool,
+    pub follow_links: bool,
+    pub order: WalkOrder,
+    /// Only the files whose path relative to their folder passes these are
+    /// scanned.
+    pub path_globs: Option<Arc<PathGlobs>>,
+    /// Whether symlinks resolving outside of their folder are skipped.
+    pub contain_symlinks: bool,
+    /// Files with the same size and modification time as the file at the
+    /// same place in this folder are skipped.
+    pub baseline_mtime_dir: Option<PathBuf>,
+    /// Whether the hardlinks to a file already scanned are reported as its
+    /// aliases instead of being scanned again.
+    pub dedupe_inodes: bool,
+    /// Maximum number of files open at once.
+    pub max_open_files: Option<usize>,
+    /// Maximum number of bytes of the files read into memory at once.
+    pub max_memory: Option<u64>,
+    /// Whether the files are read into memory before being scanned, to tell
+    /// those that return fewer bytes than their size.
+    pub detect_truncated: bool,
+    /// Number of times a scan failing with a transient error is retried.
+    pub scan_retries: u32,
+    pub scan_timeout: Option<Duration>,
+    /// The scan is paused while this file exists.
+    pub control_file: Option<PathBuf>,
+    /// Whether the messages logged by rules through the `console` module are
+    /// output.
+    pub rule_console: bool,
+    pub filename_anomalies: bool,
+    /// Whether the files without any content are reported.
+    pub flag_empty: bool,
+    /// The base64 and hex blobs embedded in text files are decoded and
+    /// scanned within these limits, if set.
+    pub decode_embedded: Option<EmbeddedLimits>,
+    /// Compressed files are decompressed and scanned within these limits,
+    /// if set.
+    pub decompress: Option<DecompressLimits>,
+    /// Whether the path of every file is also scanned, with the rules whose
+    /// `target` metadata is `path`.
+    pub scan_paths: bool,
+    /// Whether the files matching any rule are only reported once scanned
+    /// again, after every file is scanned. The first scan skips what the
+    /// files contain and their paths, scanned along with the files again.
+    pub rescan_matches: bool,
+    pub skip_log: Option<Arc<SkipLog>>,
+    pub filter_trace: Option<Arc<FilterTrace>>,
+    pub profile: Option<Arc<ScanProfile>>,
+    /// Drawn on the console while scanning, with [`WalkOutput::Console`].
+    pub view: Option<Arc<dyn Component + Send + Sync>>,
+    /// Updated as the files are scanned.
+    pub counters: Arc<ScanCounters>,
+    /// Where the messages sent by the handler are output, dropped by
+    /// default.
+    pub output: WalkOutput,
+}
+
+impl ScanConfig {
+    /// Scans `folders` with the rules at `rules`, with the same defaults as
+    /// the command line.
+    pub fn new(rules: PathBuf, folders: Vec<PathBuf>) -> Self {
+        Self {
+            rules,
+            folders,
+            volume_labels: Vec::new(),
+            single_file: false,
+            passwd: None,
+            parallel_folders: false,
+            minimum_score: DEFAULT_MINIMUM_SCORE,
+            max_size: DEFAULT_MAX_SIZE,
+            min_size: 0,
+            setuid_only: false,
+            magic: Vec::new(),
+            threads: None,
+            output_buffer: None,
+            max_depth: None,
+            deadline: None,
+            ordered_output: false,
+            follow_links: false,
+            order: WalkOrder::default(),
+            path_globs: None,
+            contain_symlinks: false,
+            baseline_mtime_dir: None,
+            dedupe_inodes: false,
+            max_open_files: None,
+            max_memory: None,
+            detect_truncated: false,
+            scan_retries: 0,
+            scan_timeout: None,
+            control_file: None,
+            rule_console: false,
+            filename_anomalies: false,
+            flag_empty: false,
+            decode_embedded: None,
+            decompress: None,
+            scan_paths: false,
+            rescan_matches: false,
+            skip_log: None,
+            filter_trace: None,
+            profile: None,
+            view: None,
+            counters: Default::default(),
+            output: WalkOutput::Discard,
+        }
+    }
+
+    /// The filter stages a file goes through, for the filter trace.
+    fn filter_stages(&self) -> Vec<FilterStage> {
+        [
+            (self.path_globs.is_some(), FilterStage::PathGlobs),
+            (self.contain_symlinks, FilterStage::ContainSymlinks),
+            (true, FilterStage::MaxSize),
+            (self.min_size > 0, FilterStage::MinSize),
+            (self.setuid_only, FilterStage::SetuidOnly),
+            (self.baseline_mtime_dir.is_some(), FilterStage::Baseline),
+            (self.dedupe_inodes, FilterStage::DedupeInodes),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, stage)| enabled.then_some(stage))
+        .collect()
+    }
+}
+
+/// Counts of the files, updated while they are scanned.
+#[derive(Debug, Default)]
+pub struct ScanCounters {
+    pub scanned: AtomicUsize,
+    /// Files matched by at least one rule, whatever its score.
+    pub matched: AtomicUsize,
+    /// Files, or contents extracted from them, with matches reported above
+    /// the minimum score. Counted by the output handler reporting them.
+    pub reported: AtomicUsize,
+    /// Files whose scan was abandoned after timing out.
+    pub timed_out: AtomicUsize,
+    /// Files bigger than the maximum size, not scanned.
+    pub skipped_size: AtomicUsize,
+    /// Files smaller than the minimum size, not scanned.
+    pub skipped_small: AtomicUsize,
+}
+
+impl ScanCounters {
+    /// Counts a scanned file, and whether it matched.
+    pub fn count_file(&self, matched: bool) {
+        self.scanned.fetch_add(1, Ordering::Relaxed);
+        if matched {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Sums up the counts of a scan with `num_rules` rules, which ran into
+    /// `errors` and took `duration`.
+    pub fn summary(
+        &self,
+        num_rules: usize,
+        errors: Vec<String>,
+        duration: Duration,
+    ) -> ScanSummary {
+        ScanSummary {
+            num_rules,
+            scanned_files: self.scanned.load(Ordering::Relaxed),
+            matching_files: self.matched.load(Ordering::Relaxed),
+            skipped_size_files: self.skipped_size.load(Ordering::Relaxed),
+            skipped_small_files: self.skipped_small.load(Ordering::Relaxed),
+            timed_out_files: self.timed_out.load(Ordering::Relaxed),
+            errors,
+            duration,
+            peak_rss: None,
+        }
+    }
+}
+
+/// Counts of a finished [`scan`], reported as JSON by `--summary`.
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanSummary {
+    #[serde(skip)]
+    pub num_rules: usize,
+    #[serde(rename = "files_scanned")]
+    pub scanned_files: usize,
+    /// Files matched by at least one rule, whatever its score. The output
+    /// handler may count the files whose matches it reported instead, see
+    /// [`ScanCounters::reported`].
+    #[serde(rename = "files_matched")]
+    pub matching_files: usize,
+    /// Files bigger than the maximum size, not scanned.
+    #[serde(rename = "files_skipped_size")]
+    pub skipped_size_files: usize,
+    /// Files smaller than the minimum size, not scanned.
+    #[serde(rename = "files_skipped_small")]
+    pub skipped_small_files: usize,
+    /// Files whose scan was abandoned after timing out.
+    #[serde(rename = "files_timed_out")]
+    pub timed_out_files: usize,
+    /// Errors on files that could not be scanned.
+    #[serde(rename = "files_errored", serialize_with = "serialize_len")]
+    pub errors: Vec<String>,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+    /// Peak resident memory of the process in bytes, if measured.
+    #[serde(rename = "peak_rss_bytes", skip_serializing_if = "Option::is_none")]
+    pub peak_rss: Option<u64>,
+}
+
+fn serialize_len<S: Serializer>(errors: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(errors.len() as u64)
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// Scans the folders of `config`, passing every scanned file to `handler`.
+///
+/// Any rule compilation error fails the scan. The messages sent by
+/// `handler` go to `config.output`.
+pub fn scan(config: ScanConfig, handler: &dyn OutputHandler) -> anyhow::Result<ScanSummary> {
+    let options = CompilerOptions::default();
+    let mut compiler = rules::new_compiler(&options)?;
+    rules::add_rules_from(&mut compiler, &config.rules, &options)?;
+    if let Some(err) = compiler.errors().first() {
+        bail!("{}", err);
+    }
+    scan_with_rules(&compiler.build(), &config, handler)
+}
+
+/// Like [`scan`], with `rules` already compiled instead of the ones at
+/// `config.rules`. They must come from [`rules::new_compiler`], which
+/// declares the variables set for every file.
+pub fn scan_with_rules(
+    rules: &Rules,
+    config: &ScanConfig,
+    handler: &dyn OutputHandler,
+) -> anyhow::Result<ScanSummary> {
+    let parsed = config
+        .magic
+        .iter()
+        .map(|path| {
+            magic::load_definitions_file(path, true)
+                .map(Option::unwrap_or_default)
+                .map_err(|err| anyhow!("{}", err))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let (definitions, max_signature_len) = magic::merge_definitions(parsed);
+    let new_state = |folders: &[(PathBuf, Option<String>)]| {
+        let roots = folders
+            .iter()
+            .map(|(path, label)| {
+                let passwd = config.passwd.as_deref();
+                if config.single_file {
+                    ScanRoot::file(path, label.clone(), passwd)
+                } else {
+                    ScanRoot::new(path, label.clone(), passwd)
+                }
+            })
+            .collect();
+        let state = ScanState::new(definitions.clone(), max_signature_len, roots);
+        match &config.view {
+            Some(view) => state.with_view(view.clone()),
+            None => state,
+        }
+    };
+
+    let started = Instant::now();
+    let scanner = FileScanner::new(rules, config);
+
+    // Folders are scanned one after the other, or all in a single walk
+    // sharing the scanning threads.
+    let labels = config.volume_labels.iter().cloned().map(Some);
+    let folders: Vec<_> = config
+        .folders
+        .iter()
+        .cloned()
+        .zip(labels.chain(std::iter::repeat(None)))
+        .collect();
+    let batches: Vec<_> = if config.parallel_folders || folders.is_empty() {
+        vec![folders.as_slice()]
+    } else {
+        folders.chunks(1).collect()
+    };
+
+    // With `rescan_matches`, the files that matched are only reported once
+    // scanned again after the walk.
+    let matched_files = config.rescan_matches.then(|| MatchedFiles::new(handler));
+    let walk_handler: &dyn OutputHandler = match &matched_files {
+        Some(matched_files) => matched_files,
+        None => handler,
+    };
+    for (i, batch) in batches.iter().enumerate() {
+        let walker = ParWalker::paths(batch.iter().map(|(path, _)| path.as_path()));
+        let last = matched_files.is_none() && i + 1 == batches.len();
+        scanner.walk(new_state(batch), walker, walk_handler, Pass::Scan, last)?;
+    }
+    if let Some(matched_files) = matched_files {
+        let paths = matched_files.into_paths();
+        config.output.send(Message::Error(format!(
+            "[+] Rescanning {} matching files",
+            paths.len()
+        )));
+        let walker = ParWalker::files(&paths);
+        scanner.walk(new_state(&folders), walker, handler, Pass::Rescan, true)?;
+    }
+
+    Ok(config.counters.summary(
+        rules.iter().len(),
+        scanner.errors.into_inner().unwrap(),
+        started.elapsed(),
+    ))
+}
+
+/// Which of the walks of a scan a file is scanned in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    /// Every file found is filtered, then scanned.
+    Scan,
+    /// The files that matched are scanned again, along with what they
+    /// contain and their paths. They were filtered and their anomalies
+    /// reported already.
+    Rescan,
+}
+
+/// The scanners owned by each walker thread.
+struct ThreadScanner<'r> {
+    scanner: Scanner<'r>,
+    /// Messages logged through the `console` module during the current scan.
+    console: Rc<RefCell<Vec<String>>>,
+}
+
+impl<'r> ThreadScanner<'r> {
+    fn new(rules: &'r Rules, config: &'r ScanConfig) -> Self {
+        let mut scanner = Scanner::new(rules);
+        let console: Rc<RefCell<Vec<String>>> = Default::default();
+        if config.rule_console {
+            let messages = console.clone();
+            scanner.console_log(move |message| messages.borrow_mut().push(message));
+        }
+        if let Some(timeout) = config.scan_timeout {
+            scanner.set_timeout(timeout);
+        }
+        Self { scanner, console }
+    }
+}
+
+/// Filters and scans the files found by the walks of a scan, shared by the
+/// scanning threads.
+struct FileScanner<'a> {
+    rules: &'a Rules,
+    config: &'a ScanConfig,
+    /// Decided once for all the files, from the modules the rules import.
+    full_file_rules: bool,
+    filter_stages: Vec<FilterStage>,
+    inodes: InodeTracker,
+    open_files: Option<Semaphore>,
+    memory_budget: Option<MemoryBudget>,
+    control: Option<ControlFile>,
+    errors: Mutex<Vec<String>>,
+}
+
+impl<'a> FileScanner<'a> {
+    fn new(rules: &'a Rules, config: &'a ScanConfig) -> Self {
+        Self {
+            rules,
+            config,
+            full_file_rules: rules::needs_full_file(rules),
+            filter_stages: config.filter_stages(),
+            inodes: InodeTracker::default(),
+            open_files: config.max_open_files.map(Semaphore::new),
+            memory_budget: config.max_memory.map(MemoryBudget::new),
+            control: config.control_file.as_deref().map(ControlFile::new),
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Scans the files found by `walker`, passing them to `handler`, whose
+    /// `on_done` is called at the end if `last`.
+    fn walk(
+        &self,
+        state: ScanState,
+        mut walker: ParWalker<'_>,
+        handler: &dyn OutputHandler,
+        pass: Pass,
+        last: bool,
+    ) -> anyhow::Result<()> {
+        let config = self.config;
+        if let Some(n) = config.output_buffer {
+            walker.output_buffer(n);
+        }
+        if let Some(n) = config.threads {
+            walker.num_threads(n);
+        }
+        if let Some(n) = config.max_depth {
+            walker.max_depth(n);
+        }
+        if let Some(deadline) = config.deadline {
+            walker.deadline(deadline);
+        }
+        walker
+            .ordered_output(config.ordered_output)
+            .follow_links(config.follow_links)
+            .order(config.order)
+            .output(config.output.clone());
+        walker
+            .walk(
+                state,
+                |_, _| ThreadScanner::new(self.rules, config),
+                |state, output, file_path, thread| {
+                    let scanned = self.scan_file(state, output, &file_path, thread, handler, pass);
+                    // Whatever failed, the next file scanned by this thread
+                    // must not inherit the variables of this one.
+                    ScanState::reset_globals(&mut thread.scanner)?;
+                    scanned
+                },
+                |_, _| {},
+                |output| {
+                    if last {
+                        handler.on_done(output);
+                    }
+                },
+                |err, output| {
+                    let error = err.to_string();
+                    let root_cause = err.root_cause().to_string();
+                    let msg = if error != root_cause {
+                        format!("{} {}: {}", "error: ".paint(Red).bold(), error, root_cause)
+                    } else {
+                        format!("{}: {}", "error: ".paint(Red).bold(), error)
+                    };
+                    let _ = output.send(Message::Error(msg));
+                    self.errors.lock().unwrap().push(format!("{:#}", err));
+                    Ok(())
+                },
+            )
+            .map_err(|_| anyhow!("a scanning thread panicked"))
+    }
+
+    /// Records that `path` went through the filters, or which one skipped it.
+    fn trace(&self, path: &Path, skipped: Option<SkipReason>) {
+        if let Some(filter_trace) = &self.config.filter_trace {
+            filter_trace.record(path, &self.filter_stages, skipped);
+        }
+    }
+
+    fn skip(&self, path: &Path, reason: SkipReason) {
+        if let Some(skip_log) = &self.config.skip_log {
+            skip_log.record(path, reason);
+        }
+        self.trace(path, Some(reason));
+    }
+
+    /// Returns the metadata of `file_path` if it goes through the filters,
+    /// or `None` if it is skipped.
+    fn filter(
+        &self,
+        root: Option<&ScanRoot>,
+        output: &Sender<Message>,
+        file_path: &Path,
+        handler: &dyn OutputHandler,
+    ) -> anyhow::Result<Option<Metadata>> {
+        let config = self.config;
+        if let Some(path_globs) = &config.path_globs {
+            // A file scanned on its own is its own root.
+            let relative = root
+                .and_then(|root| filter::relative_to_root(file_path, &root.path))
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .unwrap_or(file_path);
+            if !path_globs.is_scanned(relative) {
+                self.skip(file_path, SkipReason::ExcludedPath);
+                return Ok(None);
+            }
+        }
+        if let (true, Some(root)) = (config.contain_symlinks, root) {
+            if let Some(resolved) = filter::resolved_outside_root(file_path, &root.path)? {
+                let _ = output.send(Message::Error(format!(
+                    "[-] Not scanning {}: it resolves to {}, outside {}",
+                    file_path.display(),
+                    resolved.display(),
+                    root.path.display()
+                )));
+                self.skip(file_path, SkipReason::SymlinkOutsideRoot);
+                return Ok(None);
+            }
+        }
+        let metadata = fs::metadata(file_path)?;
+        if let Some(reason) = skips::skip_by_metadata(
+            &metadata,
+            config.min_size,
+            config.max_size,
+            config.setuid_only,
+        ) {
+            if reason == SkipReason::TooLarge {
+                config.counters.skipped_size.fetch_add(1, Ordering::Relaxed);
+            } else if reason == SkipReason::TooSmall {
+                config
+                    .counters
+                    .skipped_small
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            self.skip(file_path, reason);
+            return Ok(None);
+        }
+        if let (Some(baseline), Some(root)) = (&config.baseline_mtime_dir, root) {
+            if !filter::differs_from_baseline(file_path, &metadata, &root.path, baseline) {
+                self.skip(file_path, SkipReason::UnchangedFromBaseline);
+                return Ok(None);
+            }
+        }
+        if config.dedupe_inodes {
+            if let Some(original) =
+                self.inodes
+                    .first_seen(metadata.dev(), metadata.ino(), file_path)
+            {
+                handler.on_file_aliased(file_path, &original, output);
+                self.skip(file_path, SkipReason::DuplicateInode);
+                return Ok(None);
+            }
+        }
+        self.trace(file_path, None);
+        Ok(Some(metadata))
+    }
+
+    /// Reads the whole file at `file_path`, `len` bytes long, to scan what it
+    /// contains, holding its bytes from the memory budget. Returns `None`,
+    /// after reporting why, if it can not be read or doesn't fit.
+    fn read_content(
+        &self,
+        output: &Sender<Message>,
+        file_path: &Path,
+        len: u64,
+    ) -> Option<(Vec<u8>, Option<MemoryReservation<'_>>)> {
+        let reservation = match &self.memory_budget {
+            Some(budget) => match budget.try_reserve(len) {
+                Some(reservation) => Some(reservation),
+                None => {
+                    let _ = output.send(Message::Error(format!(
+                        "[-] Not scanning what {} contains: it doesn't fit in the memory budget",
+                        file_path.display()
+                    )));
+                    return None;
+                }
+            },
+            None => None,
+        };
+        match fs::read(file_path) {
+            Ok(data) => Some((data, reservation)),
+            Err(err) => {
+                let _ = output.send(Message::Error(format!(
+                    "[-] Can not read {}: {}",
+                    file_path.display(),
+                    err
+                )));
+                None
+            }
+        }
+    }
+
+    /// Scans the file at `file_path` and what it contains, passing the
+    /// results to `handler`.
+    fn scan_file(
+        &self,
+        state: &ScanState,
+        output: &Sender<Message>,
+        file_path: &Path,
+        thread: &mut ThreadScanner<'_>,
+        handler: &dyn OutputHandler,
+        pass: Pass,
+    ) -> anyhow::Result<()> {
+        let config = self.config;
+        if let Some(control) = &self.control {
+            if control.is_paused() {
+                let _ = output.send(Message::Error(format!(
+                    "[+] Paused until {} is removed",
+                    config.control_file.as_ref().unwrap().display()
+                )));
+                control.wait_while_paused();
+            }
+        }
+        let scanner = &mut thread.scanner;
+        let root = state.root_of(file_path);
+        let metadata = match pass {
+            Pass::Scan => match self.filter(root, output, file_path, handler)? {
+                Some(metadata) => metadata,
+                None => return Ok(()),
+            },
+            Pass::Rescan => fs::metadata(file_path)?,
+        };
+        let _permit = self.open_files.as_ref().map(|s| s.acquire());
+
+        let owner = root.and_then(|root| root.users.get(&metadata.uid()));
+        let group = root.and_then(|root| root.groups.get(&metadata.gid()));
+        state.set_globals(
+            scanner,
+            file_path,
+            &metadata,
+            owner.map(String::as_str),
+            group.map(String::as_str),
+        )?;
+
+        // Held until the scan is done, releasing the buffered bytes from the
+        // memory budget.
+        let reservation = match &self.memory_budget {
+            Some(budget) if config.detect_truncated => budget.try_reserve(metadata.len()).map(Some),
+            _ => Some(None),
+        };
+        if reservation.is_none() {
+            let _ = output.send(Message::Error(format!(
+                "[-] {} doesn't fit in the memory budget, scanning without buffering",
+                file_path.display()
+            )));
+        }
+
+        let buffer = if config.detect_truncated && reservation.is_some() {
+            let buffer = buffer::read_file(file_path, metadata.len())?;
+            if buffer.is_truncated() {
+                let _ = output.send(Message::Error(format!(
+                    "[-] {} is truncated: read {} of {} bytes, matches may be partial",
+                    file_path.display(),
+                    buffer.data.len(),
+                    buffer.expected_len
+                )));
+            }
+            Some(buffer)
+        } else {
+            None
+        };
+
+        let scan_start = Instant::now();
+        let mut retries = ScanRetries::new(config.scan_retries);
+        let scan_results = loop {
+            let scan_results =
+                scan_buffer_or_file(scanner, file_path, buffer.as_ref(), self.full_file_rules);
+            match scan_results {
+                Err(err) if retries.retry(&err) => {
+                    let _ = output.send(Message::Error(format!(
+                        "[-] Retrying {}: {}",
+                        file_path.display(),
+                        err
+                    )));
+                }
+                scan_results => break scan_results,
+            }
+        };
+        if let (Some(profile), Pass::Scan) = (&config.profile, pass) {
+            profile.record(file_path, scan_start.elapsed());
+        }
+        let scan_results = match scan_results {
+            Err(ScanError::Timeout) => {
+                let _ = output.send(Message::Error(format!(
+                    "[-] Timed out scanning {} after {} seconds, skipping it",
+                    file_path.display(),
+                    config.scan_timeout.unwrap_or_default().as_secs()
+                )));
+                config.counters.timed_out.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            scan_results => scan_results?,
+        };
+        let matched = RuleTarget::Content.matching_rules(&scan_results);
+        let mut matched_count = matched.len();
+
+        let console = take_console(&thread.console, output, &file_path.display());
+
+        let mut scanned_file = ScannedFile::new(file_path);
+        scanned_file.truncated = buffer.as_ref().is_some_and(|b| b.is_truncated());
+        scanned_file.console = &console;
+        scanned_file.metadata = Some(&metadata);
+        scanned_file.uid = Some(metadata.uid());
+        scanned_file.gid = Some(metadata.gid());
+        scanned_file.owner = owner.map(String::as_str);
+        scanned_file.group = group.map(String::as_str);
+        scanned_file.volume_label = root.and_then(|root| root.volume_label.as_deref());
+        scanned_file.rescanned = pass == Pass::Rescan;
+        handler.on_file_scanned(&scanned_file, &matched, output, config.minimum_score);
+
+        if pass == Pass::Scan {
+            if config.filename_anomalies {
+                let anomalies = anomaly::filename_anomalies(file_path);
+                if !anomalies.is_empty() {
+                    handler.on_filename_anomaly(
+                        &scanned_file,
+                        &anomalies,
+                        output,
+                        config.minimum_score,
+                    );
+                }
+            }
+            if config.flag_empty {
+                match empty::check_empty(file_path, metadata.len()) {
+                    Ok(Some(kind)) => {
+                        handler.on_empty_file(&scanned_file, kind, output, config.minimum_score)
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        let _ = output.send(Message::Error(format!(
+                            "[-] Can not check whether {} is empty: {}",
+                            file_path.display(),
+                            err
+                        )));
+                    }
+                }
+            }
+        }
+
+        // With `rescan_matches`, the first pass only picks the files to scan
+        // again, what they contain and their paths are scanned with them.
+        let extract = pass == Pass::Rescan || !config.rescan_matches;
+        if extract {
+            // The file is read at most once, for both the embedded blobs and
+            // the decompressed content.
+            let embedded_limits = config.decode_embedded.as_ref();
+            let decompress_limits = config.decompress.as_ref();
+            if embedded_limits.is_some() || decompress_limits.is_some() {
+                let head = match &buffer {
+                    Some(buffer) => buffer.data[..buffer.data.len().min(8192)].to_vec(),
+                    None => magic::read_first_bytes(file_path.to_str().unwrap_or(""), 8192)
+                        .unwrap_or_default(),
+                };
+                let embedded_limits = embedded_limits.filter(|_| embedded::looks_like_text(&head));
+                let compression = decompress_limits
+                    .and_then(|limits| Some((Compression::detect(&head)?, limits)));
+                let read;
+                let content = match &buffer {
+                    Some(buffer) => Some(buffer.data.as_slice()),
+                    None if embedded_limits.is_some() || compression.is_some() => {
+                        read = self.read_content(output, file_path, metadata.len());
+                        read.as_ref().map(|(data, _)| data.as_slice())
+                    }
+                    None => None,
+                };
+
+                if let (Some(limits), Some(content)) = (embedded_limits, content) {
+                    match scan_embedded(
+                        scanner,
+                        &thread.console,
+                        &scanned_file,
+                        content,
+                        limits,
+                        handler,
+                        output,
+                        config.minimum_score,
+                    ) {
+                        Ok(count) => matched_count += count,
+                        Err(err) => {
+                            let _ = output.send(Message::Error(format!(
+                                "[-] Can not scan the decoded blobs of {}: {:#}",
+                                file_path.display(),
+                                err
+                            )));
+                        }
+                    }
+                }
+
+                if let (Some((compression, limits)), Some(content)) = (compression, content) {
+                    match scan_decompressed(
+                        scanner,
+                        &thread.console,
+                        &scanned_file,
+                        compression,
+                        content,
+                        limits,
+                        handler,
+                        output,
+                        config.minimum_score,
+                    ) {
+                        Ok(count) => matched_count += count,
+                        Err(err) => {
+                            let _ = output.send(Message::Error(format!(
+                                "[-] Can not scan the decompressed content of {}: {:#}",
+                                file_path.display(),
+                                err
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+
+        if config.scan_paths && extract {
+            match scan_path(
+                scanner,
+                &scanned_file,
+                handler,
+                output,
+                config.minimum_score,
+            ) {
+                Ok(count) => matched_count += count,
+                Err(err) => {
+                    let _ = output.send(Message::Error(format!(
+                        "[-] Can not scan the path {}: {:#}",
+                        file_path.display(),
+                        err
+                    )));
+                }
+            }
+        }
+
+        // The files scanned again were counted the first time.
+        if pass == Pass::Scan {
+            config.counters.count_file(matched_count > 0);
+        }
+
+        Ok(())
+    }
+}
+
+/// Scans `buffer`, the content of the file at `path` read into memory, or
+/// the file itself without one. A truncated buffer misses the end of the
+/// file, which rules using modules like `pe` need for parsing it, so with
+/// `full_file` the file is scanned from disk instead.
+fn scan_buffer_or_file<'a, 'r>(
+    scanner: &'a mut Scanner<'r>,
+    path: &'a Path,
+    buffer: Option<&'a FileBuffer>,
+    full_file: bool,
+) -> Result<ScanResults<'a, 'r>, ScanError> {
+    match buffer {
+        Some(buffer) if !(full_file && buffer.is_truncated()) => scanner.scan(&buffer.data),
+        _ => scanner.scan_file(path),
+    }
+}
+
+/// Scans the path of `file` with `scanner`, reporting the matches of the
+/// rules whose `target` metadata is `path` to `handler` like matches of the
+/// file. Returns the number of matching rules.
+fn scan_path(
+    scanner: &mut Scanner<'_>,
+    file: &ScannedFile<'_>,
+    handler: &dyn OutputHandler,
+    output: &Sender<Message>,
+    minimum_score: u32,
+) -> anyhow::Result<usize> {
+    let results = scanner.scan(file.path.as_os_str().as_bytes())?;
+    let matched = RuleTarget::Path.matching_rules(&results);
+    let path_file = ScannedFile {
+        truncated: false,
+        console: &[],
+        extracted: None,
+        ..*file
+    };
+    handler.on_file_scanned(&path_file, &matched, output, minimum_score);
+    Ok(matched.len())
+}
+
+/// Takes the messages logged through the `console` module by the last scan,
+/// of `name`, and outputs them.
+fn take_console(
+    console: &RefCell<Vec<String>>,
+    output: &Sender<Message>,
+    name: &dyn std::fmt::Display,
+) -> Vec<String> {
+    let messages = std::mem::take(&mut *console.borrow_mut());
+    for message in &messages {
+        let _ = output.send(Message::Error(format!(
+            "[+] console: {}: {}",
+            name, message
+        )));
+    }
+    messages
+}
+
+/// Scans the base64 and hex blobs embedded in `content`, the contents of
+/// `file`, reporting them to `handler`. Returns the number of matching rules.
+#[allow(clippy::too_many_arguments)]
+fn scan_embedded(
+    scanner: &mut Scanner<'_>,
+    console: &RefCell<Vec<String>>,
+    file: &ScannedFile<'_>,
+    content: &[u8],
+    limits: &EmbeddedLimits,
+    handler: &dyn OutputHandler,
+    output: &Sender<Message>,
+    minimum_score: u32,
+) -> anyhow::Result<usize> {
+    let mut matched_count = 0;
+    for blob in embedded::find_embedded_blobs(content, limits) {
+        let results = scanner.scan(&blob.data)?;
+        let matched = RuleTarget::Content.matching_rules(&results);
+        matched_count += matched.len();
+        let suffix = format!("#decoded@{}", blob.offset);
+        let messages = take_console(
+            console,
+            output,
+            &format_args!("{}{}", file.path.display(), suffix),
+        );
+        let decoded = ScannedFile {
+            console: &messages,
+            extracted: Some(Extracted {
+                suffix,
+                data: &blob.data,
+            }),
+            ..*file
+        };
+        handler.on_file_scanned(&decoded, &matched, output, minimum_score);
+    }
+    Ok(matched_count)
+}
+
+/// Decompresses `content`, the content of `file` compressed with
+/// `compression`, scans it and passes the results to `handler` under the
+/// file's path suffixed with the format. Decompressed content that is
+/// compressed again is decompressed in turn, within `limits`, each layer
+/// adding its format to the suffix. Returns the number of matching rules.
+#[allow(clippy::too_many_arguments)]
+fn scan_decompressed(
+    scanner: &mut Scanner<'_>,
+    console: &RefCell<Vec<String>>,
+    file: &ScannedFile<'_>,
+    compression: Compression,
+    content: &[u8],
+    limits: &DecompressLimits,
+    handler: &dyn OutputHandler,
+    output: &Sender<Message>,
+    minimum_score: u32,
+) -> anyhow::Result<usize> {
+    let mut matched_count = 0;
+    let mut budget = limits.max_bytes;
+    let mut suffix = String::new();
+    let mut compression = compression;
+    let mut layer = Cow::Borrowed(content);
+    for depth in 1.. {
+        if depth > limits.max_depth {
+            let _ = output.send(Message::Error(format!(
+                "[-] Not decompressing {}{}: nested deeper than --max-archive-depth {}",
+                file.path.display(),
+                suffix,
+                limits.max_depth
+            )));
+            break;
+        }
+        if budget == 0 {
+            let _ = output.send(Message::Error(format!(
+                "[-] Not decompressing {}{}: the --decompress-max-bytes budget is used up",
+                file.path.display(),
+                suffix
+            )));
+            break;
+        }
+        // The layers already scanned stay reported.
+        let data = match decompress::decompress(compression, &layer, budget) {
+            Ok(data) => data,
+            Err(err) => {
+                let _ = output.send(Message::Error(format!(
+                    "[-] Can not decompress the {} data of {}{}: {}",
+                    compression.name(),
+                    file.path.display(),
+                    suffix,
+                    err
+                )));
+                break;
+            }
+        };
+        budget -= data.len() as u64;
+        suffix.push_str(&format!("#{}", compression.name()));
+
+        let results = scanner.scan(&data)?;
+        let matched = RuleTarget::Content.matching_rules(&results);
+        let messages = take_console(
+            console,
+            output,
+            &format_args!("{}{}", file.path.display(), suffix),
+        );
+        let decompressed = ScannedFile {
+            console: &messages,
+            extracted: Some(Extracted {
+                suffix: suffix.clone(),
+                data: &data,
+            }),
+            ..*file
+        };
+        handler.on_file_scanned(&decompressed, &matched, output, minimum_score);
+        matched_count += matched.len();
+
+        match Compression::detect(&data) {
+            Some(nested) => {
+                compression = nested;
+                layer = Cow::Owned(data);
+            }
+            None => break,
+        }
+    }
+    Ok(matched_count)
+}
+
+// Taken from yara-x/cli/src/commands/scan.rs
+pub struct ScanState {
+    definitions: Definitions,
+    /// Length of the longest magic signature.
+    max_signature_len: usize,
+    roots: Vec<ScanRoot>,
+    /// Drawn on the console while scanning, if set.
+    view: Option<Arc<dyn Component + Send + Sync>>,
+}
+
+/// A folder being scanned.
+pub struct ScanRoot {
+    pub path: PathBuf,
+    /// Users parsed from the folder's `/etc/passwd`, or the `--passwd-path`,
+    /// by UID.
+    pub users: HashMap<u32, String>,
+    /// Groups parsed from the folder's `/etc/group`, by GID.
+    pub groups: HashMap<u32, String>,
+    /// Label of the volume the folder comes from, if any.
+    pub volume_label: Option<String>,
+}
+
+impl ScanRoot {
+    /// A folder being scanned, whose users are read from `passwd` if set,
+    /// or from its `etc/passwd` otherwise.
+    pub fn new(path: &Path, volume_label: Option<String>, passwd: Option<&Path>) -> Self {
+        let passwd = passwd.map_or_else(|| path.join("etc/passwd"), Path::to_path_buf);
+        Self {
+            path: path.to_path_buf(),
+            users: Self::read_users(&passwd),
+            groups: Self::read_groups(path),
+            volume_label,
+        }
+    }
+
+    /// A single file being scanned, whose folder is used as the root. Its
+    /// users are only known if `passwd` is set, its groups are read from the
+    /// `etc/group` under the root like for a folder.
+    pub fn file(path: &Path, volume_label: Option<String>, passwd: Option<&Path>) -> Self {
+        let root = path.parent().unwrap_or(path);
+        Self {
+            path: root.to_path_buf(),
+            users: passwd.map(Self::read_users).unwrap_or_default(),
+            groups: Self::read_groups(root),
+            volume_label,
+        }
+    }
+
+    fn read_groups(root: &Path) -> HashMap<u32, String> {
+        userid::get_groupnames_from_group(root.join("etc/group").to_str().unwrap_or(""))
+    }
+
+    fn read_users(passwd: &Path) -> HashMap<u32, String> {
+        eprintln!("[+] Parsing users from {}", passwd.display());
+        let users =
+            userid::get_usernames_from_passwd(passwd.to_str().unwrap_or("")).unwrap_or_default();
+        if users.is_empty() {
+            eprintln!("[-] No users found in {}", passwd.display());
+        } else {
+            eprintln!("[+] {} users found", users.len());
+        }
+        users
+    }
+}
+
+impl ScanState {
+    pub fn new(definitions: Definitions, max_signature_len: usize, roots: Vec<ScanRoot>) -> Self {
+        Self {
+            definitions,
+            max_signature_len,
+            roots,
+            view: None,
+        }
+    }
+
+    /// Draws `view` on the console while scanning, instead of nothing.
+    pub fn with_view(mut self, view: Arc<dyn Component + Send + Sync>) -> Self {
+        self.view = Some(view);
+        self
+    }
+
+    /// Returns the innermost scanned folder containing `file_path`.
+    pub fn root_of(&self, file_path: &Path) -> Option<&ScanRoot> {
+        self.roots
+            .iter()
+            .filter(|root| filter::relative_to_root(file_path, &root.path).is_some())
+            .max_by_key(|root| root.path.components().count())
+    }
+
+    /// Sets the external variables describing `file_path` before it is
+    /// scanned, with its `filetype` from the magics.
+    pub fn set_globals(
+        &self,
+        scanner: &mut Scanner<'_>,
+        file_path: &Path,
+        metadata: &Metadata,
+        owner: Option<&str>,
+        group: Option<&str>,
+    ) -> anyhow::Result<()> {
+        if let Some(username) = owner {
+            scanner.set_global("owner", username)?;
+        }
+        if let Some(group) = group {
+            scanner.set_global("group", group)?;
+        }
+
+        rules::set_metadata_variables(scanner, metadata)?;
+        scanner.set_global("filepath", file_path.to_str().unwrap())?;
+        scanner.set_global("filename", file_path.file_name().unwrap().to_str().unwrap())?;
+        scanner.set_global(
+            "extension",
+            file_path
+                .extension()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or("".to_string()),
+        )?;
+
+        // Magics
+        let target_bytes =
+            magic::read_first_bytes(file_path.to_str().unwrap_or(""), self.max_signature_len)
+                .unwrap_or(vec![]);
+        if !target_bytes.is_empty() {
+            if let Some(magic) = self.definitions.iter().find(|m| m.matches(&target_bytes)) {
+                scanner.set_global("filetype", magic.description.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Clears the variables set by [`set_globals`](Self::set_globals).
+    pub fn reset_globals(scanner: &mut Scanner<'_>) -> anyhow::Result<()> {
+        scanner.set_global("owner", "")?;
+        scanner.set_global("group", "")?;
+        scanner.set_global("filepath", "")?;
+        scanner.set_global("filename", "")?;
+        scanner.set_global("extension", "")?;
+        scanner.set_global("filetype", "")?;
+        Ok(())
+    }
+}
+
+impl Component for ScanState {
+    fn draw_unchecked(
+        &self,
+        dimensions: superconsole::Dimensions,
+        mode: DrawMode,
+    ) -> anyhow::Result<Lines> {
+        match (&self.view, mode) {
+            (Some(view), DrawMode::Normal) => view.draw_unchecked(dimensions, mode),
+            // Supress std output.
+            _ => Ok(Lines::new()),
+        }
+    }
+}
+
+/// Details about a scanned file passed to the [`OutputHandler`].
+pub struct ScannedFile<'a> {
+    pub path: &'a Path,
+    /// True when the file returned fewer bytes than its reported size, so
+    /// matches may be partial.
+    pub truncated: bool,
+    /// Messages logged by rules through the `console` module while scanning
+    /// the file.
+    pub console: &'a [String],
+    /// Metadata of the file, read before scanning it, if any.
+    pub metadata: Option<&'a Metadata>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// User name resolved from the UID, if any.
+    pub owner: Option<&'a str>,
+    /// Group name resolved from the GID, if any.
+    pub group: Option<&'a str>,
+    /// Label of the volume the file comes from, prefixed to its path.
+    pub volume_label: Option<&'a str>,
+    /// Set when the scanned data was extracted from the file rather than
+    /// being the file itself.
+    pub extracted: Option<Extracted<'a>>,
+    /// Set when the file matched the first, quick scan of
+    /// [`ScanConfig::rescan_matches`] and is scanned again, for its matches
+    /// to be reported in full detail.
+    pub rescanned: bool,
+}
+
+/// Data extracted from a file and scanned on its own.
+pub struct Extracted<'a> {
+    /// Appended to the file's path when reporting, e.g. `#decoded@128`.
+    pub suffix: String,
+    /// The extracted bytes, hashed instead of the file.
+    pub data: &'a [u8],
+}
+
+impl<'a> ScannedFile<'a> {
+    pub fn new(path: &'a Path) -> Self {
+        Self {
+            path,
+            truncated: false,
+            console: &[],
+            metadata: None,
+            uid: None,
+            gid: None,
+            owner: None,
+            group: None,
+            volume_label: None,
+            extracted: None,
+            rescanned: false,
+        }
+    }
+}
+
+pub trait OutputHandler: Sync {
+    /// Called for each scanned file, with the rules it matched whose
+    /// target is what was scanned, its content or its path.
+    fn on_file_scanned(
+        &self,
+        file: &ScannedFile<'_>,
+        scan_results: &[Rule<'_, '_>],
+        output: &Sender<Message>,
+        minimum_score: u32,
+    );
+    /// Called for a file that was not scanned because it is a hardlink to
+    /// `original`, which was.
+    fn on_file_aliased(&self, _alias: &Path, _original: &Path, _output: &Sender<Message>) {}
+    /// Called for a file whose name looks like it's masquerading as
+    /// something else.
+    fn on_filename_anomaly(
+        &self,
+        _file: &ScannedFile<'_>,
+        _anomalies: &[FilenameAnomaly],
+        _output: &Sender<Message>,
+        _minimum_score: u32,
+    ) {
+    }
+    /// Called for a file without any content.
+    fn on_empty_file(
+        &self,
+        _file: &ScannedFile<'_>,
+        _kind: EmptyFile,
+        _output: &Sender<Message>,
+        _minimum_score: u32,
+    ) {
+    }
+    /// Called when the last file has been scanned.
+    fn on_done(&self, _output: &Sender<Message>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVIL_RULE: &str = r#"rule Evil { strings: $a = "EVIL" condition: $a }"#;
+
+    /// Records the paths of the scanned files and the rules they matched,
+    /// with the suffix of the extracted data.
+    #[derive(Default)]
+    struct Recorder {
+        matches: Mutex<Vec<(PathBuf, String)>>,
+        /// Every file passed on, and whether it was scanned again.
+        scanned: Mutex<Vec<(PathBuf, bool)>>,
+        /// The aliases passed on, with their original.
+        aliases: Mutex<Vec<(PathBuf, PathBuf)>>,
+        done: AtomicUsize,
+    }
+
+    impl Recorder {
+        /// The recorded matches, sorted.
+        fn sorted_matches(self) -> Vec<(PathBuf, String)> {
+            let mut matches = self.matches.into_inner().unwrap();
+            matches.sort();
+            matches
+        }
+    }
+
+    impl OutputHandler for Recorder {
+        fn on_file_scanned(
+            &self,
+            file: &ScannedFile<'_>,
+            scan_results: &[Rule<'_, '_>],
+            _output: &Sender<Message>,
+            _minimum_score: u32,
+        ) {
+            let mut path = file.path.as_os_str().to_owned();
+            if let Some(extracted) = &file.extracted {
+                path.push(&extracted.suffix);
+            }
+            let path = PathBuf::from(path);
+            self.scanned
+                .lock()
+                .unwrap()
+                .push((path.clone(), file.rescanned));
+            for rule in scan_results {
+                self.matches
+                    .lock()
+                    .unwrap()
+                    .push((path.clone(), rule.identifier().to_string()));
+            }
+        }
+
+        fn on_file_aliased(&self, alias: &Path, original: &Path, _output: &Sender<Message>) {
+            self.aliases
+                .lock()
+                .unwrap()
+                .push((alias.to_path_buf(), original.to_path_buf()));
+        }
+
+        fn on_done(&self, _output: &Sender<Message>) {
+            self.done.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_scan() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rules = dir.path().join("rules.yar");
+        fs::write(
+            &rules,
+            r#"rule Evil { strings: $a = "EVIL" condition: $a }
+rule Script { condition: filetype == "Shell script" }"#,
+        )?;
+        let magic = dir.path().join("magic");
+        fs::write(&magic, "23 21;Shell script\n")?;
+        let folder = dir.path().join("folder");
+        fs::create_dir(&folder)?;
+        fs::write(folder.join("evil.bin"), b"EVIL")?;
+        fs::write(folder.join("run.sh"), b"#!/bin/sh")?;
+        fs::write(folder.join("clean.txt"), b"clean")?;
+        fs::write(folder.join("big.bin"), b"EVIL, but too big")?;
+
+        let handler = Recorder::default();
+        let summary = scan(
+            ScanConfig {
+                magic: vec![magic],
+                max_size: 10,
+                ..ScanConfig::new(rules, vec![folder.clone()])
+            },
+            &handler,
+        )?;
+
+        assert_eq!(
+            summary,
+            ScanSummary {
+                num_rules: 2,
+                scanned_files: 3,
+                matching_files: 2,
+                skipped_size_files: 1,
+                skipped_small_files: 0,
+                timed_out_files: 0,
+                errors: vec![],
+                duration: summary.duration,
+                peak_rss: None,
+            }
+        );
+        let json = serde_json::to_value(&summary)?;
+        assert_eq!(json["files_skipped_size"], 1);
+        assert_eq!(json["files_errored"], 0);
+        assert!(json["duration_ms"].is_u64());
+        assert_eq!(json["files_timed_out"], 0);
+        assert!(json.get("num_rules").is_none());
+        assert!(json.get("peak_rss_bytes").is_none());
+        let mut matches = handler.matches.into_inner().unwrap();
+        matches.sort();
+        assert_eq!(
+            matches,
+            [
+                (folder.join("evil.bin"), "Evil".to_string()),
+                (folder.join("run.sh"), "Script".to_string()),
+            ]
+        );
+        assert_eq!(handler.done.into_inner(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_fails_on_broken_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = dir.path().join("rules.yar");
+        fs::write(&rules, "rule Broken {").unwrap();
+
+        let config = ScanConfig::new(rules, vec![dir.path().to_path_buf()]);
+        assert!(scan(config, &Recorder::default()).is_err());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_decompress_error_still_counts_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rules = dir.path().join("rules.yar");
+        fs::write(
+            &rules,
+            r#"rule Evil { strings: $a = "EVIL" condition: $a }"#,
+        )?;
+        let folder = dir.path().join("folder");
+        fs::create_dir(&folder)?;
+        // A gzip header followed by garbage, in a valid gzip layer.
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        std::io::Write::write_all(&mut gzip, b"\x1f\x8b\x08\x00EVIL garbage")?;
+        fs::write(folder.join("broken.gz"), gzip.finish()?)?;
+
+        let (send, recv) = crossbeam::channel::unbounded();
+        let handler = Recorder::default();
+        let summary = scan(
+            ScanConfig {
+                decompress: Some(DecompressLimits {
+                    max_depth: 2,
+                    ..Default::default()
+                }),
+                output: WalkOutput::Channel(send),
+                ..ScanConfig::new(rules, vec![folder.clone()])
+            },
+            &handler,
+        )?;
+
+        // The outer layer still matched and the file is counted.
+        assert_eq!(summary.scanned_files, 1);
+        assert_eq!(summary.matching_files, 1);
+        assert!(summary.errors.is_empty());
+        let errors = errors(recv);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(&format!(
+            "[-] Can not decompress the gzip data of {}#gzip:",
+            folder.join("broken.gz").display()
+        )));
+        assert_eq!(handler.done.into_inner(), 1);
+
+        Ok(())
+    }
+
+    /// Scans `config.folders` with the rules of `source` into `handler`,
+    /// returning the summary and the errors output.
+    fn scan_source(
+        source: &str,
+        config: ScanConfig,
+        handler: &dyn OutputHandler,
+    ) -> (ScanSummary, Vec<String>) {
+        let mut compiler = rules::new_compiler(&Default::default()).unwrap();
+        compiler.add_source(source).unwrap();
+        let rules = compiler.build();
+        let (send, recv) = crossbeam::channel::unbounded();
+        let config = ScanConfig {
+            output: WalkOutput::Channel(send),
+            ..config
+        };
+        let summary = scan_with_rules(&rules, &config, handler).unwrap();
+        drop(config);
+        (summary, errors(recv))
+    }
+
+    /// The errors sent to `recv`.
+    fn errors(recv: crossbeam::channel::Receiver<Message>) -> Vec<String> {
+        recv.into_iter()
+            .filter_map(|message| match message {
+                Message::Error(err) => Some(err),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decoded_blobs_console_and_memory_budget() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rules = dir.path().join("rules.yar");
+        fs::write(
+            &rules,
+            r#"import "console"
+rule Evil { strings: $a = "EVIL" condition: $a and console.log("evil") }"#,
+        )?;
+        let folder = dir.path().join("folder");
+        fs::create_dir(&folder)?;
+        let script =
+            "$p = 'c3RhZ2UgdHdvIHBheWxvYWQgd2l0aCBFVklMIGluc2lkZSBpdCwgcGFkZGVkIHRvIGxlbmd0aA=='\n";
+        fs::write(folder.join("dropper.ps1"), script)?;
+        let config = |max_memory, output| ScanConfig {
+            decode_embedded: Some(EmbeddedLimits::default()),
+            rule_console: true,
+            max_memory,
+            output,
+            ..ScanConfig::new(rules.clone(), vec![folder.clone()])
+        };
+
+        // The message logged when scanning the blob is output as the blob's.
+        let (send, recv) = crossbeam::channel::unbounded();
+        let handler = Recorder::default();
+        let summary = scan(config(None, WalkOutput::Channel(send)), &handler)?;
+        assert_eq!(summary.matching_files, 1);
+        assert_eq!(
+            errors(recv),
+            [format!(
+                "[+] console: {}#decoded@6: evil",
+                folder.join("dropper.ps1").display()
+            )]
+        );
+
+        // Reading the file for its blobs would exceed the budget.
+        let (send, recv) = crossbeam::channel::unbounded();
+        let handler = Recorder::default();
+        let summary = scan(config(Some(8), WalkOutput::Channel(send)), &handler)?;
+        assert_eq!(summary.scanned_files, 1);
+        assert_eq!(summary.matching_files, 0);
+        let errors = errors(recv);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].ends_with("it doesn't fit in the memory budget"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_buffer_falls_back_to_file_for_modules() {
+        // The smallest file the pe module accepts: an MZ header pointing at
+        // a PE signature and file header right after it.
+        let mut pe = vec![0u8; 64];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c..0x40].copy_from_slice(&64u32.to_le_bytes());
+        pe.extend_from_slice(b"PE\0\0");
+        pe.extend_from_slice(&0x14cu16.to_le_bytes());
+        pe.resize(64 + 4 + 20, 0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.exe");
+        fs::write(&path, &pe).unwrap();
+        let rules = yara_x::compile(r#"import "pe" rule IsPe { condition: pe.is_pe }"#).unwrap();
+        assert!(rules::needs_full_file(&rules));
+
+        // Only the MZ header could be read.
+        let buffer = FileBuffer {
+            data: pe[..64].to_vec(),
+            expected_len: pe.len() as u64,
+        };
+        let mut scanner = Scanner::new(&rules);
+        let results = scan_buffer_or_file(&mut scanner, &path, Some(&buffer), false).unwrap();
+        assert_eq!(results.matching_rules().len(), 0);
+        let results = scan_buffer_or_file(&mut scanner, &path, Some(&buffer), true).unwrap();
+        assert_eq!(results.matching_rules().len(), 1);
+    }
+
+    #[test]
+    fn test_hardlinks_scanned_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original.bin");
+        let link = dir.path().join("link.bin");
+        fs::write(&original, b"xx EVIL xx").unwrap();
+        fs::hard_link(&original, &link).unwrap();
+
+        let handler = Recorder::default();
+        let config = ScanConfig {
+            dedupe_inodes: true,
+            ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+        };
+        let (summary, _) = scan_source(EVIL_RULE, config, &handler);
+        assert_eq!(summary.scanned_files, 1);
+
+        // The other path is passed on as an alias of the scanned one.
+        let aliases = handler.aliases.into_inner().unwrap();
+        let matches = handler.matches.into_inner().unwrap();
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(aliases[0].1, matches[0].0);
+        let mut paths = [aliases[0].0.clone(), matches[0].0.clone()];
+        paths.sort();
+        assert_eq!(paths, [link, original]);
+    }
+
+    #[test]
+    fn test_decode_embedded_base64() {
+        use base64::Engine;
+
+        let dir = tempfile::tempdir().unwrap();
+        let payload = base64::engine::general_purpose::STANDARD
+            .encode(b"stage two payload with EVIL inside it, padded to length");
+        let script = format!(
+            "$p = '{}'\nIEX ([Text.Encoding]::UTF8.GetString($p))\n",
+            payload
+        );
+        fs::write(dir.path().join("dropper.ps1"), &script).unwrap();
+
+        // The plain file doesn't match, only its decoded payload does.
+        let handler = Recorder::default();
+        let config = ScanConfig {
+            decode_embedded: Some(EmbeddedLimits::default()),
+            ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+        };
+        let (summary, _) = scan_source(EVIL_RULE, config, &handler);
+
+        assert_eq!(summary.matching_files, 1);
+        assert_eq!(
+            handler.sorted_matches(),
+            [(dir.path().join("dropper.ps1#decoded@6"), "Evil".to_string())]
+        );
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn test_decompress_reveals_match() {
+        use std::io::Write;
+
+        // Repeated content is stored as back-references, so the full string
+        // only appears once decompressed.
+        let payload = b"stage two: EVIL EVIL EVIL EVIL EVIL EVIL EVIL EVIL";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        gzip.write_all(payload).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = ruzstd::encoding::compress_to_vec(
+            &payload[..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+
+        let source = r#"rule Repeated { strings: $a = "EVIL EVIL EVIL EVIL" condition: $a }"#;
+        for (name, content, compression) in
+            [("payload.gz", gzip, "gzip"), ("payload.zst", zstd, "zstd")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            fs::write(dir.path().join(name), &content).unwrap();
+
+            // The compressed file doesn't match, only its content does.
+            let handler = Recorder::default();
+            let config = ScanConfig {
+                decompress: Some(DecompressLimits::default()),
+                ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+            };
+            let (summary, _) = scan_source(source, config, &handler);
+
+            assert_eq!(summary.matching_files, 1);
+            assert_eq!(
+                handler.sorted_matches(),
+                [(
+                    dir.path().join(format!("{}#{}", name, compression)),
+                    "Repeated".to_string()
+                )]
+            );
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_nested_decompression_depth() {
+        use std::io::Write;
+
+        let gzip = |data: &[u8]| {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        // Three layers of gzip around the payload.
+        let content = gzip(&gzip(&gzip(b"stage three: EVIL")));
+
+        let dir = tempfile::tempdir().unwrap();
+        let bomb = dir.path().join("bomb.gz");
+        fs::write(&bomb, &content).unwrap();
+        let scan = |limits: DecompressLimits| {
+            let handler = Recorder::default();
+            let config = ScanConfig {
+                decompress: Some(limits),
+                ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+            };
+            let (summary, errors) = scan_source(EVIL_RULE, config, &handler);
+            (summary.matching_files, handler.sorted_matches(), errors)
+        };
+
+        // Stopping one layer short of the payload, which is output.
+        let (matched, matches, errors) = scan(DecompressLimits {
+            max_depth: 2,
+            ..Default::default()
+        });
+        assert_eq!(matched, 0);
+        assert!(matches.is_empty());
+        assert_eq!(
+            errors,
+            [format!(
+                "[-] Not decompressing {}#gzip#gzip: nested deeper than --max-archive-depth 2",
+                bomb.display()
+            )]
+        );
+
+        let (matched, matches, _) = scan(DecompressLimits {
+            max_depth: 3,
+            ..Default::default()
+        });
+        assert_eq!(matched, 1);
+        assert_eq!(
+            matches,
+            [(
+                dir.path().join("bomb.gz#gzip#gzip#gzip"),
+                "Evil".to_string()
+            )]
+        );
+
+        // The outer layers use up the whole budget.
+        let outer_len = gzip(&gzip(b"stage three: EVIL")).len() as u64;
+        let (matched, _, _) = scan(DecompressLimits {
+            max_depth: 3,
+            max_bytes: outer_len,
+        });
+        assert_eq!(matched, 0);
+    }
+
+    #[test]
+    fn test_parallel_folders_combined_matches() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        fs::write(first.path().join("a.bin"), b"EVIL").unwrap();
+        fs::write(first.path().join("clean.bin"), b"clean").unwrap();
+        fs::create_dir(second.path().join("sub")).unwrap();
+        fs::write(second.path().join("sub/b.bin"), b"more EVIL").unwrap();
+
+        let scan = |parallel_folders: bool| {
+            let handler = Recorder::default();
+            let config = ScanConfig {
+                parallel_folders,
+                threads: Some(2),
+                ..ScanConfig::new(
+                    PathBuf::new(),
+                    vec![first.path().to_path_buf(), second.path().to_path_buf()],
+                )
+            };
+            let (summary, _) = scan_source(EVIL_RULE, config, &handler);
+            (summary.scanned_files, handler.sorted_matches())
+        };
+
+        // Both folders are walked at once, with the matches of both.
+        let (scanned, matches) = scan(true);
+        assert_eq!(scanned, 3);
+        let mut expected = vec![
+            (first.path().join("a.bin"), "Evil".to_string()),
+            (second.path().join("sub/b.bin"), "Evil".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(matches, expected);
+        // The same as when walked one after the other.
+        assert_eq!(scan(false), (scanned, matches));
+    }
+
+    #[test]
+    fn test_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..8 {
+            fs::write(dir.path().join(format!("{}.bin", i)), b"EVIL").unwrap();
+        }
+
+        // 0 falls back to one thread per core instead of none.
+        for threads in [0, 3] {
+            let handler = Recorder::default();
+            let config = ScanConfig {
+                threads: Some(threads),
+                ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+            };
+            let (summary, _) = scan_source(EVIL_RULE, config, &handler);
+            assert_eq!(summary.scanned_files, 8);
+            assert_eq!(handler.sorted_matches().len(), 8);
+        }
+    }
+
+    #[test]
+    fn test_max_duration_stops_early() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..5 {
+            fs::write(dir.path().join(format!("{}.bin", i)), b"EVIL").unwrap();
+        }
+
+        let scan = |deadline: Instant| {
+            let handler = Recorder::default();
+            let config = ScanConfig {
+                deadline: Some(deadline),
+                ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+            };
+            let (summary, errors) = scan_source(EVIL_RULE, config, &handler);
+            (summary, handler.sorted_matches(), errors)
+        };
+
+        // Past the deadline, no file is handed out, without any error.
+        let (summary, matches, errors) = scan(Instant::now());
+        assert_eq!(summary.scanned_files, 0);
+        assert!(matches.is_empty());
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        // Well before it, every file is scanned.
+        let (summary, matches, _) = scan(Instant::now() + Duration::from_secs(3600));
+        assert_eq!(summary.scanned_files, 5);
+        assert_eq!(matches.len(), 5);
+    }
+
+    #[test]
+    fn test_rescan_matches() {
+        use base64::Engine;
+
+        let dir = tempfile::tempdir().unwrap();
+        let payload = base64::engine::general_purpose::STANDARD
+            .encode(b"stage two payload with EVIL inside it, padded to length");
+        fs::write(dir.path().join("evil.txt"), format!("EVIL = '{}'", payload)).unwrap();
+        for i in 0..4 {
+            fs::write(dir.path().join(format!("clean{}.bin", i)), b"clean").unwrap();
+        }
+
+        let handler = Recorder::default();
+        let config = ScanConfig {
+            rescan_matches: true,
+            decode_embedded: Some(EmbeddedLimits::default()),
+            ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+        };
+        let (summary, _) = scan_source(EVIL_RULE, config, &handler);
+
+        // Only the matching file is scanned again, along with its decoded
+        // blob, which isn't looked for in the first scan.
+        assert_eq!(summary.scanned_files, 5);
+        assert_eq!(
+            handler.scanned.into_inner().unwrap(),
+            [
+                (dir.path().join("evil.txt"), true),
+                (dir.path().join("evil.txt#decoded@8"), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Mimikatz.exe");
+        fs::write(&path, b"clean").unwrap();
+        // The path rule doesn't match the content.
+        fs::write(dir.path().join("notes.txt"), b"mimikatz").unwrap();
+        let source = r#"
+rule BadName {
+    meta:
+        target = "path"
+    strings:
+        $a = "mimikatz" nocase
+    condition:
+        $a
+}
+
+/* A content rule can depend on a path rule,
+   rule Commented { condition: false } */
+rule ContentName {
+    meta:
+        note = "rule Quoted { meta: target = \"path\" }"
+    strings:
+        $a = "Mimikatz"
+    condition:
+        $a and not BadName
+}
+"#;
+        let scan = |scan_paths: bool| {
+            let handler = Recorder::default();
+            let config = ScanConfig {
+                scan_paths,
+                ..ScanConfig::new(PathBuf::new(), vec![dir.path().to_path_buf()])
+            };
+            let (summary, _) = scan_source(source, config, &handler);
+            (summary.matching_files, handler.sorted_matches())
+        };
+
+        // Neither rule matches the content.
+        assert_eq!(scan(false), (0, vec![]));
+
+        // Only the path rule is reported for the path.
+        assert_eq!(scan(true), (1, vec![(path, "BadName".to_string())]));
+    }
+
+    #[test]
+    fn test_scan_single_file_like_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("evil.bin");
+        fs::write(&path, b"EVIL").unwrap();
+
+        let scan = |walked: &Path, single_file: bool| {
+            let handler = Recorder::default();
+            let config = ScanConfig {
+                single_file,
+                ..ScanConfig::new(PathBuf::new(), vec![walked.to_path_buf()])
+            };
+            let (summary, errors) = scan_source(EVIL_RULE, config, &handler);
+            assert!(errors.is_empty(), "{:?}", errors);
+            (summary.scanned_files, handler.sorted_matches())
+        };
+
+        let folder = scan(dir.path(), false);
+        let file = scan(&path, true);
+        assert_eq!(file, (1, vec![(path, "Evil".to_string())]));
+        assert_eq!(file, folder);
+    }
+
+    #[test]
+    fn test_passwd_path_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let passwd = dir.path().join("partition2-passwd");
+        fs::write(
+            &passwd,
+            "root:x:0:0:root:/root:/bin/sh\nanalyst:x:1000:1000::/home/analyst:/bin/sh\n",
+        )
+        .unwrap();
+
+        let root = ScanRoot::new(dir.path(), None, Some(&passwd));
+        assert_eq!(root.users.get(&1000).map(String::as_str), Some("analyst"));
+
+        // Without the override, the folder's own etc/passwd is read.
+        let root = ScanRoot::new(dir.path(), None, None);
+        assert!(root.users.is_empty());
+    }
+
+    #[test]
+    fn test_single_file_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("etc")).unwrap();
+        fs::write(dir.path().join("etc/group"), "staff:x:50:\n").unwrap();
+        let path = dir.path().join("evil.bin");
+        fs::write(&path, b"EVIL").unwrap();
+
+        let root = ScanRoot::file(&path, None, None);
+        assert_eq!(root.groups.get(&50).map(String::as_str), Some("staff"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::{Condvar, Mutex};
+
+/// A counting semaphore used for bounding how many files the walker threads
+/// have open at the same time.
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A permit acquired from a [`Semaphore`], released when dropped.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with `permits` available permits.
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a permit is available and takes it.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.released.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        SemaphorePermit { semaphore: self }
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_semaphore_bounds_concurrency() {
+        let semaphore = Semaphore::new(2);
+        let open = AtomicUsize::new(0);
+        let max_open = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let _permit = semaphore.acquire();
+                    let now = open.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_open.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        assert_eq!(open.load(Ordering::SeqCst), 0);
+        assert!(max_open.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn test_permit_released_on_drop() {
+        let semaphore = Semaphore::new(1);
+        drop(semaphore.acquire());
+        // Would block forever if the first permit was not released.
+        let _permit = semaphore.acquire();
+    }
+}
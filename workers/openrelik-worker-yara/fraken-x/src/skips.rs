@@ -0,0 +1,253 @@
+use std::{
+    fs::{self, Metadata},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+
+use crate::filter;
+
+/// Why a file was not scanned.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Not matched by `--include`, or matched by `--exclude`.
+    ExcludedPath,
+    /// Larger than `--maxsize`.
+    TooLarge,
+    /// Smaller than `--minsize`.
+    TooSmall,
+    /// Neither setuid nor setgid with `--setuid-only`.
+    NotSetuid,
+    /// Same modification time as in the `--baseline-mtime-dir`.
+    UnchangedFromBaseline,
+    /// A hard link to a file already scanned with `--dedupe-inodes`.
+    DuplicateInode,
+    /// Resolves outside the scanned folder with `--contain-symlinks`.
+    SymlinkOutsideRoot,
+}
+
+impl SkipReason {
+    /// The filter that skips files for this reason.
+    pub fn stage(&self) -> FilterStage {
+        match self {
+            Self::ExcludedPath => FilterStage::PathGlobs,
+            Self::TooLarge => FilterStage::MaxSize,
+            Self::TooSmall => FilterStage::MinSize,
+            Self::NotSetuid => FilterStage::SetuidOnly,
+            Self::UnchangedFromBaseline => FilterStage::Baseline,
+            Self::DuplicateInode => FilterStage::DedupeInodes,
+            Self::SymlinkOutsideRoot => FilterStage::ContainSymlinks,
+        }
+    }
+}
+
+/// A filter deciding whether a file is scanned, in the order they are
+/// applied.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterStage {
+    PathGlobs,
+    ContainSymlinks,
+    MaxSize,
+    MinSize,
+    SetuidOnly,
+    Baseline,
+    DedupeInodes,
+}
+
+/// Whether a file passed a filter.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct StageResult {
+    pub stage: FilterStage,
+    pub passed: bool,
+}
+
+/// The filters a file went through, as listed in the filter trace.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct FilterTraceEntry {
+    pub path: PathBuf,
+    /// The filters applied, up to the first one failing.
+    pub stages: Vec<StageResult>,
+    pub scanned: bool,
+}
+
+/// Collects the filters each file went through, shared by all the scanning
+/// threads.
+#[derive(Default)]
+pub struct FilterTrace {
+    entries: Mutex<Vec<FilterTraceEntry>>,
+}
+
+impl FilterTrace {
+    /// Records that the file at `path` went through the `enabled` filters,
+    /// in order, and was skipped for `skipped` if set.
+    pub fn record(&self, path: &Path, enabled: &[FilterStage], skipped: Option<SkipReason>) {
+        let failed = skipped.map(|reason| reason.stage());
+        let mut stages: Vec<_> = enabled
+            .iter()
+            .take_while(|stage| Some(**stage) != failed)
+            .map(|stage| StageResult {
+                stage: *stage,
+                passed: true,
+            })
+            .collect();
+        if let Some(stage) = failed {
+            stages.push(StageResult {
+                stage,
+                passed: false,
+            });
+        }
+        self.entries.lock().unwrap().push(FilterTraceEntry {
+            path: path.to_path_buf(),
+            stages,
+            scanned: skipped.is_none(),
+        });
+    }
+
+    /// Writes the trace to `path` as a JSON array, sorted by path.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let json = serde_json::to_string(&*entries)?;
+        fs::write(path, json).with_context(|| format!("can not write `{}`", path.display()))
+    }
+}
+
+/// Returns why a file is skipped based on its metadata alone, if it is.
+pub fn skip_by_metadata(
+    metadata: &Metadata,
+    min_size: u64,
+    max_size: u64,
+    setuid_only: bool,
+) -> Option<SkipReason> {
+    if metadata.len() > max_size {
+        Some(SkipReason::TooLarge)
+    } else if metadata.len() < min_size {
+        Some(SkipReason::TooSmall)
+    } else if setuid_only && !filter::is_setuid_or_setgid(metadata) {
+        Some(SkipReason::NotSetuid)
+    } else {
+        None
+    }
+}
+
+/// A skipped file, as listed in the skips output.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct Skip {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Collects the files skipped during a scan, shared by all the scanning
+/// threads.
+#[derive(Default)]
+pub struct SkipLog {
+    skips: Mutex<Vec<Skip>>,
+}
+
+impl SkipLog {
+    /// Records that the file at `path` was skipped for `reason`.
+    pub fn record(&self, path: &Path, reason: SkipReason) {
+        self.skips.lock().unwrap().push(Skip {
+            path: path.to_path_buf(),
+            reason,
+        });
+    }
+
+    /// Writes the skipped files to `path` as a JSON array, sorted by path.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut skips = self.skips.lock().unwrap();
+        skips.sort_by(|a, b| a.path.cmp(&b.path));
+        let json = serde_json::to_string(&*skips)?;
+        fs::write(path, json).with_context(|| format!("can not write `{}`", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skips_with_reasons() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let empty = dir.path().join("empty");
+        let small = dir.path().join("small");
+        let large = dir.path().join("large");
+        fs::write(&empty, "")?;
+        fs::write(&small, "x")?;
+        fs::write(&large, "x".repeat(100))?;
+
+        let skips = SkipLog::default();
+        for path in [&empty, &small, &large] {
+            if let Some(reason) = skip_by_metadata(&path.metadata()?, 1, 10, false) {
+                skips.record(path, reason);
+            }
+        }
+        let reason = skip_by_metadata(&small.metadata()?, 0, 10, true);
+        assert_eq!(reason, Some(SkipReason::NotSetuid));
+        skips.record(&small, reason.unwrap());
+
+        let output = dir.path().join("skips.json");
+        skips.write(&output)?;
+
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&output)?)?;
+        assert_eq!(
+            written,
+            serde_json::json!([
+                {"path": empty, "reason": "too_small"},
+                {"path": large, "reason": "too_large"},
+                {"path": small, "reason": "not_setuid"},
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_trace() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("regular");
+        fs::write(&path, "x")?;
+        let enabled = [
+            FilterStage::MaxSize,
+            FilterStage::SetuidOnly,
+            FilterStage::DedupeInodes,
+        ];
+
+        let trace = FilterTrace::default();
+        let skipped = skip_by_metadata(&path.metadata()?, 0, 10, true);
+        trace.record(&path, &enabled, skipped);
+        trace.record(&dir.path().join("scanned"), &enabled, None);
+
+        let output = dir.path().join("trace.json");
+        trace.write(&output)?;
+
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&output)?)?;
+        assert_eq!(
+            written,
+            serde_json::json!([
+                {
+                    "path": path,
+                    "stages": [
+                        {"stage": "max_size", "passed": true},
+                        {"stage": "setuid_only", "passed": false},
+                    ],
+                    "scanned": false,
+                },
+                {
+                    "path": dir.path().join("scanned"),
+                    "stages": [
+                        {"stage": "max_size", "passed": true},
+                        {"stage": "setuid_only", "passed": true},
+                        {"stage": "dedupe_inodes", "passed": true},
+                    ],
+                    "scanned": true,
+                },
+            ])
+        );
+
+        Ok(())
+    }
+}
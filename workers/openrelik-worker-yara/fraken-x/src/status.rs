@@ -0,0 +1,158 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread,
+};
+
+use anyhow::Context;
+
+use crate::scan::ScanCounters;
+
+/// How a run ended, as written to the status file.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct RunStatus {
+    pub exit_code: i32,
+    /// The error that ended the run, if any.
+    pub error: Option<String>,
+    pub scanned_files: usize,
+    pub matching_files: usize,
+    /// Files whose scan was abandoned after timing out.
+    pub timed_out_files: usize,
+}
+
+/// Records the outcome of a run and writes it to a file once the run ends,
+/// so that it is known even if the output is lost.
+pub struct StatusFile {
+    path: PathBuf,
+    error: Mutex<Option<String>>,
+    /// The counts of the scan, as they are when the run ends.
+    counters: Arc<ScanCounters>,
+    written: Mutex<bool>,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf, counters: Arc<ScanCounters>) -> Self {
+        Self {
+            path,
+            error: Mutex::new(None),
+            counters,
+            written: Mutex::new(false),
+        }
+    }
+
+    /// Records the error ending the run. Only the first one is kept.
+    pub fn record_error(&self, error: &str) {
+        self.error
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| error.to_string());
+    }
+
+    /// Writes the status with `exit_code`. Only the first call writes, the
+    /// following ones do nothing.
+    pub fn finish(&self, exit_code: i32) -> anyhow::Result<()> {
+        let mut written = self.written.lock().unwrap();
+        if *written {
+            return Ok(());
+        }
+        *written = true;
+        let status = RunStatus {
+            exit_code,
+            error: self.error.lock().unwrap().clone(),
+            scanned_files: self.counters.scanned.load(Ordering::Relaxed),
+            matching_files: self.counters.matched.load(Ordering::Relaxed),
+            timed_out_files: self.counters.timed_out.load(Ordering::Relaxed),
+        };
+        let json = serde_json::to_string(&status)?;
+        fs::write(&self.path, json)
+            .with_context(|| format!("can not write `{}`", self.path.display()))
+    }
+
+    /// Returns a guard writing the status when dropped, with exit code 0,
+    /// or 101 like Rust if dropped while panicking, unless already written.
+    pub fn guard(&self) -> FinishGuard<'_> {
+        FinishGuard(self)
+    }
+}
+
+/// Writes the status when dropped, see [`StatusFile::guard`].
+pub struct FinishGuard<'a>(&'a StatusFile);
+
+impl Drop for FinishGuard<'_> {
+    fn drop(&mut self) {
+        let exit_code = if thread::panicking() { 101 } else { 0 };
+        if let Err(err) = self.0.finish(exit_code) {
+            eprintln!("Status file error: {:#}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    fn read(path: &std::path::Path) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_status_of_failed_and_successful_runs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let failed = dir.path().join("failed.json");
+        let status = StatusFile::new(failed.clone(), Default::default());
+        status.record_error("Rules parsing error: can not read `rules`");
+        status.record_error("a later error");
+        status.finish(1)?;
+        // The guard doesn't overwrite the status.
+        drop(status.guard());
+        assert_eq!(
+            read(&failed),
+            serde_json::json!({
+                "exit_code": 1,
+                "error": "Rules parsing error: can not read `rules`",
+                "scanned_files": 0,
+                "matching_files": 0,
+                "timed_out_files": 0,
+            })
+        );
+
+        let succeeded = dir.path().join("succeeded.json");
+        let counters = Arc::new(ScanCounters::default());
+        let status = StatusFile::new(succeeded.clone(), counters.clone());
+        counters.count_file(true);
+        counters.count_file(false);
+        counters.timed_out.fetch_add(1, Ordering::Relaxed);
+        drop(status.guard());
+        assert_eq!(
+            read(&succeeded),
+            serde_json::json!({
+                "exit_code": 0,
+                "error": null,
+                "scanned_files": 2,
+                "matching_files": 1,
+                "timed_out_files": 1,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_written_on_panic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status.json");
+        let status = StatusFile::new(path.clone(), Default::default());
+
+        let result = panic::catch_unwind(|| {
+            let _guard = status.guard();
+            panic!("unexpected");
+        });
+
+        assert!(result.is_err());
+        assert_eq!(read(&path)["exit_code"], 101);
+    }
+}
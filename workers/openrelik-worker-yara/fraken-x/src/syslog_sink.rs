@@ -0,0 +1,106 @@
+use std::{collections::BTreeMap, io::Write, sync::Mutex};
+
+use anyhow::anyhow;
+use syslog::{Facility, Formatter5424, LogFormat, Logger, LoggerBackend, Severity};
+
+/// Identifier of the structured data element carrying the match details.
+const SD_ID: &str = "fraken@32473";
+
+/// Severity of the messages sent to syslog.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SyslogSeverity {
+    Err,
+    #[default]
+    Warning,
+    Notice,
+    Info,
+}
+
+impl From<SyslogSeverity> for Severity {
+    fn from(severity: SyslogSeverity) -> Self {
+        match severity {
+            SyslogSeverity::Err => Severity::LOG_ERR,
+            SyslogSeverity::Warning => Severity::LOG_WARNING,
+            SyslogSeverity::Notice => Severity::LOG_NOTICE,
+            SyslogSeverity::Info => Severity::LOG_INFO,
+        }
+    }
+}
+
+/// Sends RFC 5424 messages to syslog, shared by all the scanning threads.
+pub struct SyslogSink {
+    logger: Mutex<Logger<LoggerBackend, Formatter5424>>,
+    severity: SyslogSeverity,
+}
+
+impl SyslogSink {
+    /// Connects to the syslog server listening on UDP at `server`, or to the
+    /// local syslog daemon if `server` is `None`.
+    pub fn connect(
+        server: Option<&str>,
+        facility: Facility,
+        severity: SyslogSeverity,
+    ) -> anyhow::Result<Self> {
+        let formatter = Formatter5424 {
+            facility,
+            process: "fraken-x".to_string(),
+            ..Default::default()
+        };
+        let logger = match server {
+            Some(server) => syslog::udp(formatter, "0.0.0.0:0", server),
+            None => syslog::unix(formatter),
+        }
+        .map_err(|err| anyhow!("can not connect to syslog: {}", err))?;
+
+        Ok(Self {
+            logger: Mutex::new(logger),
+            severity,
+        })
+    }
+
+    /// Sends `message`, with `params` attached as structured data.
+    pub fn send(&self, params: &[(&str, &str)], message: &str) -> anyhow::Result<()> {
+        let params = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let data = BTreeMap::from([(SD_ID.to_string(), params)]);
+
+        let mut logger = self.logger.lock().unwrap();
+        let Logger { formatter, backend } = &mut *logger;
+        formatter
+            .format(backend, self.severity.into(), (0, data, message))
+            .map_err(|err| anyhow!("can not send to syslog: {}", err))?;
+        backend.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_send_to_udp_server() -> anyhow::Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0")?;
+        server.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let address = server.local_addr()?.to_string();
+
+        let sink =
+            SyslogSink::connect(Some(&address), Facility::LOG_LOCAL3, SyslogSeverity::Notice)?;
+        sink.send(&[("rule", "TestRule")], r#"{"Signature":"TestRule"}"#)?;
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf)?;
+        let message = String::from_utf8_lossy(&buf[..len]);
+        // local3 (19) * 8 + notice (5)
+        assert!(message.starts_with("<157>1 "), "{}", message);
+        assert!(message.contains(r#"[fraken@32473 rule="TestRule"]"#));
+        assert!(message.ends_with(r#"{"Signature":"TestRule"}"#));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context};
+
+/// A byte range of a file to scan, as listed in a targets file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Target {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Target {
+    /// Reads the target's byte range, which is shorter than `len` if the file
+    /// ends before.
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut data = Vec::new();
+        file.take(self.len).read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Parses a targets file with one `path:offset:len` target per line. Empty
+/// lines and lines starting with `#` are skipped. Paths may contain `:`.
+pub fn parse_targets<R: BufRead>(reader: R) -> anyhow::Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let target = parse_target(line).with_context(|| format!("line {}", number + 1))?;
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+fn parse_target(line: &str) -> anyhow::Result<Target> {
+    let mut parts = line.rsplitn(3, ':');
+    let (Some(len), Some(offset), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+        bail!("expected `path:offset:len`, got `{}`", line);
+    };
+    if path.is_empty() {
+        bail!("missing path in `{}`", line);
+    }
+    Ok(Target {
+        path: PathBuf::from(path),
+        offset: offset
+            .parse()
+            .with_context(|| format!("invalid offset `{}`", offset))?,
+        len: len
+            .parse()
+            .with_context(|| format!("invalid length `{}`", len))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_targets() -> anyhow::Result<()> {
+        let input = "# from a previous run\n/evidence/a.bin:0:512\n\n/evidence/c:d.bin:4096:16\n";
+
+        let targets = parse_targets(input.as_bytes())?;
+
+        assert_eq!(
+            targets,
+            vec![
+                Target {
+                    path: PathBuf::from("/evidence/a.bin"),
+                    offset: 0,
+                    len: 512,
+                },
+                Target {
+                    path: PathBuf::from("/evidence/c:d.bin"),
+                    offset: 4096,
+                    len: 16,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_target() {
+        assert!(parse_targets("/evidence/a.bin:12".as_bytes()).is_err());
+        assert!(parse_targets("/evidence/a.bin:x:12".as_bytes()).is_err());
+        assert!(parse_targets(":0:12".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_range() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data.bin");
+        fs::write(&path, b"0123456789")?;
+
+        let target = Target {
+            path: path.clone(),
+            offset: 3,
+            len: 4,
+        };
+        assert_eq!(target.read()?, b"3456");
+
+        let past_end = Target {
+            path,
+            offset: 8,
+            len: 100,
+        };
+        assert_eq!(past_end.read()?, b"89");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use superconsole::{Component, Dimensions, DrawMode, Line, Lines};
+
+/// Step of the minimum score changed by the `+` and `-` keys.
+const SCORE_STEP: i64 = 10;
+
+/// Rows scrolled by the page keys.
+const PAGE_ROWS: usize = 10;
+
+/// Help shown below the matches.
+const HELP: &str = "↑/↓ scroll  PgUp/PgDn page  +/- min score  t tag  c clear  q quit";
+
+/// Whether a [`RawMode`] put the terminal in raw mode.
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Keeps the terminal in raw mode, for reading the keys of the
+/// [`MatchBrowser`], until dropped.
+pub struct RawMode(());
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        RAW_MODE.store(true, Ordering::Relaxed);
+        Ok(Self(()))
+    }
+
+    /// Takes the terminal out of raw mode if a [`RawMode`] put it in, for
+    /// exiting the process without dropping it.
+    pub fn restore() {
+        if RAW_MODE.swap(false, Ordering::Relaxed) {
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        Self::restore();
+    }
+}
+
+/// A match listed by the [`MatchBrowser`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchRow {
+    pub path: String,
+    pub signature: String,
+    pub score: i64,
+    pub tags: Vec<String>,
+}
+
+/// The filters and scrolling of the list, changed by the keys.
+#[derive(Debug, Default)]
+struct View {
+    min_score: i64,
+    tag: Option<String>,
+    /// Index of the first row shown among the filtered ones.
+    offset: usize,
+}
+
+/// Lists the matches as they are found, filtered by score and tag from the
+/// keyboard, for hands-on triage.
+#[derive(Debug, Default)]
+pub struct MatchBrowser {
+    rows: Mutex<Vec<MatchRow>>,
+    view: Mutex<View>,
+    closed: AtomicBool,
+}
+
+/// Why [`MatchBrowser::read_keys`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeysEnd {
+    /// The browser was closed with `q`.
+    Closed,
+    /// Ctrl-C was pressed, which raw mode doesn't turn into a signal.
+    Interrupted,
+    /// `stop` was set.
+    Stopped,
+}
+
+impl MatchBrowser {
+    /// Adds matches to the list.
+    pub fn push(&self, rows: impl IntoIterator<Item = MatchRow>) {
+        self.rows.lock().unwrap().extend(rows);
+    }
+
+    /// Returns whether the browser was closed with `q`.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Updates the view after `key` was pressed.
+    pub fn handle_key(&self, key: KeyCode) {
+        let mut view = self.view.lock().unwrap();
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => view.offset = view.offset.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => view.offset += 1,
+            KeyCode::PageUp => view.offset = view.offset.saturating_sub(PAGE_ROWS),
+            KeyCode::PageDown => view.offset += PAGE_ROWS,
+            // Changing the filters scrolls back to the top.
+            KeyCode::Char('+') => {
+                view.min_score += SCORE_STEP;
+                view.offset = 0;
+            }
+            KeyCode::Char('-') => {
+                view.min_score = (view.min_score - SCORE_STEP).max(0);
+                view.offset = 0;
+            }
+            KeyCode::Char('t') => {
+                view.tag = self.next_tag(view.tag.as_deref());
+                view.offset = 0;
+            }
+            KeyCode::Char('c') => *view = View::default(),
+            KeyCode::Char('q') | KeyCode::Esc => self.closed.store(true, Ordering::Relaxed),
+            _ => return,
+        }
+        // Scrolled past the end.
+        let shown = self.filtered(&view).len();
+        view.offset = view.offset.min(shown.saturating_sub(1));
+    }
+
+    /// Reads the keys pressed until the browser is closed, Ctrl-C is
+    /// pressed or `stop` is set. The terminal must be in raw mode.
+    pub fn read_keys(&self, stop: &AtomicBool) -> io::Result<KeysEnd> {
+        while !stop.load(Ordering::Relaxed) {
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            if let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) = event::read()?
+            {
+                if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+                    return Ok(KeysEnd::Interrupted);
+                }
+                self.handle_key(code);
+                if self.is_closed() {
+                    return Ok(KeysEnd::Closed);
+                }
+            }
+        }
+        Ok(KeysEnd::Stopped)
+    }
+
+    /// Returns the tag after `tag` among the tags of the matches, in
+    /// alphabetical order, or none after the last one.
+    fn next_tag(&self, tag: Option<&str>) -> Option<String> {
+        let rows = self.rows.lock().unwrap();
+        let mut tags: Vec<&str> = rows
+            .iter()
+            .flat_map(|row| row.tags.iter().map(String::as_str))
+            .collect();
+        tags.sort_unstable();
+        tags.dedup();
+        let next = match tag {
+            Some(tag) => tags.iter().position(|t| *t == tag).map_or(0, |i| i + 1),
+            None => 0,
+        };
+        tags.get(next).map(|tag| tag.to_string())
+    }
+
+    /// Returns the rows that pass the filters of `view`.
+    fn filtered(&self, view: &View) -> Vec<MatchRow> {
+        self.rows
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|row| row.score >= view.min_score)
+            .filter(|row| view.tag.as_ref().is_none_or(|tag| row.tags.contains(tag)))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Component for MatchBrowser {
+    fn draw_unchecked(&self, dimensions: Dimensions, _mode: DrawMode) -> anyhow::Result<Lines> {
+        let view = self.view.lock().unwrap();
+        let total = self.rows.lock().unwrap().len();
+        let shown = self.filtered(&view);
+        let mut lines = vec![Line::sanitized(&format!(
+            "{} matches, {} shown | min score {} | tag {}",
+            total,
+            shown.len(),
+            view.min_score,
+            view.tag.as_deref().unwrap_or("any"),
+        ))];
+        // The header and the help take a line each.
+        let height = dimensions.height.saturating_sub(2);
+        lines.extend(shown.iter().skip(view.offset).take(height).map(|row| {
+            Line::sanitized(&format!(
+                "{:>5}  {:<32}  {}",
+                row.score, row.signature, row.path
+            ))
+        }));
+        lines.push(Line::sanitized(HELP));
+        let mut lines = Lines(lines);
+        lines.truncate_lines(dimensions.width);
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(path: &str, signature: &str, score: i64, tags: &[&str]) -> MatchRow {
+        MatchRow {
+            path: path.to_string(),
+            signature: signature.to_string(),
+            score,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    fn render(browser: &MatchBrowser, height: usize) -> Vec<String> {
+        let dimensions = Dimensions { width: 80, height };
+        browser
+            .draw(dimensions, DrawMode::Normal)
+            .unwrap()
+            .iter()
+            .map(|line| line.to_unstyled().trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_renders_match_rows() {
+        let browser = MatchBrowser::default();
+        browser.push([
+            row("/evidence/a.exe", "Mimikatz", 90, &["windows"]),
+            row("/evidence/b.sh", "Miner", 60, &["linux"]),
+            row("/evidence/c.bin", "Packed", 40, &[]),
+        ]);
+
+        assert_eq!(
+            render(&browser, 10),
+            [
+                "3 matches, 3 shown | min score 0 | tag any",
+                "   90  Mimikatz                          /evidence/a.exe",
+                "   60  Miner                             /evidence/b.sh",
+                "   40  Packed                            /evidence/c.bin",
+                HELP,
+            ]
+        );
+
+        // Only the rows fitting between the header and the help are shown.
+        browser.handle_key(KeyCode::Down);
+        let lines = render(&browser, 3);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("Miner"));
+
+        for _ in 0..5 {
+            browser.handle_key(KeyCode::Char('+'));
+        }
+        let lines = render(&browser, 10);
+        assert_eq!(lines[0], "3 matches, 2 shown | min score 50 | tag any");
+        assert!(lines[1].contains("Mimikatz"));
+
+        // Tags cycle in order, then back to any.
+        browser.handle_key(KeyCode::Char('t'));
+        let lines = render(&browser, 10);
+        assert_eq!(lines[0], "3 matches, 1 shown | min score 50 | tag linux");
+        assert!(lines[1].contains("Miner"));
+        browser.handle_key(KeyCode::Char('t'));
+        browser.handle_key(KeyCode::Char('t'));
+        assert_eq!(
+            render(&browser, 10)[0],
+            "3 matches, 2 shown | min score 50 | tag any"
+        );
+
+        browser.handle_key(KeyCode::Char('c'));
+        assert_eq!(render(&browser, 10).len(), 5);
+
+        assert!(!browser.is_closed());
+        browser.handle_key(KeyCode::Char('q'));
+        assert!(browser.is_closed());
+    }
+}
@@ -4,6 +4,9 @@ use std::{
     io::{BufRead, BufReader},
 };
 
+/// Maps UIDs to user names from the `name:password:UID:...` lines of the
+/// passwd file at `file_path`. Comments and lines that don't parse are
+/// skipped, and names that are not valid UTF-8 are decoded lossily.
 pub fn get_usernames_from_passwd(
     file_path: &str,
 ) -> Result<HashMap<u32, String>, Box<dyn std::error::Error>> {
@@ -11,14 +14,100 @@ pub fn get_usernames_from_passwd(
     let reader = BufReader::new(file);
     let mut users = HashMap::new();
 
-    for line in reader.lines() {
+    for line in reader.split(b'\n') {
         let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        if line.starts_with('#') {
+            continue;
+        }
         let parts: Vec<&str> = line.split(':').collect();
         if parts.len() >= 3 {
             // Ensure at least username, password, and UID exist
-            let uid = parts[2].parse::<u32>()?;
-            users.insert(uid, parts[0].to_string());
+            match parts[2].parse::<u32>() {
+                Ok(uid) => {
+                    users.insert(uid, parts[0].to_string());
+                }
+                Err(_err) => {
+                    #[cfg(feature = "logging")]
+                    log::debug!("skipping passwd line with invalid UID: {}: {}", line, _err);
+                }
+            }
         }
     }
     Ok(users)
 }
+
+/// Maps GIDs to group names from the `name:password:GID:members` lines of
+/// the group file at `file_path`. Malformed lines are skipped, names that
+/// are not valid UTF-8 are decoded lossily, and a missing or unreadable file
+/// gives no groups.
+pub fn get_groupnames_from_group(file_path: &str) -> HashMap<u32, String> {
+    let Ok(file) = File::open(file_path) else {
+        return HashMap::new();
+    };
+    BufReader::new(file)
+        .split(b'\n')
+        // Only a failed read ends the parse, the lines are never invalid.
+        .map_while(Result::ok)
+        .filter_map(|line| {
+            let line = String::from_utf8_lossy(&line);
+            let parts: Vec<&str> = line.split(':').collect();
+            if parts.len() < 3 || parts[0].is_empty() {
+                return None;
+            }
+            let gid = parts[2].parse::<u32>().ok()?;
+            Some((gid, parts[0].to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_get_usernames_from_passwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        fs::write(
+            &path,
+            b"# local accounts\nroot:x:0:0:root:/root:/bin/bash\n\
+             svc:x:notanumber:0::/:/sbin/nologin\n\
+             caf\xe9:x:33:33:Latin-1:/var/www:/bin/sh\n\
+             alice:x:1000:1000:Alice:/home/alice:/bin/sh\n",
+        )
+        .unwrap();
+
+        let users = get_usernames_from_passwd(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            users,
+            HashMap::from([
+                (0, "root".to_string()),
+                (33, "caf\u{fffd}".to_string()),
+                (1000, "alice".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_get_groupnames_from_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("group");
+        fs::write(
+            &path,
+            b"root:x:0:\nbroken line\nwheel:x:notanumber:alice\n\xff\xfe\n:x:5:\n\
+              adm:x:4:syslog,alice\n",
+        )
+        .unwrap();
+
+        let groups = get_groupnames_from_group(path.to_str().unwrap());
+        assert_eq!(
+            groups,
+            HashMap::from([(0, "root".to_string()), (4, "adm".to_string())])
+        );
+
+        assert!(get_groupnames_from_group("/nonexistent/group").is_empty());
+    }
+}
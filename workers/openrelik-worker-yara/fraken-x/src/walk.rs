@@ -4,6 +4,7 @@
 use std::fs::{File, Metadata};
 use std::io::BufRead;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{io, thread};
@@ -14,6 +15,49 @@ use crossterm::tty::IsTty;
 use globwalk::FileType;
 use superconsole::{Component, Lines, SuperConsole};
 
+use crate::reorder::OrderedSender;
+
+/// Order in which the files of a directory are walked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WalkOrder {
+    /// Breadth-first: the files closer to the root first.
+    Bfs,
+    /// Depth-first, each directory being walked before its siblings.
+    #[default]
+    Dfs,
+    /// The smallest files first.
+    SizeAsc,
+    /// The largest files first.
+    SizeDesc,
+}
+
+/// Where the messages sent while walking are output.
+#[derive(Clone, Default)]
+pub enum WalkOutput {
+    /// Printed, `Info` to stdout and `Error` to stderr, with the state drawn
+    /// on the console when stdout is a terminal.
+    #[default]
+    Console,
+    /// Sent to a channel, for the caller to output them.
+    Channel(Sender<Message>),
+    /// Dropped.
+    Discard,
+}
+
+impl WalkOutput {
+    /// Outputs `message` outside of a walk.
+    pub fn send(&self, message: Message) {
+        match (self, message) {
+            (WalkOutput::Console, Message::Info(s)) => println!("{}", s),
+            (WalkOutput::Console, Message::Error(s)) => eprintln!("{}", s),
+            (WalkOutput::Channel(output), message) => {
+                let _ = output.send(message);
+            }
+            _ => {}
+        }
+    }
+}
+
 /// Walks the files in a directory or a text file containing file paths,
 /// running a given function for each file.
 ///
@@ -38,6 +82,8 @@ pub struct Walker<'a> {
     path: &'a Path,
     /// If true, `path` is a file containing a list of paths, one per line.
     file_list: bool,
+    /// If set, the paths walked instead of `path`.
+    files: Option<&'a [PathBuf]>,
     /// A list of filters applied to the files being walked, those that don't
     /// match at least one of the filters are ignored.
     filters: Vec<String>,
@@ -47,6 +93,12 @@ pub struct Walker<'a> {
     /// An optional function that allows filtering the walked files based on
     /// their metadata.
     metadata_filter: Option<Box<dyn Fn(Metadata) -> bool + Send + 'a>>,
+    /// If true, the filters match paths regardless of case.
+    case_insensitive: bool,
+    /// If true, symlinks are followed while walking a directory.
+    follow_links: bool,
+    /// Order in which the files of a directory are walked.
+    order: WalkOrder,
 }
 
 impl<'a> Walker<'a> {
@@ -58,8 +110,12 @@ impl<'a> Walker<'a> {
             path,
             filters: Vec::new(),
             file_list: false,
+            files: None,
             max_depth: None,
             metadata_filter: None,
+            case_insensitive: false,
+            follow_links: false,
+            order: WalkOrder::Dfs,
         }
     }
 
@@ -72,8 +128,20 @@ impl<'a> Walker<'a> {
             path,
             filters: Vec::new(),
             file_list: true,
+            files: None,
             max_depth: None,
             metadata_filter: None,
+            case_insensitive: false,
+            follow_links: false,
+            order: WalkOrder::Dfs,
+        }
+    }
+
+    /// Creates a [`Walker`] that walks the files at `paths`, in order.
+    pub fn files(paths: &'a [PathBuf]) -> Self {
+        Self {
+            files: Some(paths),
+            ..Self::file_list(Path::new(""))
         }
     }
 
@@ -92,7 +160,7 @@ impl<'a> Walker<'a> {
     /// - `**`     matches any sequence of characters, including the path separator.
     ///
     /// - `[...]`  matches any character inside the brackets. Can also specify ranges of
-    ///          characters (e.g. `[0-9]`, `[a-z]`)
+    ///   characters (e.g. `[0-9]`, `[a-z]`)
     ///
     /// - `[!...]` is the negation of `[...]`
     ///
@@ -107,6 +175,32 @@ impl<'a> Walker<'a> {
         self
     }
 
+    /// Makes the glob filters match paths regardless of case, so that
+    /// `*.exe` also matches `SETUP.EXE`. Useful for evidence from file
+    /// systems that ignore case, like NTFS.
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// Follows symlinks while walking a directory, so that the files and
+    /// directories they point to are walked too. By default symlinks are
+    /// skipped.
+    pub fn follow_links(&mut self, yes: bool) -> &mut Self {
+        self.follow_links = yes;
+        self
+    }
+
+    /// Sets the order in which the files of a directory are walked.
+    ///
+    /// Every order but [`WalkOrder::Dfs`] needs to find all the files
+    /// before walking the first one. Lists of files are walked in the order
+    /// of the list.
+    pub fn order(&mut self, order: WalkOrder) -> &mut Self {
+        self.order = order;
+        self
+    }
+
     /// Sets a filter based in file metadata.
     ///
     /// The specified function receives the file metadata associated with a
@@ -137,6 +231,10 @@ impl<'a> Walker<'a> {
         F: FnMut(&Path) -> anyhow::Result<()>,
         E: FnMut(anyhow::Error) -> anyhow::Result<()>,
     {
+        if let Some(files) = self.files {
+            return self.walk_paths(files.iter().cloned().map(Ok), f, e);
+        }
+
         let metadata = match self
             .path
             .metadata()
@@ -166,15 +264,28 @@ impl<'a> Walker<'a> {
         }
     }
 
-    fn walk_file_list<F, E>(self, mut f: F, mut e: E) -> anyhow::Result<()>
+    fn walk_file_list<F, E>(self, f: F, e: E) -> anyhow::Result<()>
     where
         F: FnMut(&Path) -> anyhow::Result<()>,
         E: FnMut(anyhow::Error) -> anyhow::Result<()>,
     {
         let file = File::open(self.path)?;
+        let lines = io::BufReader::new(file).lines();
+        self.walk_paths(lines.map(|line| line.map(PathBuf::from)), f, e)
+    }
 
-        for line in io::BufReader::new(file).lines() {
-            let path = PathBuf::from(line?);
+    fn walk_paths<F, E>(
+        &self,
+        paths: impl Iterator<Item = io::Result<PathBuf>>,
+        mut f: F,
+        mut e: E,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(&Path) -> anyhow::Result<()>,
+        E: FnMut(anyhow::Error) -> anyhow::Result<()>,
+    {
+        for path in paths {
+            let path = path?;
             let metadata = match path
                 .metadata()
                 .with_context(|| format!("can't open `{}`", path.display()))
@@ -223,12 +334,18 @@ impl<'a> Walker<'a> {
             globwalk::GlobWalkerBuilder::from_patterns(path, self.filters.iter().as_ref())
         };
 
-        builder = builder.file_type(FileType::FILE);
+        builder = builder
+            .file_type(FileType::FILE)
+            .case_insensitive(self.case_insensitive)
+            .follow_links(self.follow_links);
 
         if let Some(max_depth) = self.max_depth {
             builder = builder.max_depth(max_depth + 1);
         }
 
+        // Files found with orders other than depth-first, walked at the end.
+        let mut found = Vec::new();
+
         for entry in builder.build()? {
             let entry = match entry {
                 Ok(e) => e,
@@ -243,16 +360,33 @@ impl<'a> Walker<'a> {
 
             match entry.metadata() {
                 Ok(metadata) => {
-                    if self.pass_metadata_filter(metadata) {
-                        if let Err(err) = f(entry.path()) {
-                            e(err)?
-                        }
+                    let len = metadata.len();
+                    if !self.pass_metadata_filter(metadata) {
+                        continue;
+                    }
+                    if self.order != WalkOrder::Dfs {
+                        found.push((entry.depth(), len, entry.into_path()));
+                    } else if let Err(err) = f(entry.path()) {
+                        e(err)?
                     }
                 }
                 Err(err) => e(err.into())?,
             }
         }
 
+        // The sorts are stable, files that compare equal stay depth-first.
+        match self.order {
+            WalkOrder::Bfs => found.sort_by_key(|(depth, _, _)| *depth),
+            WalkOrder::Dfs => {}
+            WalkOrder::SizeAsc => found.sort_by_key(|(_, len, _)| *len),
+            WalkOrder::SizeDesc => found.sort_by_key(|(_, len, _)| std::cmp::Reverse(*len)),
+        }
+        for (_, _, path) in found {
+            if let Err(err) = f(&path) {
+                e(err)?
+            }
+        }
+
         Ok(())
     }
 
@@ -338,7 +472,11 @@ impl<'a> Walker<'a> {
 /// ```
 pub struct ParWalker<'a> {
     num_threads: Option<u8>,
-    walker: Walker<'a>,
+    output_buffer: Option<usize>,
+    deadline: Option<Instant>,
+    ordered_output: bool,
+    output: WalkOutput,
+    walkers: Vec<Walker<'a>>,
 }
 
 impl<'a> ParWalker<'a> {
@@ -347,8 +485,27 @@ impl<'a> ParWalker<'a> {
     /// `path` can also point to an individual file instead of a directory.
     pub fn path(path: &'a Path) -> Self {
         Self {
-            walker: Walker::path(path),
+            walkers: vec![Walker::path(path)],
             num_threads: None,
+            output_buffer: None,
+            deadline: None,
+            ordered_output: false,
+            output: WalkOutput::Console,
+        }
+    }
+
+    /// Creates a [`ParWalker`] that walks several directories at once.
+    ///
+    /// Each directory is traversed by its own thread, but the files found in
+    /// all of them are processed by the same pool of threads.
+    pub fn paths(paths: impl IntoIterator<Item = &'a Path>) -> Self {
+        Self {
+            walkers: paths.into_iter().map(Walker::path).collect(),
+            num_threads: None,
+            output_buffer: None,
+            deadline: None,
+            ordered_output: false,
+            output: WalkOutput::Console,
         }
     }
 
@@ -358,27 +515,81 @@ impl<'a> ParWalker<'a> {
     /// `path` points to the text file that contains the paths to be walked.
     pub fn file_list(path: &'a Path) -> Self {
         Self {
-            walker: Walker::file_list(path),
+            walkers: vec![Walker::file_list(path)],
+            num_threads: None,
+            output_buffer: None,
+            deadline: None,
+            ordered_output: false,
+            output: WalkOutput::Console,
+        }
+    }
+
+    /// Creates a [`ParWalker`] that walks the files at `paths`.
+    pub fn files(paths: &'a [PathBuf]) -> Self {
+        Self {
+            walkers: vec![Walker::files(paths)],
             num_threads: None,
+            output_buffer: None,
+            deadline: None,
+            ordered_output: false,
+            output: WalkOutput::Console,
         }
     }
 
+    /// Sets where the messages are output, printed by default.
+    pub fn output(&mut self, output: WalkOutput) -> &mut Self {
+        self.output = output;
+        self
+    }
+
     /// Sets the number of threads used.
     ///
-    /// By default, the number of threads is determined by the number of CPUs
-    /// in the current host.
+    /// By default, or when `n` is 0, the number of threads is determined by
+    /// the number of CPUs in the current host.
     pub fn num_threads(&mut self, n: u8) -> &mut Self {
         self.num_threads = Some(n);
         self
     }
 
+    /// Sets how many messages can be waiting to be output.
+    ///
+    /// Threads sending a message block while the buffer is full. By default
+    /// the buffer is unbounded, which never blocks but lets messages pile up
+    /// in memory when they are produced faster than they are output.
+    pub fn output_buffer(&mut self, n: usize) -> &mut Self {
+        self.output_buffer = Some(n);
+        self
+    }
+
+    /// Outputs the messages sent while processing each file in the order the
+    /// files were found, instead of as soon as they are sent.
+    ///
+    /// The messages of a file are held back until all the files found before
+    /// it are done, so a slow file delays the output of the ones after it.
+    pub fn ordered_output(&mut self, yes: bool) -> &mut Self {
+        self.ordered_output = yes;
+        self
+    }
+
+    /// Stops the walk once `deadline` is reached.
+    ///
+    /// No more files are handed out after the deadline, those being processed
+    /// are finished and `on_walk_done` is still called, so the results so far
+    /// are not lost.
+    pub fn deadline(&mut self, deadline: Instant) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
     /// Sets a maximum depth while traversing the directory tree.
     ///
     /// When the maximum depth is 0 only the files that reside in the given
     /// directory are processed, subdirectories are not processed. By default,
     /// subdirectories are traversed without depth limits.
     pub fn max_depth(&mut self, n: usize) -> &mut Self {
-        self.walker.max_depth(n);
+        for walker in &mut self.walkers {
+            walker.max_depth(n);
+        }
         self
     }
 
@@ -386,12 +597,49 @@ impl<'a> ParWalker<'a> {
     ///
     /// See [`Walker::filter`] for details.
     pub fn filter(&mut self, filter: &str) -> &mut Self {
-        self.walker.filter(filter);
+        for walker in &mut self.walkers {
+            walker.filter(filter);
+        }
         self
     }
 
-    pub fn metadata_filter(&mut self, filter: impl Fn(Metadata) -> bool + Send + 'a) -> &mut Self {
-        self.walker.metadata_filter(filter);
+    /// Makes the glob filters match paths regardless of case.
+    ///
+    /// See [`Walker::case_insensitive`] for details.
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        for walker in &mut self.walkers {
+            walker.case_insensitive(yes);
+        }
+        self
+    }
+
+    /// Sets the order in which the files of the directories are handed out.
+    ///
+    /// See [`Walker::order`] for details.
+    pub fn order(&mut self, order: WalkOrder) -> &mut Self {
+        for walker in &mut self.walkers {
+            walker.order(order);
+        }
+        self
+    }
+
+    /// Follows symlinks while walking directories.
+    ///
+    /// See [`Walker::follow_links`] for details.
+    pub fn follow_links(&mut self, yes: bool) -> &mut Self {
+        for walker in &mut self.walkers {
+            walker.follow_links(yes);
+        }
+        self
+    }
+
+    pub fn metadata_filter(
+        &mut self,
+        filter: impl Fn(Metadata) -> bool + Send + Clone + 'a,
+    ) -> &mut Self {
+        for walker in &mut self.walkers {
+            walker.metadata_filter(filter.clone());
+        }
         self
     }
 
@@ -417,7 +665,7 @@ impl<'a> ParWalker<'a> {
     {
         // Use the given num_threads or compute it based on available
         // parallelism.
-        let num_threads = if let Some(num_threads) = self.num_threads {
+        let num_threads = if let Some(num_threads) = self.num_threads.filter(|&n| n > 0) {
             num_threads as usize
         } else {
             thread::available_parallelism()
@@ -425,73 +673,122 @@ impl<'a> ParWalker<'a> {
                 .unwrap_or(32)
         };
 
+        let found = AtomicU64::new(0);
+        let found = &found;
+        let ordered_output = self.ordered_output;
+
         crossbeam::scope(|s| {
             let mut threads = Vec::with_capacity(num_threads);
 
             // Channel that will contain the paths of the files that need to
-            // be processed by `func`.
-            let (paths_send, paths_recv) = crossbeam::channel::bounded::<PathBuf>(128);
+            // be processed by `func`, along with the order in which they were
+            // found.
+            let (paths_send, paths_recv) = crossbeam::channel::bounded::<(u64, PathBuf)>(128);
 
             // Channel where `func` will put the lines that it wants to show
             // in the console.
-            let (msg_send, msg_recv) = crossbeam::channel::unbounded::<Message>();
+            let (msg_send, msg_recv) = match self.output_buffer {
+                Some(n) => crossbeam::channel::bounded::<Message>(n),
+                None => crossbeam::channel::unbounded::<Message>(),
+            };
+
+            // Releases the messages of each file in turn, with ordered output.
+            let ordered_send = Arc::new(OrderedSender::new(msg_send.clone()));
 
             let state = Arc::new(state);
+            let deadline = self.deadline;
+            let past_deadline = move || deadline.is_some_and(|deadline| Instant::now() >= deadline);
 
             // Spawn the threads that will do the actual job. These threads
             // will obtain file paths from the paths channel and call `func`.
             for _ in 0..num_threads {
                 let paths_recv = paths_recv.clone();
                 let msg_send = msg_send.clone();
+                let ordered_send = ordered_send.clone();
                 let state = state.clone();
                 threads.push(s.spawn(move |_| {
                     let mut per_thread_obj = init(&state, &msg_send);
-                    for path in paths_recv {
-                        let res =
-                            action(&state, &msg_send, path.to_path_buf(), &mut per_thread_obj);
-                        if let Err(err) = res {
-                            if error(err, &msg_send).is_err() {
-                                let _ = msg_send.send(Message::Abort);
-                                break;
+                    for (index, path) in paths_recv {
+                        // Drain the remaining paths, so that the walking
+                        // threads don't block.
+                        if past_deadline() {
+                            if ordered_output {
+                                ordered_send.send(index, Vec::new());
                             }
+                            continue;
+                        }
+                        // With ordered output, the file's messages are
+                        // collected and released in turn.
+                        let file_channel = ordered_output.then(crossbeam::channel::unbounded);
+                        let output = file_channel.as_ref().map_or(&msg_send, |(send, _)| send);
+                        let aborted = match action(&state, output, path, &mut per_thread_obj) {
+                            Ok(()) => false,
+                            Err(err) => error(err, output).is_err(),
+                        };
+                        if let Some((_, file_recv)) = &file_channel {
+                            ordered_send.send(index, file_recv.try_iter().collect());
+                        }
+                        if aborted {
+                            let _ = msg_send.send(Message::Abort);
+                            break;
                         }
                     }
                     finalize(&per_thread_obj, &msg_send);
                 }));
             }
 
-            // Span a thread that walks the directory and puts file paths in
-            // the channel.
-            threads.push(s.spawn(move |_| {
-                let res = self.walker.walk(
-                    |file_path| Ok(paths_send.send(file_path.to_path_buf())?),
-                    |err| {
-                        // If an error occurs while sending the file path
-                        // through the channel, abort the walk.
-                        if err.is::<SendError<PathBuf>>() {
-                            return Err(err);
-                        }
+            // Spawn a thread per directory that walks it and puts file paths
+            // in the channel.
+            for walker in self.walkers {
+                let paths_send = paths_send.clone();
+                let msg_send = msg_send.clone();
+                threads.push(s.spawn(move |_| {
+                    let res = walker.walk(
+                        |file_path| {
+                            if past_deadline() {
+                                return Err(DeadlineReached.into());
+                            }
+                            let index = found.fetch_add(1, Ordering::Relaxed);
+                            Ok(paths_send.send((index, file_path.to_path_buf()))?)
+                        },
+                        |err| {
+                            // If an error occurs while sending the file path
+                            // through the channel, or the deadline is
+                            // reached, abort the walk.
+                            if err.is::<SendError<(u64, PathBuf)>>() || err.is::<DeadlineReached>()
+                            {
+                                return Err(err);
+                            }
 
-                        // Invoke the error callback and abort the walk if the
-                        // callback returns error.
-                        if let Err(err) = error(err, &msg_send) {
-                            let _ = msg_send.send(Message::Abort);
-                            return Err(err);
-                        }
+                            // Invoke the error callback and abort the walk if the
+                            // callback returns error.
+                            if let Err(err) = error(err, &msg_send) {
+                                let _ = msg_send.send(Message::Abort);
+                                return Err(err);
+                            }
 
-                        // Keep walking the directory tree.
-                        Ok(())
-                    },
-                );
+                            // Keep walking the directory tree.
+                            Ok(())
+                        },
+                    );
 
-                if let Err(err) = res {
-                    if error(err, &msg_send).is_err() {
-                        let _ = msg_send.send(Message::Abort);
+                    if let Err(err) = res {
+                        if !err.is::<DeadlineReached>() && error(err, &msg_send).is_err() {
+                            let _ = msg_send.send(Message::Abort);
+                        }
                     }
-                }
-            }));
+                }));
+            }
+
+            // The walking threads hold their own senders, the channels are
+            // closed once all of them are done.
+            drop(paths_send);
+            drop(msg_send);
+            drop(ordered_send);
 
-            let mut console = if cfg!(feature = "logging") {
+            let output = self.output;
+            let mut console = if cfg!(feature = "logging") || !matches!(output, WalkOutput::Console)
+            {
                 None
             } else {
                 // `console` will be `None` if either stdout or stderr is not a tty
@@ -509,11 +806,17 @@ impl<'a> ParWalker<'a> {
             output_messages(
                 render_period,
                 Instant::now(),
-                msg_recv,
+                &msg_recv,
+                &output,
                 console.as_mut(),
                 state.clone(),
             );
 
+            // Output stops at the first `Abort`, but threads that are still
+            // running may block on a full buffer. Discard their messages
+            // until all of them are done.
+            while msg_recv.recv().is_ok() {}
+
             threads
                 .into_iter()
                 .for_each(|thread| thread.join().unwrap());
@@ -524,7 +827,8 @@ impl<'a> ParWalker<'a> {
                 output_messages(
                     render_period,
                     Instant::now(),
-                    msg_recv,
+                    &msg_recv,
+                    &output,
                     console.as_mut(),
                     state.clone(),
                 );
@@ -550,7 +854,8 @@ impl<'a> ParWalker<'a> {
 fn output_messages<S>(
     render_period: Duration,
     last_render: Instant,
-    msg_recv: crossbeam::channel::Receiver<Message>,
+    msg_recv: &crossbeam::channel::Receiver<Message>,
+    output: &WalkOutput,
     console: Option<&mut SuperConsole>,
     state: Arc<S>,
 ) where
@@ -561,18 +866,9 @@ fn output_messages<S>(
 
     loop {
         match msg_recv.recv_timeout(render_period) {
-            Ok(Message::Info(s)) => {
+            Ok(Message::Info(s) | Message::Error(s)) if console.is_some() => {
                 if let Some(console) = console.as_mut() {
                     console.emit(Lines::from_colored_multiline_string(s.as_str()));
-                } else {
-                    println!("{}", s)
-                }
-            }
-            Ok(Message::Error(s)) => {
-                if let Some(console) = console.as_mut() {
-                    console.emit(Lines::from_colored_multiline_string(s.as_str()));
-                } else {
-                    eprintln!("{}", s)
                 }
             }
             Ok(Message::Abort) => {
@@ -582,6 +878,7 @@ fn output_messages<S>(
                 break;
             }
             Err(RecvTimeoutError::Timeout) => {}
+            Ok(message) => output.send(message),
         }
 
         if let Some(console) = console.as_mut() {
@@ -593,8 +890,254 @@ fn output_messages<S>(
     }
 }
 
+/// Error stopping the walking threads once the [`ParWalker::deadline`] is
+/// reached.
+#[derive(Debug)]
+struct DeadlineReached;
+
+impl std::fmt::Display for DeadlineReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline reached")
+    }
+}
+
+impl std::error::Error for DeadlineReached {}
+
 pub enum Message {
     Info(String),
     Error(String),
     Abort,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::scan::ScanState;
+
+    #[test]
+    fn test_case_insensitive_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file.exe"), b"MZ").unwrap();
+        fs::write(dir.path().join("notes.txt"), b"text").unwrap();
+
+        let walked = |case_insensitive: bool| {
+            let mut walker = Walker::path(dir.path());
+            walker.filter("*.EXE").case_insensitive(case_insensitive);
+            let mut paths = Vec::new();
+            walker
+                .walk(
+                    |path| {
+                        paths.push(path.file_name().unwrap().to_owned());
+                        Ok(())
+                    },
+                    Err,
+                )
+                .unwrap();
+            paths
+        };
+
+        assert!(walked(false).is_empty());
+        assert_eq!(walked(true), ["file.exe"]);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("one/two/three")).unwrap();
+        for file in [
+            "root.bin",
+            "one/1.bin",
+            "one/two/2.bin",
+            "one/two/three/3.bin",
+        ] {
+            fs::write(dir.path().join(file), b"EVIL").unwrap();
+        }
+
+        let scanned = |max_depth: usize| {
+            let paths = std::sync::Mutex::new(Vec::new());
+            let mut walker = ParWalker::path(dir.path());
+            walker.num_threads(2).max_depth(max_depth);
+            walker
+                .walk(
+                    ScanState::new(Vec::new(), 0, Vec::new()),
+                    |_, _| (),
+                    |_, _, file_path, _| {
+                        let name = file_path.file_name().unwrap().to_owned();
+                        paths.lock().unwrap().push(name);
+                        Ok(())
+                    },
+                    |_, _| {},
+                    |_| {},
+                    |err, _| Err(err),
+                )
+                .unwrap();
+            let mut paths = paths.into_inner().unwrap();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(scanned(0), ["root.bin"]);
+        assert_eq!(scanned(2), ["1.bin", "2.bin", "root.bin"]);
+        assert_eq!(scanned(3).len(), 4);
+    }
+
+    #[test]
+    fn test_num_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..8 {
+            fs::write(dir.path().join(format!("{}.bin", i)), b"EVIL").unwrap();
+        }
+
+        // Returns the number of threads started and of files walked.
+        let walked = |num_threads: u8| {
+            let (threads, files) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            let mut walker = ParWalker::path(dir.path());
+            walker.num_threads(num_threads);
+            walker
+                .walk(
+                    ScanState::new(Vec::new(), 0, Vec::new()),
+                    |_, _| threads.fetch_add(1, Ordering::Relaxed),
+                    |_, _, _, _| {
+                        files.fetch_add(1, Ordering::Relaxed);
+                        Ok(())
+                    },
+                    |_, _| {},
+                    |_| {},
+                    |err, _| Err(err),
+                )
+                .unwrap();
+            (threads.into_inner(), files.into_inner())
+        };
+
+        assert_eq!(walked(3), (3, 8));
+        // 0 falls back to one thread per core instead of none.
+        let cores = thread::available_parallelism().map_or(32, usize::from);
+        assert_eq!(walked(0), (cores, 8));
+    }
+
+    #[test]
+    fn test_small_output_buffer_delivers_all_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..300 {
+            fs::write(dir.path().join(format!("{}.bin", i)), b"EVIL").unwrap();
+        }
+
+        let (send, recv) = crossbeam::channel::unbounded();
+        let mut walker = ParWalker::path(dir.path());
+        walker
+            .num_threads(4)
+            .output_buffer(1)
+            .output(WalkOutput::Channel(send));
+        walker
+            .walk(
+                ScanState::new(Vec::new(), 0, Vec::new()),
+                |_, _| (),
+                |_, output, file_path, _| {
+                    // Every file competes twice for the one-message buffer.
+                    output.send(Message::Error(format!("scanned {}", file_path.display())))?;
+                    output.send(Message::Info(file_path.display().to_string()))?;
+                    Ok(())
+                },
+                |_, _| {},
+                |_| {},
+                |err, _| Err(err),
+            )
+            .unwrap();
+
+        let (mut infos, mut errors) = (0, 0);
+        for message in recv {
+            match message {
+                Message::Info(_) => infos += 1,
+                Message::Error(_) => errors += 1,
+                _ => {}
+            }
+        }
+        assert_eq!((infos, errors), (300, 300));
+    }
+
+    #[test]
+    fn test_follow_symlinks_with_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file.bin"), b"EVIL").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("file.bin"), dir.path().join("link.bin"))
+            .unwrap();
+        // A directory linking back to its parent.
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("sub/loop")).unwrap();
+
+        let walked = |follow_links: bool| {
+            let mut walker = Walker::path(dir.path());
+            walker.follow_links(follow_links);
+            let mut paths = Vec::new();
+            let mut errors = 0;
+            walker
+                .walk(
+                    |path| {
+                        paths.push(path.strip_prefix(dir.path()).unwrap().to_owned());
+                        Ok(())
+                    },
+                    |_| {
+                        errors += 1;
+                        Ok(())
+                    },
+                )
+                .unwrap();
+            paths.sort();
+            (paths, errors)
+        };
+
+        // Symlinks are skipped altogether.
+        assert_eq!(walked(false), (vec![PathBuf::from("file.bin")], 0));
+
+        // The loop is reported once and not walked into.
+        let (paths, errors) = walked(true);
+        assert_eq!(
+            paths,
+            [PathBuf::from("file.bin"), PathBuf::from("link.bin")]
+        );
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn test_scan_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/small.bin"), b"EVIL").unwrap();
+        fs::write(dir.path().join("large.bin"), vec![b'A'; 4096]).unwrap();
+        fs::write(dir.path().join("medium.bin"), vec![b'A'; 512]).unwrap();
+
+        let scanned = |order: WalkOrder| {
+            let scanned = std::sync::Mutex::new(Vec::new());
+            let mut walker = ParWalker::path(dir.path());
+            walker.num_threads(1).order(order);
+            walker
+                .walk(
+                    ScanState::new(Vec::new(), 0, Vec::new()),
+                    |_, _| (),
+                    |_, _, file_path, _| {
+                        let name = file_path.file_name().unwrap().to_string_lossy().to_string();
+                        scanned.lock().unwrap().push(name);
+                        Ok(())
+                    },
+                    |_, _| {},
+                    |_| {},
+                    |err, _| Err(err),
+                )
+                .unwrap();
+            scanned.into_inner().unwrap()
+        };
+
+        assert_eq!(
+            scanned(WalkOrder::SizeAsc),
+            ["small.bin", "medium.bin", "large.bin"]
+        );
+        assert_eq!(
+            scanned(WalkOrder::SizeDesc),
+            ["large.bin", "medium.bin", "small.bin"]
+        );
+        assert_eq!(scanned(WalkOrder::Bfs).last().unwrap(), "small.bin");
+    }
+}
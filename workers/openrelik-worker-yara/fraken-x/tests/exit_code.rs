@@ -0,0 +1,80 @@
+use std::{fs, path::Path, process::Command};
+
+const RULE: &str = r#"
+rule TestRule {
+    meta:
+        score = 60
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#;
+
+/// Runs fraken-x with `rules` on `folder` and the extra `args`, returning
+/// its exit code.
+fn run(rules: &Path, folder: &Path, args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_fraken-x"))
+        .arg(rules)
+        .arg("--folder")
+        .arg(folder)
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn test_exit_code() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yar");
+    fs::write(&rules, RULE).unwrap();
+    let matching = dir.path().join("matching");
+    let clean = dir.path().join("clean");
+    fs::create_dir_all(matching.join("sub")).unwrap();
+    fs::create_dir(&clean).unwrap();
+    fs::write(matching.join("sub/evil.bin"), b"EVIL").unwrap();
+    fs::write(matching.join("clean.bin"), b"clean").unwrap();
+    fs::write(clean.join("clean.bin"), b"clean").unwrap();
+
+    assert_eq!(run(&rules, &matching, &["--exit-code"]), Some(1));
+    assert_eq!(run(&rules, &clean, &["--exit-code"]), Some(0));
+    // Matches below the minimum score don't count.
+    assert_eq!(
+        run(&rules, &matching, &["--exit-code", "--minscore", "70"]),
+        Some(0)
+    );
+    // Without the flag, matches don't change the exit code.
+    assert_eq!(run(&rules, &matching, &[]), Some(0));
+
+    // Errors are told apart from matches.
+    let missing = dir.path().join("missing.yar");
+    assert_eq!(run(&missing, &matching, &["--exit-code"]), Some(2));
+    assert_eq!(run(&missing, &matching, &[]), Some(1));
+}
+
+#[test]
+fn test_exit_code_targets() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yar");
+    fs::write(&rules, RULE).unwrap();
+    let image = dir.path().join("image.bin");
+    fs::write(&image, b"clean....EVIL").unwrap();
+    let run_targets = |range: &str| {
+        let targets = dir.path().join("targets.txt");
+        fs::write(&targets, format!("{}:{}\n", image.display(), range)).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_fraken-x"))
+            .arg(&rules)
+            .arg("--targets")
+            .arg(&targets)
+            .arg("--exit-code")
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+
+    assert_eq!(run_targets("9:4"), Some(1));
+    assert_eq!(run_targets("0:5"), Some(0));
+}
@@ -0,0 +1,88 @@
+use std::{fs, process::Command};
+
+use serde_json::Value;
+
+const RULE: &str = r#"
+rule TestRule {
+    meta:
+        score = 60
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#;
+
+#[test]
+fn test_manifest_lists_every_written_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yar");
+    fs::write(&rules, RULE).unwrap();
+    let folder = dir.path().join("folder");
+    fs::create_dir(&folder).unwrap();
+    fs::write(folder.join("evil.bin"), b"EVIL").unwrap();
+    let out = dir.path().join("out");
+    fs::create_dir(&out).unwrap();
+    let manifest = out.join("manifest.json");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fraken-x"))
+        .arg(&rules)
+        .arg("--folder")
+        .arg(&folder)
+        .arg("--output")
+        .arg(out.join("results.json"))
+        .arg("--save-rules")
+        .arg(out.join("rules.bin"))
+        .arg("--hash-cache")
+        .arg(out.join("hashes.cache"))
+        .arg("--heartbeat-file")
+        .arg(out.join("heartbeat"))
+        .arg("--status-file")
+        .arg(out.join("status.json"))
+        .arg("--skips-output")
+        .arg(out.join("skips.json"))
+        .arg("--trace-filters")
+        .arg(out.join("trace.json"))
+        .arg("--summary-path")
+        .arg(out.join("summary.json"))
+        .arg("--manifest")
+        .arg(&manifest)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let entries: Value = serde_json::from_slice(&fs::read(&manifest).unwrap()).unwrap();
+    let mut kinds: Vec<_> = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["type"].as_str().unwrap())
+        .collect();
+    kinds.sort_unstable();
+    assert_eq!(
+        kinds,
+        [
+            "filter-trace",
+            "hash-cache",
+            "heartbeat",
+            "results",
+            "rules",
+            "skips",
+            "status",
+            "summary"
+        ]
+    );
+    // Listed once final.
+    let status = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["type"] == "status")
+        .unwrap();
+    let written = fs::read(out.join("status.json")).unwrap();
+    assert_eq!(status["size"], written.len() as u64);
+}
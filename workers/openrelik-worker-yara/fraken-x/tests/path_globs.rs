@@ -0,0 +1,63 @@
+use std::{fs, process::Command};
+
+const RULE: &str = r#"
+rule TestRule {
+    meta:
+        score = 60
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#;
+
+#[test]
+fn test_include_exclude() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yar");
+    fs::write(&rules, RULE).unwrap();
+    let folder = dir.path().join("folder");
+    fs::create_dir_all(folder.join("app/cache")).unwrap();
+    for name in [
+        "setup.exe",
+        "notes.txt",
+        "app/tool.exe",
+        "app/cache/tmp.exe",
+    ] {
+        fs::write(folder.join(name), b"EVIL").unwrap();
+    }
+
+    let scanned = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_fraken-x"))
+            .arg(&rules)
+            .arg("--folder")
+            .arg(&folder)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut names: Vec<_> = ["setup.exe", "notes.txt", "tool.exe", "tmp.exe"]
+            .into_iter()
+            .filter(|name| stdout.contains(&format!("/{}", name)))
+            .collect();
+        names.sort_unstable();
+        names
+    };
+
+    assert_eq!(
+        scanned(&[]),
+        ["notes.txt", "setup.exe", "tmp.exe", "tool.exe"]
+    );
+    assert_eq!(
+        scanned(&["--include", "**/*.exe"]),
+        ["setup.exe", "tmp.exe", "tool.exe"]
+    );
+    // Excludes take precedence over includes.
+    assert_eq!(
+        scanned(&["--include", "**/*.exe", "--exclude", "**/cache/**"]),
+        ["setup.exe", "tool.exe"]
+    );
+    // Relative to the scanned folder, `*` not crossing folders.
+    assert_eq!(scanned(&["--include", "*.exe"]), ["setup.exe"]);
+}
@@ -0,0 +1,139 @@
+use std::{fs, process::Command};
+
+use serde_json::Value;
+
+const RULE: &str = r#"
+rule TestRule {
+    meta:
+        score = 60
+    strings:
+        $a = "EVIL"
+    condition:
+        $a
+}
+"#;
+
+#[test]
+fn test_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yar");
+    fs::write(&rules, RULE).unwrap();
+    let folder = dir.path().join("folder");
+    fs::create_dir(&folder).unwrap();
+    fs::write(folder.join("evil.bin"), b"EVIL").unwrap();
+    fs::write(folder.join("clean.bin"), b"clean").unwrap();
+    fs::write(folder.join("big.bin"), b"EVIL, but too big").unwrap();
+    let summary_path = dir.path().join("summary.json");
+
+    let fraken_x = || {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fraken-x"));
+        command
+            .arg(&rules)
+            .arg("--folder")
+            .arg(&folder)
+            .args(["--maxsize", "10"]);
+        command
+    };
+
+    // Printed after the matches.
+    let output = fraken_x().arg("--summary").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let summary: Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["files_scanned"], 2);
+    assert_eq!(summary["files_matched"], 1);
+    assert_eq!(summary["files_skipped_size"], 1);
+    assert_eq!(summary["files_errored"], 0);
+    assert!(summary["duration_ms"].is_u64());
+
+    // Or written to its own file, leaving the matches alone on stdout.
+    let output = fraken_x()
+        .arg("--summary-path")
+        .arg(&summary_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let written: Value = serde_json::from_slice(&fs::read(&summary_path).unwrap()).unwrap();
+    assert_eq!(written["files_scanned"], summary["files_scanned"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("files_scanned"));
+
+    // Matches below the minimum score aren't counted, as they aren't
+    // reported.
+    let output = fraken_x()
+        .args(["--summary", "--minscore", "70", "--profile-memory"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let summary: Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["files_scanned"], 2);
+    assert_eq!(summary["files_matched"], 0);
+    assert_eq!(summary["files_timed_out"], 0);
+    assert!(summary["peak_rss_bytes"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn test_summary_targets() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yar");
+    fs::write(&rules, RULE).unwrap();
+    let image = dir.path().join("image.bin");
+    fs::write(&image, b"clean....EVIL").unwrap();
+    // The last target can't be read.
+    let missing = dir.path().join("missing.bin");
+    let targets = dir.path().join("targets.txt");
+    fs::write(
+        &targets,
+        format!(
+            "{0}:0:5\n{0}:9:4\n{1}:0:4\n",
+            image.display(),
+            missing.display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fraken-x"))
+        .arg(&rules)
+        .arg("--targets")
+        .arg(&targets)
+        .arg("--summary")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let summary: Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["files_scanned"], 2);
+    assert_eq!(summary["files_matched"], 1);
+    assert_eq!(summary["files_errored"], 1);
+}
+
+#[test]
+fn test_min_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yar");
+    fs::write(&rules, RULE).unwrap();
+    let folder = dir.path().join("folder");
+    fs::create_dir(&folder).unwrap();
+    fs::write(folder.join("empty.bin"), b"").unwrap();
+    fs::write(folder.join("tiny.bin"), b"EVIL").unwrap();
+    fs::write(folder.join("evil.bin"), b"EVIL EVIL").unwrap();
+    fs::write(folder.join("big.bin"), b"EVIL, but too big").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fraken-x"))
+        .arg(&rules)
+        .arg("--folder")
+        .arg(&folder)
+        .args(["--minsize", "5", "--maxsize", "10", "--summary"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let summary: Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["files_scanned"], 1);
+    assert_eq!(summary["files_skipped_small"], 2);
+    assert_eq!(summary["files_skipped_size"], 1);
+    // Only the file within the bounds is reported.
+    assert!(stdout.contains("evil.bin"));
+    assert!(!stdout.contains("tiny.bin"));
+    assert!(!stdout.contains("big.bin"));
+}